@@ -172,7 +172,7 @@ function handleKey(key, event) {
       console.log('(');
       break;
     case '€':
-      console.log('€');
+      sendText('€');
       break;
 
     // ) / ¥
@@ -180,7 +180,7 @@ function handleKey(key, event) {
       console.log(')');
       break;
     case '¥':
-      console.log('¥');
+      sendText('¥');
       break;
 
     // backspace
@@ -0,0 +1,71 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// source of time for script timers, abstracted so timers can be driven by a fake clock
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    fn duration_until(&self, time: Instant) -> Duration {
+        return time.saturating_duration_since(self.now());
+    }
+}
+
+// monotonic clock which is not affected by changes to the wall clock
+pub struct SystemClock {}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        return Instant::now();
+    }
+}
+
+// clock which only moves when advanced, for testing timers
+pub struct FakeClock {
+    now: Mutex<Instant>,
+}
+
+impl Default for FakeClock {
+    fn default() -> Self {
+        return FakeClock::new();
+    }
+}
+
+impl FakeClock {
+    pub fn new() -> Self {
+        return FakeClock {
+            now: Mutex::new(Instant::now()),
+        };
+    }
+
+    pub fn advance(&self, duration: Duration) -> () {
+        let mut now = self.now.lock().unwrap_or_else(|err| err.into_inner());
+        *now += duration;
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        return *self.now.lock().unwrap_or_else(|err| err.into_inner());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fake_clock_only_moves_when_advanced() {
+        let clock = FakeClock::new();
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+        clock.advance(Duration::from_millis(5));
+        assert_eq!(clock.now(), start + Duration::from_millis(5));
+        assert_eq!(
+            clock.duration_until(start + Duration::from_millis(8)),
+            Duration::from_millis(3)
+        );
+        assert_eq!(clock.duration_until(start), Duration::ZERO);
+    }
+}
@@ -0,0 +1,232 @@
+use std::{fmt, fs, path::Path};
+
+use crate::{
+    hook_protocol::{FailPolicy, HOOK_TIMEOUT_MARGIN_MILLIS},
+    unknown_device::UnknownDevicePolicy,
+    RekeyError,
+};
+
+// how long the key handlers of all scripts have to handle one key event before the key is passed
+// on, the hooks block keyboard input while they wait
+pub const DEFAULT_HANDLER_TIMEOUT_MILLIS: u32 = 200;
+
+// which hook delivers keyboard and mouse input to rekey_exe
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum InputBackend {
+    // WH_KEYBOARD from rekey_lib.dll, injected into every process, identifies devices reliably
+    Hook,
+    // WH_KEYBOARD_LL in rekey_exe, sees elevated windows and processes of the other bitness. It
+    // runs before raw input is generated so the device of a key usually isn't known yet, device
+    // filters don't work reliably and unknown_device = buffer acts like skip.
+    LowLevelHook,
+}
+
+impl InputBackend {
+    pub fn parse(s: &str) -> Result<Self, RekeyError> {
+        match s {
+            "hook" => Result::Ok(InputBackend::Hook),
+            "ll-hook" => Result::Ok(InputBackend::LowLevelHook),
+            _ => Result::Err(RekeyError::GenericError(format!(
+                "invalid backend {}, expected 'hook' or 'll-hook'",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for InputBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InputBackend::Hook => write!(f, "hook"),
+            InputBackend::LowLevelHook => write!(f, "ll-hook"),
+        }
+    }
+}
+
+// settings from rekey.ini in the user dir, one "key = value" per line, lines starting with '#'
+// or ';' are comments
+pub struct Config {
+    pub backend: InputBackend,
+    pub handler_timeout_millis: u32,
+    // only used by the hook backend, the low level hook runs inside rekey_exe. None waits
+    // HOOK_TIMEOUT_MARGIN_MILLIS longer than the handler timeout, see resolve_hook_timeout_millis.
+    pub hook_timeout_millis: Option<u32>,
+    pub hook_fail_policy: FailPolicy,
+    // for handlers with a device filter which don't set their own, see rekeySetDefaultUnknownDevice.
+    // Buffer is treated as skip with the low level hook.
+    pub unknown_device: UnknownDevicePolicy,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        return Config {
+            backend: InputBackend::Hook,
+            handler_timeout_millis: DEFAULT_HANDLER_TIMEOUT_MILLIS,
+            hook_timeout_millis: Option::None,
+            hook_fail_policy: FailPolicy::Open,
+            unknown_device: UnknownDevicePolicy::Match,
+        };
+    }
+}
+
+impl Config {
+    pub fn load(filename: &Path) -> Result<Self, RekeyError> {
+        if !filename.exists() {
+            return Result::Ok(Config::default());
+        }
+        let contents = fs::read_to_string(filename)?;
+        return Config::parse(&contents).map_err(|err| {
+            RekeyError::GenericError(format!("invalid config {}: {}", filename.display(), err))
+        });
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, RekeyError> {
+        let mut config = Config::default();
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| {
+                RekeyError::GenericError(format!("line {}: expected key = value", i + 1))
+            })?;
+            match key.trim() {
+                "backend" => {
+                    config.backend = InputBackend::parse(value.trim())?;
+                }
+                "handler_timeout_ms" => {
+                    config.handler_timeout_millis = parse_millis("handler_timeout_ms", value)
+                        .map_err(|err| {
+                            RekeyError::GenericError(format!("line {}: {}", i + 1, err))
+                        })?;
+                }
+                "hook_timeout_ms" => {
+                    let millis = parse_millis("hook_timeout_ms", value).map_err(|err| {
+                        RekeyError::GenericError(format!("line {}: {}", i + 1, err))
+                    })?;
+                    config.hook_timeout_millis = Option::Some(millis);
+                }
+                "hook_fail_policy" => {
+                    config.hook_fail_policy = FailPolicy::parse(value.trim())?;
+                }
+                "unknown_device" => {
+                    config.unknown_device = UnknownDevicePolicy::parse(value.trim())?;
+                }
+                key => {
+                    return Result::Err(RekeyError::GenericError(format!(
+                        "line {}: unknown setting {}",
+                        i + 1,
+                        key
+                    )));
+                }
+            }
+        }
+        config.validate()?;
+        return Result::Ok(config);
+    }
+
+    // checked again once the command line has overridden settings
+    pub fn validate(&self) -> Result<(), RekeyError> {
+        if let Option::Some(hook_timeout_millis) = self.hook_timeout_millis {
+            if hook_timeout_millis < self.handler_timeout_millis {
+                return Result::Err(RekeyError::GenericError(format!(
+                    "hook_timeout_ms {} is below the handler timeout {}, the hook would give up on keys the handlers are still allowed to handle",
+                    hook_timeout_millis, self.handler_timeout_millis
+                )));
+            }
+        }
+        return Result::Ok(());
+    }
+
+    pub fn resolve_hook_timeout_millis(&self) -> u32 {
+        return self
+            .hook_timeout_millis
+            .unwrap_or(self.handler_timeout_millis + HOOK_TIMEOUT_MARGIN_MILLIS);
+    }
+}
+
+// a timeout setting in milliseconds, 0 would pass on or drop every key
+pub fn parse_millis(name: &str, value: &str) -> Result<u32, RekeyError> {
+    let millis = value.trim().parse::<u32>().map_err(|err| {
+        RekeyError::GenericError(format!("invalid {} {}: {}", name, value.trim(), err))
+    })?;
+    if millis == 0 {
+        return Result::Err(RekeyError::GenericError(format!(
+            "invalid {} 0, expected at least 1",
+            name
+        )));
+    }
+    return Result::Ok(millis);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hook_protocol::DEFAULT_HOOK_TIMEOUT_MILLIS;
+
+    #[test]
+    fn defaults_are_used_for_missing_settings() {
+        let config = Config::parse("# nothing set\n\n; still nothing\n").unwrap();
+        assert_eq!(config.backend, InputBackend::Hook);
+        assert_eq!(
+            config.handler_timeout_millis,
+            DEFAULT_HANDLER_TIMEOUT_MILLIS
+        );
+        assert_eq!(config.hook_timeout_millis, Option::None);
+        assert_eq!(
+            config.resolve_hook_timeout_millis(),
+            DEFAULT_HOOK_TIMEOUT_MILLIS
+        );
+        assert_eq!(config.hook_fail_policy, FailPolicy::Open);
+        assert_eq!(config.unknown_device, UnknownDevicePolicy::Match);
+    }
+
+    #[test]
+    fn settings_parse() {
+        let config = Config::parse(
+            "backend = ll-hook\nhandler_timeout_ms = 100\nhook_fail_policy = closed\nunknown_device = buffer\n",
+        )
+        .unwrap();
+        assert_eq!(config.backend, InputBackend::LowLevelHook);
+        assert_eq!(config.handler_timeout_millis, 100);
+        assert_eq!(config.hook_fail_policy, FailPolicy::Closed);
+        assert_eq!(config.unknown_device, UnknownDevicePolicy::Buffer);
+    }
+
+    #[test]
+    fn hook_timeout_follows_the_handler_timeout_unless_set() {
+        let config = Config::parse("handler_timeout_ms = 1000\n").unwrap();
+        assert_eq!(
+            config.resolve_hook_timeout_millis(),
+            1000 + HOOK_TIMEOUT_MARGIN_MILLIS
+        );
+
+        let config = Config::parse("handler_timeout_ms = 100\nhook_timeout_ms = 100\n").unwrap();
+        assert_eq!(config.resolve_hook_timeout_millis(), 100);
+    }
+
+    #[test]
+    fn hook_timeout_below_the_handler_timeout_is_rejected() {
+        assert!(Config::parse("hook_timeout_ms = 100\n").is_err());
+        assert!(Config::parse("hook_timeout_ms = 300\nhandler_timeout_ms = 400\n").is_err());
+
+        // a handler timeout from the command line is checked against rekey.ini
+        let mut config = Config::parse("hook_timeout_ms = 300\n").unwrap();
+        config.handler_timeout_millis = 400;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        for contents in [
+            "unknown_device = maybe",
+            "colour = blue",
+            "backend",
+            "handler_timeout_ms = 0",
+            "handler_timeout_ms = soon",
+            "hook_timeout_ms = 0",
+        ] {
+            assert!(Config::parse(contents).is_err(), "{}", contents);
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use std::fmt;
+
+use crate::{config::DEFAULT_HANDLER_TIMEOUT_MILLIS, RekeyError};
+
+// the LRESULT values rekey_exe answers WM_USER_SHOULD_SKIP_INPUT and
+// WM_USER_SHOULD_SKIP_MOUSE_INPUT with
+pub const DONT_SKIP_INPUT_RESULT: isize = 1;
+pub const SKIP_INPUT_RESULT: isize = 42;
+
+// unless hook_timeout_ms is set the dll hooks wait this much longer than the script handler
+// timeout for rekey_exe to answer, so a slow handler is passed on by rekey_exe before it also
+// counts as a hook timeout
+pub const HOOK_TIMEOUT_MARGIN_MILLIS: u32 = 300;
+pub const DEFAULT_HOOK_TIMEOUT_MILLIS: u32 =
+    DEFAULT_HANDLER_TIMEOUT_MILLIS + HOOK_TIMEOUT_MARGIN_MILLIS;
+
+// what the dll hooks do with input rekey_exe didn't answer for in time or couldn't be asked about
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum FailPolicy {
+    // pass the input on, a hung rekey can't lock up the keyboard
+    Open,
+    // drop the input, remapped keys don't leak through while rekey is busy
+    Closed,
+}
+
+impl FailPolicy {
+    pub fn parse(s: &str) -> Result<Self, RekeyError> {
+        match s {
+            "open" => Result::Ok(FailPolicy::Open),
+            "closed" => Result::Ok(FailPolicy::Closed),
+            _ => Result::Err(RekeyError::GenericError(format!(
+                "invalid fail policy {}, expected 'open' or 'closed'",
+                s
+            ))),
+        }
+    }
+
+    pub fn to_u32(&self) -> u32 {
+        return match *self {
+            FailPolicy::Open => 0,
+            FailPolicy::Closed => 1,
+        };
+    }
+
+    pub fn from_u32(value: u32) -> Result<Self, RekeyError> {
+        return match value {
+            0 => Result::Ok(FailPolicy::Open),
+            1 => Result::Ok(FailPolicy::Closed),
+            _ => Result::Err(RekeyError::GenericError(format!(
+                "invalid fail policy {}",
+                value
+            ))),
+        };
+    }
+
+    fn decision(&self) -> HookDecision {
+        return match *self {
+            FailPolicy::Open => HookDecision::PassOn,
+            FailPolicy::Closed => HookDecision::Skip,
+        };
+    }
+}
+
+impl fmt::Display for FailPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FailPolicy::Open => write!(f, "open"),
+            FailPolicy::Closed => write!(f, "closed"),
+        }
+    }
+}
+
+// the outcome of asking rekey_exe about one input message
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum HookReply {
+    Answered(isize),
+    TimedOut,
+    // rekey_exe's window is gone or hung
+    Failed,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum HookDecision {
+    Skip,
+    PassOn,
+}
+
+// counters kept by the dll hooks, every hooked process has its own and only uses them to throttle
+// its log messages, there is no total across processes
+#[derive(Default, Debug)]
+pub struct HookStats {
+    pub timeouts: u64,
+    pub failures: u64,
+    pub consecutive_timeouts: u64,
+}
+
+// only log the first timeout of a run and then every this many so a hung rekey doesn't flood
+// the log
+const LOG_EVERY: u64 = 100;
+
+impl HookStats {
+    // counts the reply and decides whether the hook drops the input
+    pub fn record(&mut self, reply: HookReply, policy: FailPolicy) -> HookDecision {
+        match reply {
+            HookReply::Answered(result) => {
+                self.consecutive_timeouts = 0;
+                if result == SKIP_INPUT_RESULT {
+                    return HookDecision::Skip;
+                }
+                return HookDecision::PassOn;
+            }
+            HookReply::TimedOut => {
+                self.timeouts += 1;
+                self.consecutive_timeouts += 1;
+                return policy.decision();
+            }
+            HookReply::Failed => {
+                self.failures += 1;
+                return policy.decision();
+            }
+        }
+    }
+
+    pub fn should_log_timeout(&self) -> bool {
+        return self.consecutive_timeouts == 1 || self.timeouts % LOG_EVERY == 0;
+    }
+
+    pub fn should_log_failure(&self) -> bool {
+        return self.failures == 1 || self.failures % LOG_EVERY == 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_are_followed_regardless_of_policy() {
+        for policy in [FailPolicy::Open, FailPolicy::Closed] {
+            let mut stats = HookStats::default();
+            assert_eq!(
+                stats.record(HookReply::Answered(SKIP_INPUT_RESULT), policy),
+                HookDecision::Skip
+            );
+            assert_eq!(
+                stats.record(HookReply::Answered(DONT_SKIP_INPUT_RESULT), policy),
+                HookDecision::PassOn
+            );
+            assert_eq!(stats.timeouts, 0);
+            assert_eq!(stats.failures, 0);
+        }
+    }
+
+    #[test]
+    fn fail_open_passes_input_on() {
+        let mut stats = HookStats::default();
+        assert_eq!(
+            stats.record(HookReply::TimedOut, FailPolicy::Open),
+            HookDecision::PassOn
+        );
+        assert_eq!(
+            stats.record(HookReply::Failed, FailPolicy::Open),
+            HookDecision::PassOn
+        );
+    }
+
+    #[test]
+    fn fail_closed_drops_input() {
+        let mut stats = HookStats::default();
+        assert_eq!(
+            stats.record(HookReply::TimedOut, FailPolicy::Closed),
+            HookDecision::Skip
+        );
+        assert_eq!(
+            stats.record(HookReply::Failed, FailPolicy::Closed),
+            HookDecision::Skip
+        );
+    }
+
+    #[test]
+    fn timeouts_are_counted() {
+        let mut stats = HookStats::default();
+        stats.record(HookReply::TimedOut, FailPolicy::Open);
+        stats.record(HookReply::TimedOut, FailPolicy::Open);
+        stats.record(HookReply::Failed, FailPolicy::Open);
+        assert_eq!(stats.timeouts, 2);
+        assert_eq!(stats.consecutive_timeouts, 2);
+        assert_eq!(stats.failures, 1);
+
+        stats.record(
+            HookReply::Answered(DONT_SKIP_INPUT_RESULT),
+            FailPolicy::Open,
+        );
+        assert_eq!(stats.timeouts, 2);
+        assert_eq!(stats.consecutive_timeouts, 0);
+    }
+
+    #[test]
+    fn timeout_logging_is_throttled() {
+        let mut stats = HookStats::default();
+        let mut logged = 0;
+        for _ in 0..250 {
+            stats.record(HookReply::TimedOut, FailPolicy::Open);
+            if stats.should_log_timeout() {
+                logged += 1;
+            }
+        }
+        assert_eq!(logged, 3);
+
+        stats.record(
+            HookReply::Answered(DONT_SKIP_INPUT_RESULT),
+            FailPolicy::Open,
+        );
+        stats.record(HookReply::TimedOut, FailPolicy::Open);
+        assert!(stats.should_log_timeout());
+    }
+
+    #[test]
+    fn failure_logging_is_throttled() {
+        let mut stats = HookStats::default();
+        let mut logged = 0;
+        for _ in 0..250 {
+            stats.record(HookReply::Failed, FailPolicy::Open);
+            if stats.should_log_failure() {
+                logged += 1;
+            }
+        }
+        assert_eq!(logged, 3);
+    }
+
+    #[test]
+    fn policy_round_trips() {
+        for policy in [FailPolicy::Open, FailPolicy::Closed] {
+            assert_eq!(FailPolicy::from_u32(policy.to_u32()).unwrap(), policy);
+            assert_eq!(FailPolicy::parse(&policy.to_string()).unwrap(), policy);
+        }
+        assert!(FailPolicy::from_u32(2).is_err());
+        assert!(FailPolicy::parse("ajar").is_err());
+    }
+}
@@ -1,27 +1,69 @@
 use chrono::Local;
 use directories::ProjectDirs;
-use std::{fmt, fs::OpenOptions, io::Write, path::PathBuf};
+#[cfg(windows)]
+use hook_protocol::{DONT_SKIP_INPUT_RESULT, SKIP_INPUT_RESULT};
+use lazy_static::lazy_static;
+use std::{
+    fmt,
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Mutex,
+    },
+};
+#[cfg(windows)]
 use vkeys::VKEY_LOOKUP_BY_NAME;
+#[cfg(windows)]
 use windows::Win32::{
     Foundation::LRESULT,
     UI::{
-        Input::KeyboardAndMouse::{VkKeyScanW, VIRTUAL_KEY, VK_0, VK_9, VK_NUMPAD0, VK_NUMPAD9, VK_Z, VK_A},
+        Input::KeyboardAndMouse::{
+            VkKeyScanW, VIRTUAL_KEY, VK_0, VK_9, VK_A, VK_NUMPAD0, VK_NUMPAD9, VK_Z,
+        },
         WindowsAndMessaging::WM_USER,
     },
 };
 
+pub mod clock;
+pub mod config;
+pub mod hook_protocol;
+pub mod shared_data;
+pub mod timers;
+pub mod unknown_device;
+#[cfg(windows)]
 pub mod vkeys;
 
+// the protocol, shared data and config modules are plain rust so they build and test off windows,
+// everything touching Win32 is gated
+#[cfg(windows)]
 pub const WM_USER_SHOULD_SKIP_INPUT: u32 = WM_USER + 300;
+#[cfg(windows)]
 pub const WM_USER_SHELL_ICON: u32 = WM_USER + 301;
-pub const DONT_SKIP_INPUT: LRESULT = LRESULT(1);
-pub const SKIP_INPUT: LRESULT = LRESULT(42);
+#[cfg(windows)]
+pub const WM_USER_SHOULD_SKIP_MOUSE_INPUT: u32 = WM_USER + 302;
+#[cfg(windows)]
+pub const WM_USER_INPUT_REGISTRATIONS_CHANGED: u32 = WM_USER + 303;
+#[cfg(windows)]
+pub const WM_USER_INSTANCE_COMMAND: u32 = WM_USER + 304;
+#[cfg(windows)]
+pub const DONT_SKIP_INPUT: LRESULT = LRESULT(DONT_SKIP_INPUT_RESULT);
+#[cfg(windows)]
+pub const SKIP_INPUT: LRESULT = LRESULT(SKIP_INPUT_RESULT);
 pub const REKEY_API_JS_FILENAME: &str = "rekey-api.js";
+// dwExtraInfo of all input sent by rekey so the hooks can tell it apart from other injected input
+pub const REKEY_INPUT_EXTRA_INFO: usize = 0x52454B59;
+// or'ed into the wparam of WM_USER_SHOULD_SKIP_INPUT and WM_USER_SHOULD_SKIP_MOUSE_INPUT when the
+// input was sent by rekey, the rest of the wparam is the virtual key or mouse message
+pub const SELF_INJECTED_WPARAM_FLAG: usize = 0x10000;
 
 #[derive(Debug)]
 pub enum RekeyError {
     GenericError(String),
+    #[cfg(windows)]
     Win32GetLastError(String, Result<(), windows::core::Error>),
+    #[cfg(windows)]
     Win32Error(String, windows::core::Error),
     IoError(std::io::Error),
 }
@@ -38,6 +80,7 @@ impl fmt::Display for RekeyError {
             RekeyError::GenericError(s) => {
                 write!(f, "Generic Error: {}", s)
             }
+            #[cfg(windows)]
             RekeyError::Win32GetLastError(s, error) => {
                 let error_as_string = match error {
                     Result::Ok(()) => "".to_string(),
@@ -45,6 +88,7 @@ impl fmt::Display for RekeyError {
                 };
                 write!(f, "Win32 Error: {}: {}", s, error_as_string)
             }
+            #[cfg(windows)]
             RekeyError::Win32Error(s, error) => {
                 write!(f, "Win32 Error: {}: {}", s, error)
             }
@@ -72,18 +116,118 @@ pub fn get_user_dir() -> Result<PathBuf, RekeyError> {
     }
 }
 
+lazy_static! {
+    // set from the command line, otherwise files live in the user dir
+    static ref LOG_FILENAME: Mutex<Option<PathBuf>> = Mutex::new(Option::None);
+    static ref SCRIPTS_DIR: Mutex<Option<PathBuf>> = Mutex::new(Option::None);
+}
+
 pub fn get_log_filename() -> Result<PathBuf, RekeyError> {
+    let log_filename = LOG_FILENAME.lock().map_err(|err| {
+        RekeyError::GenericError(format!("could not get log filename lock: {}", err))
+    })?;
+    if let Option::Some(log_filename) = log_filename.as_ref() {
+        return Result::Ok(log_filename.clone());
+    }
     return Result::Ok(get_user_dir()?.join("rekey.log"));
 }
 
+pub fn set_log_filename(filename: PathBuf) -> Result<(), RekeyError> {
+    let mut log_filename = LOG_FILENAME.lock().map_err(|err| {
+        RekeyError::GenericError(format!("could not get log filename lock: {}", err))
+    })?;
+    *log_filename = Option::Some(filename);
+    return Result::Ok(());
+}
+
+pub fn get_config_filename() -> Result<PathBuf, RekeyError> {
+    return Result::Ok(get_user_dir()?.join("rekey.ini"));
+}
+
 pub fn get_scripts_dir() -> Result<PathBuf, RekeyError> {
+    let scripts_dir = SCRIPTS_DIR.lock().map_err(|err| {
+        RekeyError::GenericError(format!("could not get scripts dir lock: {}", err))
+    })?;
+    if let Option::Some(scripts_dir) = scripts_dir.as_ref() {
+        return Result::Ok(scripts_dir.clone());
+    }
     return Result::Ok(get_user_dir()?.join("scripts"));
 }
 
+pub fn set_scripts_dir(dir: PathBuf) -> Result<(), RekeyError> {
+    let mut scripts_dir = SCRIPTS_DIR.lock().map_err(|err| {
+        RekeyError::GenericError(format!("could not get scripts dir lock: {}", err))
+    })?;
+    *scripts_dir = Option::Some(dir);
+    return Result::Ok(());
+}
+
+// messages more detailed than the log level are dropped
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum LogLevel {
+    Error = 0,
+    Info = 1,
+    Debug = 2,
+}
+
+impl LogLevel {
+    pub fn parse(s: &str) -> Result<Self, RekeyError> {
+        match s {
+            "error" => Result::Ok(LogLevel::Error),
+            "info" => Result::Ok(LogLevel::Info),
+            "debug" => Result::Ok(LogLevel::Debug),
+            _ => Result::Err(RekeyError::GenericError(format!(
+                "invalid log level {}, expected 'error', 'info' or 'debug'",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LogLevel::Error => write!(f, "error"),
+            LogLevel::Info => write!(f, "info"),
+            LogLevel::Debug => write!(f, "debug"),
+        }
+    }
+}
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Debug as u8);
+
+pub fn set_log_level(level: LogLevel) -> () {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn error<S>(s: S) -> ()
+where
+    S: Into<String>,
+{
+    log(LogLevel::Error, s);
+}
+
+pub fn info<S>(s: S) -> ()
+where
+    S: Into<String>,
+{
+    log(LogLevel::Info, s);
+}
+
 pub fn debug<S>(s: S) -> ()
 where
     S: Into<String>,
 {
+    log(LogLevel::Debug, s);
+}
+
+fn log<S>(level: LogLevel, s: S) -> ()
+where
+    S: Into<String>,
+{
+    if level as u8 > LOG_LEVEL.load(Ordering::Relaxed) {
+        return;
+    }
     let now = Local::now();
     let s = s.into();
 
@@ -103,6 +247,22 @@ where
     }
 }
 
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => {{
+        let res = $crate::error(format!($($arg)*));
+        res
+    }}
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {{
+        let res = $crate::info(format!($($arg)*));
+        res
+    }}
+}
+
 #[macro_export]
 macro_rules! debug {
     ($($arg:tt)*) => {{
@@ -133,6 +293,39 @@ pub struct KeyboardModifiers {
     pub hankaku: bool,
 }
 
+// splits a key expression like "ctrl+shift+k" into its modifiers and its key, a trailing "+" is
+// the plus key itself so "ctrl++" is ctrl and "+"
+pub fn split_key_expr(key_expr: &str) -> Result<(Vec<&str>, &str), RekeyError> {
+    let (modifiers, key) = if key_expr == "+" {
+        (Option::None, "+")
+    } else if let Option::Some(modifiers) = key_expr.strip_suffix("++") {
+        (Option::Some(modifiers), "+")
+    } else {
+        match key_expr.rsplit_once('+') {
+            Option::Some((modifiers, key)) => (Option::Some(modifiers), key),
+            Option::None => (Option::None, key_expr),
+        }
+    };
+    if key.is_empty() {
+        return Result::Err(RekeyError::GenericError(format!(
+            "missing key in key expression \"{}\"",
+            key_expr
+        )));
+    }
+    let modifiers: Vec<&str> = match modifiers {
+        Option::Some(modifiers) => modifiers.split('+').collect(),
+        Option::None => vec![],
+    };
+    if modifiers.iter().any(|modifier| modifier.is_empty()) {
+        return Result::Err(RekeyError::GenericError(format!(
+            "empty modifier in key expression \"{}\"",
+            key_expr
+        )));
+    }
+    return Result::Ok((modifiers, key));
+}
+
+#[cfg(windows)]
 pub struct ToVirtualKeyResult {
     pub vkey: VIRTUAL_KEY,
     pub shift: bool,
@@ -141,6 +334,7 @@ pub struct ToVirtualKeyResult {
     pub hankaku: bool,
 }
 
+#[cfg(windows)]
 impl ToVirtualKeyResult {
     fn from_vkey(vkey: VIRTUAL_KEY) -> Self {
         return ToVirtualKeyResult {
@@ -153,6 +347,7 @@ impl ToVirtualKeyResult {
     }
 }
 
+#[cfg(windows)]
 pub fn to_virtual_key(s: &str) -> Result<ToVirtualKeyResult, RekeyError> {
     if let Option::Some(lookup_value) = VKEY_LOOKUP_BY_NAME.get(s.to_ascii_lowercase().as_str()) {
         return Result::Ok(ToVirtualKeyResult::from_vkey(lookup_value.code));
@@ -161,18 +356,8 @@ pub fn to_virtual_key(s: &str) -> Result<ToVirtualKeyResult, RekeyError> {
     let s = s.to_ascii_lowercase();
     if s.len() == 1 {
         if let Option::Some(ch) = s.chars().next() {
-            let r = unsafe { VkKeyScanW(ch as u16) as u16 };
-            let low = (r & 0xff) as i8;
-            let high = ((r >> 8) & 0xff) as i8;
-            if low >= 0 && high >= 0 {
-                let vkey = VIRTUAL_KEY(low as u16);
-                return Result::Ok(ToVirtualKeyResult {
-                    vkey,
-                    shift: high & 1 == 1,
-                    ctrl: high & 2 == 2,
-                    alt: high & 4 == 4,
-                    hankaku: high & 8 == 8,
-                });
+            if let Option::Some(r) = char_to_virtual_key(ch) {
+                return Result::Ok(r);
             }
         }
     }
@@ -183,6 +368,29 @@ pub fn to_virtual_key(s: &str) -> Result<ToVirtualKeyResult, RekeyError> {
     )));
 }
 
+// the key and modifiers which type the character on the current keyboard layout
+#[cfg(windows)]
+pub fn char_to_virtual_key(ch: char) -> Option<ToVirtualKeyResult> {
+    if ch as u32 > 0xffff {
+        return Option::None;
+    }
+    let r = unsafe { VkKeyScanW(ch as u16) as u16 };
+    let low = (r & 0xff) as i8;
+    let high = ((r >> 8) & 0xff) as i8;
+    if low >= 0 && high >= 0 {
+        let vkey = VIRTUAL_KEY(low as u16);
+        return Option::Some(ToVirtualKeyResult {
+            vkey,
+            shift: high & 1 == 1,
+            ctrl: high & 2 == 2,
+            alt: high & 4 == 4,
+            hankaku: high & 8 == 8,
+        });
+    }
+    return Option::None;
+}
+
+#[cfg(windows)]
 pub fn char_from_vcode(vkey_code: u16) -> Option<char> {
     if vkey_code >= VK_0.0 && vkey_code <= VK_9.0 {
         return char::from_u32(('0' as u32) + (vkey_code - VK_0.0) as u32);
@@ -195,3 +403,33 @@ pub fn char_from_vcode(vkey_code: u16) -> Option<char> {
     }
     return Option::None;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_key_expr_splits_modifiers_from_key() {
+        let cases: Vec<(&str, Vec<&str>, &str)> = vec![
+            ("a", vec![], "a"),
+            ("numpad1", vec![], "numpad1"),
+            ("ctrl+c", vec!["ctrl"], "c"),
+            ("ctrl+shift+k", vec!["ctrl", "shift"], "k"),
+            ("+", vec![], "+"),
+            ("ctrl++", vec!["ctrl"], "+"),
+            ("ctrl+shift++", vec!["ctrl", "shift"], "+"),
+        ];
+        for (key_expr, modifiers, key) in cases {
+            let (actual_modifiers, actual_key) = split_key_expr(key_expr).unwrap();
+            assert_eq!(actual_modifiers, modifiers, "modifiers of {}", key_expr);
+            assert_eq!(actual_key, key, "key of {}", key_expr);
+        }
+    }
+
+    #[test]
+    fn split_key_expr_rejects_missing_parts() {
+        for key_expr in ["", "ctrl+", "+a", "ctrl++a", "ctrl+++"] {
+            assert!(split_key_expr(key_expr).is_err(), "{}", key_expr);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use crate::{hook_protocol::FailPolicy, RekeyError};
+
+// name of the file mapping rekey_exe shares with the hooks in other processes, "Local\" keeps it
+// to the current session so rekey running for other users doesn't clash
+pub const SHARED_DATA_NAME: &str = "Local\\rekey_shared_data";
+
+// bump whenever the layout below changes so hooks from an older dll ignore the block
+pub const SHARED_DATA_PROTOCOL_VERSION: u32 = 2;
+
+const SHARED_DATA_MAGIC: u32 = 0x5245_4B44;
+
+// magic, protocol version, instance id, hhook, hwnd, hook timeout, fail policy, checksum
+pub const SHARED_DATA_SIZE: usize = 4 + 4 + 8 + 8 + 8 + 4 + 4 + 4;
+
+const CHECKSUM_OFFSET: usize = SHARED_DATA_SIZE - 4;
+
+// what the hooks need from the rekey process, handles are stored as i64 so the layout is the
+// same for 32 and 64 bit processes
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct SharedData {
+    pub instance_id: u64,
+    pub hhook: i64,
+    pub hwnd: i64,
+    pub hook_timeout_millis: u32,
+    pub hook_fail_policy: FailPolicy,
+}
+
+impl SharedData {
+    pub fn encode(&self) -> [u8; SHARED_DATA_SIZE] {
+        let mut bytes = [0u8; SHARED_DATA_SIZE];
+        bytes[0..4].copy_from_slice(&SHARED_DATA_MAGIC.to_le_bytes());
+        bytes[4..8].copy_from_slice(&SHARED_DATA_PROTOCOL_VERSION.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.instance_id.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.hhook.to_le_bytes());
+        bytes[24..32].copy_from_slice(&self.hwnd.to_le_bytes());
+        bytes[32..36].copy_from_slice(&self.hook_timeout_millis.to_le_bytes());
+        bytes[36..40].copy_from_slice(&self.hook_fail_policy.to_u32().to_le_bytes());
+        let checksum = checksum(&bytes[0..CHECKSUM_OFFSET]);
+        bytes[CHECKSUM_OFFSET..].copy_from_slice(&checksum.to_le_bytes());
+        return bytes;
+    }
+
+    // fails if the block was never written, was cleared, is from another protocol version or
+    // was read while being written
+    pub fn decode(bytes: &[u8]) -> Result<Self, RekeyError> {
+        if bytes.len() < SHARED_DATA_SIZE {
+            return Result::Err(RekeyError::GenericError(format!(
+                "shared data too short, expected {} bytes, found {}",
+                SHARED_DATA_SIZE,
+                bytes.len()
+            )));
+        }
+        let magic = read_u32(bytes, 0);
+        if magic != SHARED_DATA_MAGIC {
+            return Result::Err(RekeyError::GenericError(
+                "shared data not initialized".to_string(),
+            ));
+        }
+        let version = read_u32(bytes, 4);
+        if version != SHARED_DATA_PROTOCOL_VERSION {
+            return Result::Err(RekeyError::GenericError(format!(
+                "shared data protocol version {} not supported, expected {}",
+                version, SHARED_DATA_PROTOCOL_VERSION
+            )));
+        }
+        let expected_checksum = read_u32(bytes, CHECKSUM_OFFSET);
+        if checksum(&bytes[0..CHECKSUM_OFFSET]) != expected_checksum {
+            return Result::Err(RekeyError::GenericError(
+                "shared data checksum mismatch".to_string(),
+            ));
+        }
+        return Result::Ok(SharedData {
+            instance_id: read_u64(bytes, 8),
+            hhook: read_u64(bytes, 16) as i64,
+            hwnd: read_u64(bytes, 24) as i64,
+            hook_timeout_millis: read_u32(bytes, 32),
+            hook_fail_policy: FailPolicy::from_u32(read_u32(bytes, 36))?,
+        });
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut b = [0u8; 4];
+    b.copy_from_slice(&bytes[offset..offset + 4]);
+    return u32::from_le_bytes(b);
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut b = [0u8; 8];
+    b.copy_from_slice(&bytes[offset..offset + 8]);
+    return u64::from_le_bytes(b);
+}
+
+// 32 bit FNV-1a
+fn checksum(bytes: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    for b in bytes {
+        hash ^= *b as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    return hash;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> SharedData {
+        return SharedData {
+            instance_id: 0x1234_5678_9abc_def0,
+            hhook: 0x0001_0203,
+            hwnd: -2,
+            hook_timeout_millis: 500,
+            hook_fail_policy: FailPolicy::Closed,
+        };
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = sample();
+        assert_eq!(SharedData::decode(&data.encode()).unwrap(), data);
+    }
+
+    #[test]
+    fn uninitialized_block_is_rejected() {
+        assert!(SharedData::decode(&[0u8; SHARED_DATA_SIZE]).is_err());
+    }
+
+    #[test]
+    fn short_block_is_rejected() {
+        let bytes = sample().encode();
+        assert!(SharedData::decode(&bytes[0..SHARED_DATA_SIZE - 1]).is_err());
+    }
+
+    #[test]
+    fn other_protocol_version_is_rejected() {
+        let mut bytes = sample().encode();
+        bytes[4..8].copy_from_slice(&(SHARED_DATA_PROTOCOL_VERSION + 1).to_le_bytes());
+        assert!(SharedData::decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn corrupted_block_is_rejected() {
+        for i in 8..CHECKSUM_OFFSET {
+            let mut bytes = sample().encode();
+            bytes[i] ^= 0x01;
+            assert!(
+                SharedData::decode(&bytes).is_err(),
+                "byte {} not detected",
+                i
+            );
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    cmp,
+    sync::atomic::{AtomicU32, Ordering},
+    time::{Duration, Instant},
+};
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+
+// setInterval never repeats faster than this
+pub const MIN_INTERVAL: Duration = Duration::from_millis(1);
+// like browsers delays are at most 2^31-1 ms, longer ones such as Infinity are clamped to this
+pub const MAX_DELAY: Duration = Duration::from_millis(i32::MAX as u64);
+
+struct ScheduledTimer<T> {
+    id: i32,
+    time: Instant,
+    // Some for timers created with setInterval
+    interval: Option<Duration>,
+    callback: T,
+}
+
+// the timeouts and intervals of one script, `T` is whatever runs when a timer is due
+pub struct TimerQueue<T> {
+    timers: Vec<ScheduledTimer<T>>,
+}
+
+impl<T: Clone> Default for TimerQueue<T> {
+    fn default() -> Self {
+        return TimerQueue::new();
+    }
+}
+
+impl<T: Clone> TimerQueue<T> {
+    pub fn new() -> Self {
+        return TimerQueue { timers: vec![] };
+    }
+
+    // schedules a timer `delay` after `now` and returns its id, repeating timers are rescheduled
+    // every `delay` once due
+    pub fn add(&mut self, now: Instant, delay: Duration, repeat: bool, callback: T) -> i32 {
+        let delay = cmp::min(delay, MAX_DELAY);
+        let interval = if repeat {
+            Option::Some(cmp::max(delay, MIN_INTERVAL))
+        } else {
+            Option::None
+        };
+        let id = next_id();
+        self.timers.push(ScheduledTimer {
+            id,
+            time: now + delay,
+            interval,
+            callback,
+        });
+        return id;
+    }
+
+    pub fn remove(&mut self, id: i32) -> () {
+        self.timers.retain(|timer| timer.id != id);
+    }
+
+    pub fn clear(&mut self) -> () {
+        self.timers.clear();
+    }
+
+    // when the next timer is due
+    pub fn next_time(&self) -> Option<Instant> {
+        return self.timers.iter().map(|timer| timer.time).min();
+    }
+
+    // the ids of the timers due at `now`, earliest first
+    pub fn due_ids(&self, now: Instant) -> Vec<i32> {
+        let mut due: Vec<(Instant, i32)> = self
+            .timers
+            .iter()
+            .filter(|timer| now >= timer.time)
+            .map(|timer| (timer.time, timer.id))
+            .collect();
+        due.sort();
+        return due.into_iter().map(|(_, id)| id).collect();
+    }
+
+    // removes a due timeout or reschedules a due interval from `now` and returns its callback,
+    // None if the timer was removed since `due_ids`
+    pub fn take_due(&mut self, id: i32, now: Instant) -> Option<T> {
+        let i = self.timers.iter().position(|timer| timer.id == id)?;
+        let timer = &mut self.timers[i];
+        if let Option::Some(interval) = timer.interval {
+            timer.time = now + interval;
+            return Option::Some(timer.callback.clone());
+        }
+        return Option::Some(self.timers.remove(i).callback);
+    }
+}
+
+// ids count from 1 to i32::MAX so they only repeat after billions of timers, javascript treats
+// 0 as false so lets avoid that
+fn next_id() -> i32 {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed) % (i32::MAX as u32);
+    return id as i32 + 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, FakeClock};
+
+    fn ms(millis: u64) -> Duration {
+        return Duration::from_millis(millis);
+    }
+
+    // runs the due timers like the script thread does, `on_run` may change the queue
+    fn run_due(
+        queue: &mut TimerQueue<&'static str>,
+        clock: &FakeClock,
+        mut on_run: impl FnMut(&mut TimerQueue<&'static str>, &'static str),
+    ) -> Vec<&'static str> {
+        let now = clock.now();
+        let mut ran = vec![];
+        for id in queue.due_ids(now) {
+            if let Option::Some(callback) = queue.take_due(id, now) {
+                ran.push(callback);
+                on_run(queue, callback);
+            }
+        }
+        return ran;
+    }
+
+    #[test]
+    fn timeouts_run_once_when_due() {
+        let clock = FakeClock::new();
+        let mut queue = TimerQueue::new();
+        queue.add(clock.now(), ms(10), false, "a");
+        assert_eq!(queue.next_time(), Option::Some(clock.now() + ms(10)));
+
+        clock.advance(ms(9));
+        assert!(run_due(&mut queue, &clock, |_, _| {}).is_empty());
+        clock.advance(ms(1));
+        assert_eq!(run_due(&mut queue, &clock, |_, _| {}), vec!["a"]);
+        clock.advance(ms(100));
+        assert!(run_due(&mut queue, &clock, |_, _| {}).is_empty());
+        assert_eq!(queue.next_time(), Option::None);
+    }
+
+    #[test]
+    fn intervals_repeat_from_when_they_ran() {
+        let clock = FakeClock::new();
+        let mut queue = TimerQueue::new();
+        queue.add(clock.now(), ms(10), true, "a");
+
+        clock.advance(ms(15));
+        assert_eq!(run_due(&mut queue, &clock, |_, _| {}), vec!["a"]);
+        assert_eq!(queue.next_time(), Option::Some(clock.now() + ms(10)));
+        clock.advance(ms(9));
+        assert!(run_due(&mut queue, &clock, |_, _| {}).is_empty());
+        clock.advance(ms(1));
+        assert_eq!(run_due(&mut queue, &clock, |_, _| {}), vec!["a"]);
+    }
+
+    #[test]
+    fn zero_intervals_repeat_after_the_minimum() {
+        let clock = FakeClock::new();
+        let mut queue = TimerQueue::new();
+        queue.add(clock.now(), ms(0), true, "a");
+        assert_eq!(run_due(&mut queue, &clock, |_, _| {}), vec!["a"]);
+        assert_eq!(queue.next_time(), Option::Some(clock.now() + MIN_INTERVAL));
+    }
+
+    #[test]
+    fn huge_delays_are_clamped() {
+        let clock = FakeClock::new();
+        let mut queue = TimerQueue::new();
+        // what setTimeout(cb, Infinity) and setInterval(cb, 1e300) pass in
+        queue.add(
+            clock.now(),
+            Duration::from_millis(f64::INFINITY as u64),
+            false,
+            "a",
+        );
+        queue.add(clock.now(), Duration::from_millis(1e300 as u64), true, "b");
+        assert_eq!(queue.next_time(), Option::Some(clock.now() + MAX_DELAY));
+
+        clock.advance(MAX_DELAY);
+        assert_eq!(run_due(&mut queue, &clock, |_, _| {}), vec!["a", "b"]);
+        assert_eq!(queue.next_time(), Option::Some(clock.now() + MAX_DELAY));
+    }
+
+    #[test]
+    fn due_timers_run_earliest_first() {
+        let clock = FakeClock::new();
+        let mut queue = TimerQueue::new();
+        queue.add(clock.now(), ms(30), false, "c");
+        queue.add(clock.now(), ms(10), false, "a");
+        queue.add(clock.now(), ms(20), false, "b");
+        clock.advance(ms(30));
+        assert_eq!(run_due(&mut queue, &clock, |_, _| {}), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn removed_timers_dont_run() {
+        let clock = FakeClock::new();
+        let mut queue = TimerQueue::new();
+        let id = queue.add(clock.now(), ms(10), true, "a");
+        queue.remove(id);
+        clock.advance(ms(10));
+        assert!(run_due(&mut queue, &clock, |_, _| {}).is_empty());
+    }
+
+    #[test]
+    fn timers_removed_by_an_earlier_callback_dont_run() {
+        let clock = FakeClock::new();
+        let mut queue = TimerQueue::new();
+        queue.add(clock.now(), ms(10), false, "a");
+        let b = queue.add(clock.now(), ms(10), true, "b");
+        clock.advance(ms(10));
+        let ran = run_due(&mut queue, &clock, |queue, callback| {
+            if callback == "a" {
+                queue.remove(b);
+            }
+        });
+        assert_eq!(ran, vec!["a"]);
+        assert_eq!(queue.next_time(), Option::None);
+    }
+
+    #[test]
+    fn timers_added_by_a_callback_wait_for_the_next_run() {
+        let clock = FakeClock::new();
+        let mut queue = TimerQueue::new();
+        queue.add(clock.now(), ms(0), false, "a");
+        let now = clock.now();
+        let ran = run_due(&mut queue, &clock, |queue, _| {
+            queue.add(now, ms(0), false, "b");
+        });
+        assert_eq!(ran, vec!["a"]);
+        assert_eq!(run_due(&mut queue, &clock, |_, _| {}), vec!["b"]);
+    }
+
+    #[test]
+    fn ids_dont_repeat_after_u16_max_timers() {
+        let clock = FakeClock::new();
+        let mut queue = TimerQueue::new();
+        let interval = queue.add(clock.now(), ms(10), true, "interval");
+        for _ in 0..(u16::MAX as u32 + 2) {
+            let id = queue.add(clock.now(), ms(0), false, "a");
+            assert!(id > 0);
+            assert_ne!(id, interval);
+            queue.remove(id);
+        }
+        clock.advance(ms(10));
+        assert_eq!(run_due(&mut queue, &clock, |_, _| {}), vec!["interval"]);
+    }
+}
@@ -0,0 +1,183 @@
+use std::fmt;
+
+use crate::RekeyError;
+
+// what to do with a handler that has a device filter when the device of an event is unknown
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum UnknownDevicePolicy {
+    // call the handler as if the device matched
+    Match,
+    // don't call the handler
+    Skip,
+    // hold the event briefly for the device to be identified, if it still isn't don't call the
+    // handler
+    Buffer,
+}
+
+impl UnknownDevicePolicy {
+    pub fn parse(s: &str) -> Result<Self, RekeyError> {
+        match s {
+            "match" => Result::Ok(UnknownDevicePolicy::Match),
+            "skip" => Result::Ok(UnknownDevicePolicy::Skip),
+            "buffer" => Result::Ok(UnknownDevicePolicy::Buffer),
+            _ => Result::Err(RekeyError::GenericError(format!(
+                "invalid unknown device policy {}, expected 'match', 'skip' or 'buffer'",
+                s
+            ))),
+        }
+    }
+
+    // the policy of a handler, its own if it has one, otherwise the script's default and then
+    // the default from rekey.ini
+    pub fn resolve(
+        handler: Option<UnknownDevicePolicy>,
+        script_default: Option<UnknownDevicePolicy>,
+        config_default: UnknownDevicePolicy,
+    ) -> UnknownDevicePolicy {
+        return handler.or(script_default).unwrap_or(config_default);
+    }
+}
+
+impl fmt::Display for UnknownDevicePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UnknownDevicePolicy::Match => write!(f, "match"),
+            UnknownDevicePolicy::Skip => write!(f, "skip"),
+            UnknownDevicePolicy::Buffer => write!(f, "buffer"),
+        }
+    }
+}
+
+// how a handler's device filter treats one event
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum DeviceFilterDecision {
+    // the device is known, test it against the device filter
+    TestFilter,
+    // call the handler without looking at the device
+    Call,
+    // don't call the handler
+    Skip,
+    // hold the event until the device is identified or the buffer times out
+    Buffer,
+}
+
+// `can_buffer` is false once an event has been buffered and for backends which can't hold input
+pub fn device_filter_decision(
+    device_known: bool,
+    has_device_filter: bool,
+    policy: UnknownDevicePolicy,
+    can_buffer: bool,
+) -> DeviceFilterDecision {
+    if !has_device_filter {
+        return DeviceFilterDecision::Call;
+    }
+    if device_known {
+        return DeviceFilterDecision::TestFilter;
+    }
+    return match policy {
+        UnknownDevicePolicy::Match => DeviceFilterDecision::Call,
+        UnknownDevicePolicy::Skip => DeviceFilterDecision::Skip,
+        UnknownDevicePolicy::Buffer if can_buffer => DeviceFilterDecision::Buffer,
+        UnknownDevicePolicy::Buffer => DeviceFilterDecision::Skip,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICIES: [UnknownDevicePolicy; 3] = [
+        UnknownDevicePolicy::Match,
+        UnknownDevicePolicy::Skip,
+        UnknownDevicePolicy::Buffer,
+    ];
+
+    #[test]
+    fn handlers_without_a_device_filter_are_always_called() {
+        for policy in POLICIES {
+            for device_known in [false, true] {
+                for can_buffer in [false, true] {
+                    assert_eq!(
+                        device_filter_decision(device_known, false, policy, can_buffer),
+                        DeviceFilterDecision::Call
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn known_devices_are_tested_against_the_filter() {
+        for policy in POLICIES {
+            for can_buffer in [false, true] {
+                assert_eq!(
+                    device_filter_decision(true, true, policy, can_buffer),
+                    DeviceFilterDecision::TestFilter
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_devices_follow_the_policy() {
+        let cases = [
+            (UnknownDevicePolicy::Match, true, DeviceFilterDecision::Call),
+            (
+                UnknownDevicePolicy::Match,
+                false,
+                DeviceFilterDecision::Call,
+            ),
+            (UnknownDevicePolicy::Skip, true, DeviceFilterDecision::Skip),
+            (UnknownDevicePolicy::Skip, false, DeviceFilterDecision::Skip),
+            (
+                UnknownDevicePolicy::Buffer,
+                true,
+                DeviceFilterDecision::Buffer,
+            ),
+            // already buffered or the backend can't hold input
+            (
+                UnknownDevicePolicy::Buffer,
+                false,
+                DeviceFilterDecision::Skip,
+            ),
+        ];
+        for (policy, can_buffer, decision) in cases {
+            assert_eq!(
+                device_filter_decision(false, true, policy, can_buffer),
+                decision,
+                "{} can_buffer {}",
+                policy,
+                can_buffer
+            );
+        }
+    }
+
+    #[test]
+    fn policies_resolve_handler_then_script_then_config() {
+        let buffer = Option::Some(UnknownDevicePolicy::Buffer);
+        let skip = Option::Some(UnknownDevicePolicy::Skip);
+        assert_eq!(
+            UnknownDevicePolicy::resolve(buffer, skip, UnknownDevicePolicy::Match),
+            UnknownDevicePolicy::Buffer
+        );
+        assert_eq!(
+            UnknownDevicePolicy::resolve(Option::None, skip, UnknownDevicePolicy::Match),
+            UnknownDevicePolicy::Skip
+        );
+        assert_eq!(
+            UnknownDevicePolicy::resolve(Option::None, Option::None, UnknownDevicePolicy::Match),
+            UnknownDevicePolicy::Match
+        );
+    }
+
+    #[test]
+    fn policies_parse() {
+        for policy in POLICIES {
+            assert_eq!(
+                UnknownDevicePolicy::parse(&policy.to_string()).unwrap(),
+                policy
+            );
+        }
+        assert!(UnknownDevicePolicy::parse("maybe").is_err());
+    }
+}
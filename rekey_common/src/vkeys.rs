@@ -63,7 +63,7 @@ lazy_static! {
             insert(
                 &mut t,
                 &d.to_string(),
-                VIRTUAL_KEY(KeyboardAndMouse::VK_A.0 + d_num),
+                VIRTUAL_KEY(KeyboardAndMouse::VK_0.0 + d_num),
             );
         }
         for d in 'a'..='z' {
@@ -97,7 +97,7 @@ lazy_static! {
             insert(
                 &mut t,
                 &s,
-                VIRTUAL_KEY(KeyboardAndMouse::VK_F1.0 + d),
+                VIRTUAL_KEY(KeyboardAndMouse::VK_F1.0 + d - 1),
             );
         }
         insert(&mut t, "num_lock", KeyboardAndMouse::VK_NUMLOCK);
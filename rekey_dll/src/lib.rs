@@ -1,45 +1,65 @@
 use lazy_static::lazy_static;
 use std::{
-    env,
-    fs::{self, File},
-    io::Write,
     sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-use rekey_common::{debug, RekeyError, SKIP_INPUT, WM_USER_SHOULD_SKIP_INPUT};
+use rekey_common::{
+    debug,
+    hook_protocol::{FailPolicy, HookDecision, HookReply, HookStats},
+    shared_data::{SharedData, SHARED_DATA_NAME, SHARED_DATA_SIZE},
+    RekeyError, REKEY_INPUT_EXTRA_INFO, SELF_INJECTED_WPARAM_FLAG, WM_USER_SHOULD_SKIP_INPUT,
+    WM_USER_SHOULD_SKIP_MOUSE_INPUT,
+};
+use windows::Win32::Foundation::HINSTANCE;
+use windows::Win32::System::SystemServices::{DLL_PROCESS_ATTACH, DLL_PROCESS_DETACH};
 use windows::{
-    core::s,
+    core::{s, HSTRING},
     Win32::{
-        Foundation::{HMODULE, HWND, LPARAM, LRESULT, WPARAM},
-        System::LibraryLoader::GetProcAddress,
+        Foundation::{
+            CloseHandle, ERROR_TIMEOUT, HANDLE, HMODULE, HWND, INVALID_HANDLE_VALUE, LPARAM,
+            LRESULT, WPARAM,
+        },
+        System::{
+            LibraryLoader::GetProcAddress,
+            Memory::{
+                CreateFileMappingW, MapViewOfFile, OpenFileMappingW, UnmapViewOfFile,
+                FILE_MAP_READ, FILE_MAP_WRITE, MEMORY_MAPPED_VIEW_ADDRESS, PAGE_READWRITE,
+            },
+        },
         UI::WindowsAndMessaging::{
-            CallNextHookEx, SendMessageW, SetWindowsHookExW, UnhookWindowsHookEx, HC_ACTION, HHOOK,
-            HOOKPROC, WH_KEYBOARD,
+            CallNextHookEx, GetMessageExtraInfo, SendMessageTimeoutW, SetWindowsHookExW,
+            UnhookWindowsHookEx, HC_ACTION, HHOOK, HOOKPROC, MOUSEHOOKSTRUCTEX, SMTO_ABORTIFHUNG,
+            WH_KEYBOARD, WH_MOUSE, WM_LBUTTONDOWN, WM_LBUTTONUP, WM_MBUTTONDOWN, WM_MBUTTONUP,
+            WM_MOUSEHWHEEL, WM_MOUSEWHEEL, WM_RBUTTONDOWN, WM_RBUTTONUP, WM_XBUTTONDOWN,
+            WM_XBUTTONUP,
         },
     },
 };
-use windows::Win32::Foundation::HINSTANCE;
-use windows::Win32::System::SystemServices::{DLL_PROCESS_ATTACH, DLL_PROCESS_DETACH};
 
 type PROC = unsafe extern "system" fn() -> isize;
 
-struct GlobalData {
-    hhook: HHOOK,
-    hwnd: HWND,
+// a view of the named file mapping holding the SharedData, writable in the rekey process and
+// read only in hooked processes
+struct SharedMapping {
+    handle: HANDLE,
+    view: usize,
+    writable: bool,
+    // the instance this process wrote, 0 in hooked processes
+    instance_id: u64,
 }
 
 lazy_static! {
-    static ref MY_DATA: Mutex<Option<GlobalData>> = Mutex::new(Option::None);
+    static ref SHARED_MAPPING: Mutex<Option<SharedMapping>> = Mutex::new(Option::None);
+    // only set in the rekey process, other processes only need the shared data
+    static ref KEYBOARD_HHOOK: Mutex<Option<HHOOK>> = Mutex::new(Option::None);
+    static ref MOUSE_HHOOK: Mutex<Option<HHOOK>> = Mutex::new(Option::None);
+    static ref HOOK_STATS: Mutex<HookStats> = Mutex::new(HookStats::default());
 }
 
 #[no_mangle]
 #[allow(non_snake_case, unused_variables)]
-extern "system" fn DllMain(
-    dll_module: HINSTANCE,
-    call_reason: u32,
-    _: *mut ())
-    -> bool
-{
+extern "system" fn DllMain(dll_module: HINSTANCE, call_reason: u32, _: *mut ()) -> bool {
     match call_reason {
         DLL_PROCESS_ATTACH => {
             debug!("dll: attach");
@@ -47,16 +67,33 @@ extern "system" fn DllMain(
         DLL_PROCESS_DETACH => {
             debug!("dll: detach");
         }
-        _ => ()
+        _ => (),
     }
 
     return true;
 }
 
 #[no_mangle]
-pub extern "C" fn install(dll: u64, hwnd: u64) -> i32 {
+pub extern "C" fn install(
+    dll: u64,
+    hwnd: u64,
+    hook_timeout_millis: u32,
+    hook_fail_policy: u32,
+) -> i32 {
     debug!("dll: installing");
-    match _install(HMODULE(dll as isize), HWND(hwnd as isize)) {
+    let hook_fail_policy = match FailPolicy::from_u32(hook_fail_policy) {
+        Result::Err(err) => {
+            debug!("dll: install failed {}", err);
+            return 1;
+        }
+        Result::Ok(policy) => policy,
+    };
+    match _install(
+        HMODULE(dll as isize),
+        HWND(hwnd as isize),
+        hook_timeout_millis,
+        hook_fail_policy,
+    ) {
         Result::Err(err) => {
             debug!("dll: install failed {}", err);
             return 1;
@@ -68,13 +105,18 @@ pub extern "C" fn install(dll: u64, hwnd: u64) -> i32 {
     };
 }
 
-fn _install(dll: HMODULE, hwnd: HWND) -> Result<(), RekeyError> {
-    let mut data = MY_DATA
+fn _install(
+    dll: HMODULE,
+    hwnd: HWND,
+    hook_timeout_millis: u32,
+    hook_fail_policy: FailPolicy,
+) -> Result<(), RekeyError> {
+    let mut keyboard_hhook = KEYBOARD_HHOOK
         .lock()
-        .map_err(|err| RekeyError::GenericError(format!("could not get data lock: {}", err)))?;
+        .map_err(|err| RekeyError::GenericError(format!("could not get hook lock: {}", err)))?;
 
     unsafe {
-        if data.is_some() {
+        if keyboard_hhook.is_some() {
             return Result::Err(RekeyError::GenericError("already installed".to_string()));
         }
 
@@ -85,9 +127,18 @@ fn _install(dll: HMODULE, hwnd: HWND) -> Result<(), RekeyError> {
         let hhook = SetWindowsHookExW(WH_KEYBOARD, keyboard_hook, dll, 0)
             .map_err(|err| RekeyError::Win32Error("failed to set hook".to_string(), err))?;
 
-        let d = GlobalData { hhook, hwnd };
-        write_global_data(&d)?;
-        *data = Option::Some(d);
+        let d = SharedData {
+            instance_id: new_instance_id(),
+            hhook: hhook.0 as i64,
+            hwnd: hwnd.0 as i64,
+            hook_timeout_millis,
+            hook_fail_policy,
+        };
+        if let Result::Err(err) = write_shared_data(&d) {
+            let _ = UnhookWindowsHookEx(hhook);
+            return Result::Err(err);
+        }
+        *keyboard_hhook = Option::Some(hhook);
     }
     return Result::Ok(());
 }
@@ -108,19 +159,83 @@ pub extern "C" fn uninstall() -> i32 {
 }
 
 fn _uninstall() -> Result<(), RekeyError> {
-    let mut data = MY_DATA
+    let mut keyboard_hhook = KEYBOARD_HHOOK
         .lock()
-        .map_err(|err| RekeyError::GenericError(format!("could not get data lock: {}", err)))?;
+        .map_err(|err| RekeyError::GenericError(format!("could not get hook lock: {}", err)))?;
 
     unsafe {
-        if data.is_none() {
+        if let Some(hhook) = keyboard_hhook.take() {
+            UnhookWindowsHookEx(hhook)
+                .map_err(|err| RekeyError::Win32Error("failed to unhook".to_string(), err))?;
+        } else {
             return Result::Err(RekeyError::GenericError("not installed".to_string()));
         }
+    }
+    return clear_shared_data();
+}
 
-        if let Some(d) = data.as_ref() {
-            UnhookWindowsHookEx(d.hhook)
-                .map_err(|err| RekeyError::Win32Error("failed to unhook".to_string(), err))?;
-            *data = Option::None;
+#[no_mangle]
+pub extern "C" fn install_mouse(dll: u64) -> i32 {
+    debug!("dll: installing mouse");
+    match _install_mouse(HMODULE(dll as isize)) {
+        Result::Err(err) => {
+            debug!("dll: install mouse failed {}", err);
+            return 1;
+        }
+        Result::Ok(()) => {
+            debug!("dll: install mouse success");
+            return 0;
+        }
+    };
+}
+
+fn _install_mouse(dll: HMODULE) -> Result<(), RekeyError> {
+    let mut mouse_hhook = MOUSE_HHOOK.lock().map_err(|err| {
+        RekeyError::GenericError(format!("could not get mouse hook lock: {}", err))
+    })?;
+
+    unsafe {
+        if mouse_hhook.is_some() {
+            return Result::Err(RekeyError::GenericError("already installed".to_string()));
+        }
+
+        let mouse_hook_bare = GetProcAddress(dll, s!("mouse_hook"))
+            .ok_or_else(|| RekeyError::GenericError("failed to find mouse_hook".to_string()))?;
+        let mouse_hook =
+            std::mem::transmute::<Option<PROC>, HOOKPROC>(Option::Some(mouse_hook_bare));
+        let hhook = SetWindowsHookExW(WH_MOUSE, mouse_hook, dll, 0)
+            .map_err(|err| RekeyError::Win32Error("failed to set mouse hook".to_string(), err))?;
+        *mouse_hhook = Option::Some(hhook);
+    }
+    return Result::Ok(());
+}
+
+#[no_mangle]
+pub extern "C" fn uninstall_mouse() -> i32 {
+    debug!("dll: uninstalling mouse");
+    match _uninstall_mouse() {
+        Result::Err(err) => {
+            debug!("dll: uninstall mouse failed {}", err);
+            return 1;
+        }
+        Result::Ok(()) => {
+            debug!("dll: uninstall mouse success");
+            return 0;
+        }
+    };
+}
+
+fn _uninstall_mouse() -> Result<(), RekeyError> {
+    let mut mouse_hhook = MOUSE_HHOOK.lock().map_err(|err| {
+        RekeyError::GenericError(format!("could not get mouse hook lock: {}", err))
+    })?;
+
+    unsafe {
+        if let Some(hhook) = mouse_hhook.take() {
+            UnhookWindowsHookEx(hhook)
+                .map_err(|err| RekeyError::Win32Error("failed to unhook mouse".to_string(), err))?;
+        } else {
+            return Result::Err(RekeyError::GenericError("not installed".to_string()));
         }
     }
     return Result::Ok(());
@@ -140,73 +255,270 @@ pub extern "C" fn keyboard_hook(code: i32, wparam: WPARAM, lparam: LPARAM) -> LR
 }
 
 fn _keyboard_hook(code: i32, wparam: WPARAM, lparam: LPARAM) -> Result<LRESULT, RekeyError> {
-    let mut data = MY_DATA
-        .lock()
-        .map_err(|err| RekeyError::GenericError(format!("could not get data lock: {}", err)))?;
+    let d = read_shared_data()?;
+    let hhook = HHOOK(d.hhook as isize);
+
+    unsafe {
+        if code < 0 || code != HC_ACTION as i32 {
+            return Result::Ok(CallNextHookEx(hhook, code, wparam, lparam));
+        }
+
+        // the extra info of the key message being processed, set by SendInput, the flag is only
+        // for rekey so the next hook still gets the virtual key as is
+        let message = if GetMessageExtraInfo().0 as usize == REKEY_INPUT_EXTRA_INFO {
+            WPARAM(wparam.0 | SELF_INJECTED_WPARAM_FLAG)
+        } else {
+            wparam
+        };
+        if ask_rekey(&d, WM_USER_SHOULD_SKIP_INPUT, message, lparam)? == HookDecision::Skip {
+            return Result::Ok(LRESULT(1));
+        }
+        return Result::Ok(CallNextHookEx(hhook, code, wparam, lparam));
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn mouse_hook(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    match _mouse_hook(code, wparam, lparam) {
+        Result::Err(err) => {
+            debug!("dll: mouse_hook failed {}", err);
+            return LRESULT(0);
+        }
+        Result::Ok(r) => {
+            return r;
+        }
+    };
+}
+
+fn _mouse_hook(code: i32, wparam: WPARAM, lparam: LPARAM) -> Result<LRESULT, RekeyError> {
+    // CallNextHookEx ignores the hook handle, which other processes don't know for this hook
+    let next_hook = HHOOK::default();
 
     unsafe {
-        if data.is_none() {
-            let d = read_global_data()?;
-            *data = Option::Some(d);
+        // moves are too frequent to send to rekey and can't be remapped, so only buttons and
+        // wheels are sent
+        if code < 0
+            || code != HC_ACTION as i32
+            || !matches!(
+                wparam.0 as u32,
+                WM_LBUTTONDOWN
+                    | WM_LBUTTONUP
+                    | WM_RBUTTONDOWN
+                    | WM_RBUTTONUP
+                    | WM_MBUTTONDOWN
+                    | WM_MBUTTONUP
+                    | WM_XBUTTONDOWN
+                    | WM_XBUTTONUP
+                    | WM_MOUSEWHEEL
+                    | WM_MOUSEHWHEEL
+            )
+        {
+            return Result::Ok(CallNextHookEx(next_hook, code, wparam, lparam));
         }
 
-        if let Some(d) = data.as_ref() {
-            if code < 0 || code != HC_ACTION as i32 {
-                return Result::Ok(CallNextHookEx(d.hhook, code, wparam, lparam));
-            }
+        let d = read_shared_data()?;
 
-            let result = SendMessageW(d.hwnd, WM_USER_SHOULD_SKIP_INPUT, wparam, lparam);
-            if result == SKIP_INPUT {
-                return Result::Ok(LRESULT(1));
-            }
-            return Result::Ok(CallNextHookEx(d.hhook, code, wparam, lparam));
+        // the hook struct is only valid in this process, so pass on the high word of
+        // mouseData which holds the x button or the wheel delta
+        let hook_struct = &*(lparam.0 as *const MOUSEHOOKSTRUCTEX);
+        let mouse_data = (hook_struct.mouseData >> 16) as u16 as i16 as isize;
+        let message = if hook_struct.Base.dwExtraInfo == REKEY_INPUT_EXTRA_INFO {
+            WPARAM(wparam.0 | SELF_INJECTED_WPARAM_FLAG)
+        } else {
+            wparam
+        };
+        if ask_rekey(
+            &d,
+            WM_USER_SHOULD_SKIP_MOUSE_INPUT,
+            message,
+            LPARAM(mouse_data),
+        )? == HookDecision::Skip
+        {
+            return Result::Ok(LRESULT(1));
         }
-        return Result::Ok(LRESULT(0));
+        return Result::Ok(CallNextHookEx(next_hook, code, wparam, lparam));
+    }
+}
+
+// asks rekey_exe whether to drop the input, giving up after the configured budget so a busy or
+// hung rekey can't stall input in every process
+fn ask_rekey(
+    d: &SharedData,
+    message: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> Result<HookDecision, RekeyError> {
+    let mut result: usize = 0;
+    let reply = unsafe {
+        let ret = SendMessageTimeoutW(
+            HWND(d.hwnd as isize),
+            message,
+            wparam,
+            lparam,
+            SMTO_ABORTIFHUNG,
+            d.hook_timeout_millis,
+            Option::Some(&mut result),
+        );
+        if ret.0 != 0 {
+            HookReply::Answered(result as isize)
+        } else if windows::core::Error::from_win32().code() == ERROR_TIMEOUT.to_hresult() {
+            HookReply::TimedOut
+        } else {
+            HookReply::Failed
+        }
+    };
+
+    let mut stats = HOOK_STATS
+        .lock()
+        .map_err(|err| RekeyError::GenericError(format!("could not get stats lock: {}", err)))?;
+    let decision = stats.record(reply, d.hook_fail_policy);
+    if reply == HookReply::TimedOut && stats.should_log_timeout() {
+        debug!(
+            "dll: rekey did not answer within {}ms ({} timeouts in this process), failing {}",
+            d.hook_timeout_millis, stats.timeouts, d.hook_fail_policy
+        );
+    } else if reply == HookReply::Failed && stats.should_log_failure() {
+        debug!(
+            "dll: could not reach rekey ({} failures in this process), failing {}",
+            stats.failures, d.hook_fail_policy
+        );
     }
+    return Result::Ok(decision);
 }
 
-fn read_global_data() -> Result<GlobalData, RekeyError> {
-    let dir = env::temp_dir();
-    let filename = dir.join("rekey.dat");
-    debug!("reading {}", filename.display());
+// identifies the rekey instance which wrote the shared data
+fn new_instance_id() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    return ((std::process::id() as u64) << 32) | nanos as u64;
+}
 
-    let contents = fs::read_to_string(filename)?;
-    let mut parts = contents.split(",");
+fn open_shared_mapping(writable: bool) -> Result<SharedMapping, RekeyError> {
+    let name = HSTRING::from(SHARED_DATA_NAME);
+    unsafe {
+        let (handle, access) = if writable {
+            let handle = CreateFileMappingW(
+                INVALID_HANDLE_VALUE,
+                Option::None,
+                PAGE_READWRITE,
+                0,
+                SHARED_DATA_SIZE as u32,
+                &name,
+            )
+            .map_err(|err| {
+                RekeyError::Win32Error("failed to create shared data".to_string(), err)
+            })?;
+            (handle, FILE_MAP_READ | FILE_MAP_WRITE)
+        } else {
+            let handle = OpenFileMappingW(FILE_MAP_READ.0, false, &name).map_err(|err| {
+                RekeyError::Win32Error("failed to open shared data".to_string(), err)
+            })?;
+            (handle, FILE_MAP_READ)
+        };
+        let view = MapViewOfFile(handle, access, 0, 0, SHARED_DATA_SIZE);
+        if view.Value.is_null() {
+            let err = windows::core::Error::from_win32();
+            let _ = CloseHandle(handle);
+            return Result::Err(RekeyError::Win32Error(
+                "failed to map shared data".to_string(),
+                err,
+            ));
+        }
+        return Result::Ok(SharedMapping {
+            handle,
+            view: view.Value as usize,
+            writable,
+            instance_id: 0,
+        });
+    }
+}
 
-    let hhook_str = parts
-        .nth(0)
-        .ok_or_else(|| RekeyError::GenericError("failed to get hhook from file".to_string()))?;
+fn close_shared_mapping(mapping: SharedMapping) {
+    unsafe {
+        let _ = UnmapViewOfFile(MEMORY_MAPPED_VIEW_ADDRESS {
+            Value: mapping.view as *mut core::ffi::c_void,
+        });
+        let _ = CloseHandle(mapping.handle);
+    }
+}
+
+fn read_mapping(mapping: &SharedMapping) -> [u8; SHARED_DATA_SIZE] {
+    let mut bytes = [0u8; SHARED_DATA_SIZE];
+    unsafe {
+        std::ptr::copy_nonoverlapping(
+            mapping.view as *const u8,
+            bytes.as_mut_ptr(),
+            SHARED_DATA_SIZE,
+        );
+    }
+    return bytes;
+}
 
-    let hwnd_str = parts
-        .nth(0)
-        .ok_or_else(|| RekeyError::GenericError("failed to get hwnd from file".to_string()))?;
+fn write_mapping(mapping: &SharedMapping, bytes: &[u8; SHARED_DATA_SIZE]) {
+    unsafe {
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), mapping.view as *mut u8, SHARED_DATA_SIZE);
+    }
+}
 
-    let hhook = hhook_str.parse::<isize>().map_err(|err| {
-        RekeyError::GenericError(format!("failed to parse hhook to string {}", err))
+// called from the hooks in every process, the mapping stays open until the dll is unloaded so
+// each call only copies the block
+fn read_shared_data() -> Result<SharedData, RekeyError> {
+    let mut shared_mapping = SHARED_MAPPING.lock().map_err(|err| {
+        RekeyError::GenericError(format!("could not get shared data lock: {}", err))
     })?;
+    if shared_mapping.is_none() {
+        *shared_mapping = Option::Some(open_shared_mapping(false)?);
+    }
+    let mapping = shared_mapping
+        .as_ref()
+        .ok_or_else(|| RekeyError::GenericError("shared data not open".to_string()))?;
+    return SharedData::decode(&read_mapping(mapping));
+}
 
-    let hwnd = hwnd_str.parse::<isize>().map_err(|err| {
-        RekeyError::GenericError(format!("failed to parse hwnd to string {}", err))
+fn write_shared_data(data: &SharedData) -> Result<(), RekeyError> {
+    let mut shared_mapping = SHARED_MAPPING.lock().map_err(|err| {
+        RekeyError::GenericError(format!("could not get shared data lock: {}", err))
     })?;
+    if !shared_mapping.as_ref().map(|m| m.writable).unwrap_or(false) {
+        if let Option::Some(mapping) = shared_mapping.take() {
+            close_shared_mapping(mapping);
+        }
+        *shared_mapping = Option::Some(open_shared_mapping(true)?);
+    }
+    let mapping = shared_mapping
+        .as_mut()
+        .ok_or_else(|| RekeyError::GenericError("shared data not open".to_string()))?;
 
-    return Result::Ok(GlobalData {
-        hhook: HHOOK(hhook),
-        hwnd: HWND(hwnd),
-    });
+    // the mapping outlives an instance while hooked processes still have it open
+    if let Result::Ok(existing) = SharedData::decode(&read_mapping(mapping)) {
+        if existing.instance_id != data.instance_id {
+            debug!(
+                "dll: replacing shared data of instance {:x}",
+                existing.instance_id
+            );
+        }
+    }
+    write_mapping(mapping, &data.encode());
+    mapping.instance_id = data.instance_id;
+    return Result::Ok(());
 }
 
-fn write_global_data(data: &GlobalData) -> Result<(), RekeyError> {
-    let dir = env::temp_dir();
-    let filename = dir.join("rekey.dat");
-    let filename_clone = filename.clone();
-
-    let mut file = File::create(filename).map_err(|error| {
-        RekeyError::GenericError(format!(
-            "failed to create file: {}: {}",
-            filename_clone.display(),
-            error
-        ))
+// hooks which are still running in other processes stop forwarding input once the block no
+// longer decodes, unless another instance has taken it over since
+fn clear_shared_data() -> Result<(), RekeyError> {
+    let mut shared_mapping = SHARED_MAPPING.lock().map_err(|err| {
+        RekeyError::GenericError(format!("could not get shared data lock: {}", err))
     })?;
-    write!(file, "{},{}", data.hhook.0, data.hwnd.0)?;
+    if let Option::Some(mapping) = shared_mapping.take() {
+        let ours = SharedData::decode(&read_mapping(&mapping))
+            .map(|d| d.instance_id == mapping.instance_id)
+            .unwrap_or(false);
+        if mapping.writable && ours {
+            write_mapping(&mapping, &[0u8; SHARED_DATA_SIZE]);
+        }
+        close_shared_mapping(mapping);
+    }
     return Result::Ok(());
 }
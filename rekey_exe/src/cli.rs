@@ -0,0 +1,267 @@
+use std::{env, path::PathBuf, slice::Iter};
+
+use rekey_common::{
+    config::{parse_millis, InputBackend},
+    LogLevel, RekeyError,
+};
+
+use crate::instance::InstanceCommand;
+
+pub const USAGE: &str = "usage: rekey [options]
+
+  --scripts-dir <dir>   load scripts from dir instead of the user dir
+  --log-file <file>     write the log to file instead of the user dir
+  --log-level <level>   error, info or debug (default)
+  --no-tray             don't show the tray icon
+  --backend <backend>   hook or ll-hook, overrides rekey.ini
+  --handler-timeout <ms>
+                        time the key handlers get per key, overrides rekey.ini
+  --check <script>      evaluate a script without sending input, exits non-zero on error
+  --list-devices        print the attached input devices
+  --reload              reload the scripts of the running instance
+  --open-log            open the log of the running instance
+  --exit                exit the running instance
+  --help                show this help
+";
+
+#[derive(Default)]
+pub struct CliArgs {
+    pub scripts_dir: Option<PathBuf>,
+    pub log_file: Option<PathBuf>,
+    pub log_level: Option<LogLevel>,
+    pub no_tray: bool,
+    pub backend: Option<InputBackend>,
+    pub handler_timeout_millis: Option<u32>,
+    pub check: Option<PathBuf>,
+    pub list_devices: bool,
+    // sent to the running instance, see instance.rs
+    pub command: Option<InstanceCommand>,
+    pub help: bool,
+}
+
+impl CliArgs {
+    pub fn parse(args: &[String]) -> Result<Self, RekeyError> {
+        let mut cli_args = CliArgs::default();
+        let mut args = args.iter();
+        while let Option::Some(arg) = args.next() {
+            match arg.as_str() {
+                "--scripts-dir" => {
+                    cli_args.scripts_dir =
+                        Option::Some(to_absolute_path(next_value(&mut args, arg)?)?);
+                }
+                "--log-file" => {
+                    cli_args.log_file =
+                        Option::Some(to_absolute_path(next_value(&mut args, arg)?)?);
+                }
+                "--log-level" => {
+                    cli_args.log_level =
+                        Option::Some(LogLevel::parse(next_value(&mut args, arg)?)?);
+                }
+                "--no-tray" => {
+                    cli_args.no_tray = true;
+                }
+                "--backend" => {
+                    cli_args.backend =
+                        Option::Some(InputBackend::parse(next_value(&mut args, arg)?)?);
+                }
+                "--handler-timeout" => {
+                    cli_args.handler_timeout_millis =
+                        Option::Some(parse_millis(arg, next_value(&mut args, arg)?)?);
+                }
+                "--check" => {
+                    cli_args.check = Option::Some(to_absolute_path(next_value(&mut args, arg)?)?);
+                }
+                "--list-devices" => {
+                    cli_args.list_devices = true;
+                }
+                "--help" | "-h" | "/?" => {
+                    cli_args.help = true;
+                }
+                _ => {
+                    let command = InstanceCommand::from_arg(arg).ok_or_else(|| {
+                        RekeyError::GenericError(format!("unknown argument {}\n\n{}", arg, USAGE))
+                    })?;
+                    if cli_args.command.is_some() {
+                        return Result::Err(RekeyError::GenericError(format!(
+                            "only one of --reload, --open-log and --exit can be given, found {}",
+                            arg
+                        )));
+                    }
+                    cli_args.command = Option::Some(command);
+                }
+            }
+        }
+        return Result::Ok(cli_args);
+    }
+
+    // the options given which only apply when rekey starts, a second launch can't pass them on
+    // to the running instance
+    pub fn startup_options(&self) -> Vec<&'static str> {
+        let mut options = vec![];
+        if self.scripts_dir.is_some() {
+            options.push("--scripts-dir");
+        }
+        if self.log_file.is_some() {
+            options.push("--log-file");
+        }
+        if self.log_level.is_some() {
+            options.push("--log-level");
+        }
+        if self.no_tray {
+            options.push("--no-tray");
+        }
+        if self.backend.is_some() {
+            options.push("--backend");
+        }
+        if self.handler_timeout_millis.is_some() {
+            options.push("--handler-timeout");
+        }
+        return options;
+    }
+}
+
+fn next_value<'a>(args: &mut Iter<'a, String>, arg: &str) -> Result<&'a str, RekeyError> {
+    return args
+        .next()
+        .map(|value| value.as_str())
+        .ok_or_else(|| RekeyError::GenericError(format!("missing value for {}", arg)));
+}
+
+// relative paths are relative to the directory rekey was started from
+fn to_absolute_path(value: &str) -> Result<PathBuf, RekeyError> {
+    let path = PathBuf::from(value);
+    if path.is_absolute() {
+        return Result::Ok(path);
+    }
+    return Result::Ok(env::current_dir()?.join(path));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<CliArgs, RekeyError> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        return CliArgs::parse(&args);
+    }
+
+    fn parse_error(args: &[&str]) -> String {
+        return match parse(args) {
+            Result::Ok(_) => panic!("expected {:?} to fail", args),
+            Result::Err(err) => err.to_string(),
+        };
+    }
+
+    #[test]
+    fn options_parse() {
+        let cli_args = parse(&[
+            "--log-level",
+            "info",
+            "--no-tray",
+            "--backend",
+            "ll-hook",
+            "--handler-timeout",
+            "50",
+            "--list-devices",
+            "--reload",
+        ])
+        .unwrap();
+        assert_eq!(cli_args.log_level, Option::Some(LogLevel::Info));
+        assert!(cli_args.no_tray);
+        assert_eq!(cli_args.backend, Option::Some(InputBackend::LowLevelHook));
+        assert_eq!(cli_args.handler_timeout_millis, Option::Some(50));
+        assert!(cli_args.list_devices);
+        assert_eq!(cli_args.command, Option::Some(InstanceCommand::Reload));
+        assert!(!cli_args.help);
+
+        for help in ["--help", "-h", "/?"] {
+            assert!(parse(&[help]).unwrap().help, "{}", help);
+        }
+    }
+
+    #[test]
+    fn missing_values_are_rejected() {
+        for arg in [
+            "--scripts-dir",
+            "--log-file",
+            "--log-level",
+            "--backend",
+            "--handler-timeout",
+            "--check",
+        ] {
+            assert!(
+                parse_error(&["--no-tray", arg]).contains(&format!("missing value for {}", arg)),
+                "{}",
+                arg
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_arguments_and_invalid_values_are_rejected() {
+        assert!(parse_error(&["--scripts"]).contains("unknown argument --scripts"));
+        assert!(parse_error(&["scripts"]).contains("unknown argument scripts"));
+        assert!(parse(&["--log-level", "verbose"]).is_err());
+        assert!(parse(&["--backend", "raw"]).is_err());
+        assert!(parse(&["--handler-timeout", "0"]).is_err());
+    }
+
+    #[test]
+    fn only_one_instance_command_is_accepted() {
+        assert_eq!(
+            parse(&["--exit"]).unwrap().command,
+            Option::Some(InstanceCommand::Exit)
+        );
+        assert!(parse_error(&["--reload", "--exit"]).contains("only one of"));
+        assert!(parse_error(&["--open-log", "--open-log"]).contains("only one of"));
+    }
+
+    #[test]
+    fn relative_paths_are_relative_to_the_current_dir() {
+        let current_dir = env::current_dir().unwrap();
+        let cli_args = parse(&["--scripts-dir", "scripts", "--check", "a.js"]).unwrap();
+        assert_eq!(
+            cli_args.scripts_dir,
+            Option::Some(current_dir.join("scripts"))
+        );
+        assert_eq!(cli_args.check, Option::Some(current_dir.join("a.js")));
+
+        let log_file = current_dir.join("rekey.log");
+        let cli_args = parse(&["--log-file", log_file.to_str().unwrap()]).unwrap();
+        assert_eq!(cli_args.log_file, Option::Some(log_file));
+    }
+
+    #[test]
+    fn startup_options_are_listed() {
+        assert!(parse(&[]).unwrap().startup_options().is_empty());
+        // instance commands and one-off options are fine for a second launch
+        let cli_args = parse(&["--reload", "--list-devices", "--check", "a.js", "--help"]).unwrap();
+        assert!(cli_args.startup_options().is_empty());
+
+        let cli_args = parse(&[
+            "--scripts-dir",
+            "scripts",
+            "--log-file",
+            "rekey.log",
+            "--log-level",
+            "error",
+            "--no-tray",
+            "--backend",
+            "hook",
+            "--handler-timeout",
+            "100",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli_args.startup_options(),
+            vec![
+                "--scripts-dir",
+                "--log-file",
+                "--log-level",
+                "--no-tray",
+                "--backend",
+                "--handler-timeout"
+            ]
+        );
+    }
+}
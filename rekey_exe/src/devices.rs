@@ -1,18 +1,153 @@
 use lazy_static::lazy_static;
 use rekey_common::RekeyError;
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::{
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+};
 
-use windows::Win32::Foundation::HANDLE;
+use windows::Win32::{
+    Foundation::HANDLE,
+    UI::Input::{RID_DEVICE_INFO_TYPE, RIM_TYPEHID, RIM_TYPEKEYBOARD, RIM_TYPEMOUSE},
+};
 
-use crate::win32hal::{get_raw_input_device_info_device_name, get_raw_input_device_list};
+use crate::win32hal::{
+    get_hid_product_string, get_raw_input_device_info_device_name,
+    get_raw_input_device_info_hid_usage, get_raw_input_device_list,
+};
+
+// a HID usage page and usage, e.g. consumer control is usage page 0x0C usage 0x01
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub struct HidUsage {
+    pub usage_page: u16,
+    pub usage: u16,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum DeviceType {
+    Keyboard,
+    Mouse,
+    Hid,
+}
+
+impl DeviceType {
+    fn from_raw_input_type(dw_type: RID_DEVICE_INFO_TYPE) -> Option<Self> {
+        return match dw_type {
+            RIM_TYPEKEYBOARD => Option::Some(DeviceType::Keyboard),
+            RIM_TYPEMOUSE => Option::Some(DeviceType::Mouse),
+            RIM_TYPEHID => Option::Some(DeviceType::Hid),
+            _ => Option::None,
+        };
+    }
+}
+
+impl fmt::Display for DeviceType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DeviceType::Keyboard => write!(f, "keyboard"),
+            DeviceType::Mouse => write!(f, "mouse"),
+            DeviceType::Hid => write!(f, "hid"),
+        }
+    }
+}
 
 pub struct Device {
     pub hdevice: HANDLE,
     pub device_name: String,
+    // None for devices windows didn't list
+    pub device_type: Option<DeviceType>,
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+    pub interface: Option<u16>,
+    // None for keyboards and mice
+    pub hid_usage: Option<HidUsage>,
+    // from the HID attributes, several devices of a composite device usually share it
+    pub product_name: Option<String>,
+}
+
+impl Device {
+    fn new(
+        hdevice: HANDLE,
+        device_name: String,
+        device_type: Option<DeviceType>,
+        hid_usage: Option<HidUsage>,
+        product_name: Option<String>,
+    ) -> Self {
+        let device_name_upper = device_name.to_ascii_uppercase();
+        let vendor_id = parse_device_name_id(&device_name_upper, &["VID_", "VID&"]);
+        let product_id = parse_device_name_id(&device_name_upper, &["PID_", "PID&"]);
+        let interface = parse_device_name_id(&device_name_upper, &["MI_"]);
+        return Device {
+            hdevice,
+            device_name,
+            device_type,
+            vendor_id,
+            product_id,
+            interface,
+            hid_usage,
+            product_name,
+        };
+    }
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn id(value: Option<u16>) -> String {
+            return value
+                .map(|v| format!("{:04X}", v))
+                .unwrap_or_else(|| "-".to_string());
+        }
+        let device_type = self
+            .device_type
+            .map(|device_type| device_type.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        write!(
+            f,
+            "{:#x} {} vid {} pid {} mi {}",
+            self.hdevice.0,
+            device_type,
+            id(self.vendor_id),
+            id(self.product_id),
+            id(self.interface)
+        )?;
+        if let Option::Some(product_name) = &self.product_name {
+            write!(f, " \"{}\"", product_name)?;
+        }
+        if let Option::Some(hid_usage) = self.hid_usage {
+            write!(
+                f,
+                " usage {:#04x}:{:#04x}",
+                hid_usage.usage_page, hid_usage.usage
+            )?;
+        }
+        return write!(f, " {}", self.device_name);
+    }
+}
+
+// device names look like "\\?\HID#VID_046D&PID_C52B&MI_00#..." or for bluetooth devices
+// "..._VID&0002046D_PID&B023...", only the low 16 bits are kept to drop the bluetooth vendor source
+fn parse_device_name_id(device_name: &str, prefixes: &[&str]) -> Option<u16> {
+    for prefix in prefixes {
+        if let Option::Some(i) = device_name.find(prefix) {
+            let digits: String = device_name[i + prefix.len()..]
+                .chars()
+                .take_while(|ch| ch.is_ascii_hexdigit())
+                .collect();
+            if digits.is_empty() {
+                continue;
+            }
+            let digits = &digits[digits.len().saturating_sub(4)..];
+            if let Result::Ok(id) = u16::from_str_radix(digits, 16) {
+                return Option::Some(id);
+            }
+        }
+    }
+    return Option::None;
 }
 
 lazy_static! {
     static ref DEVICES: Mutex<Vec<Arc<Device>>> = Mutex::new(vec![]);
+    // the attached devices as the scripts last saw them, None until first read
+    static ref ATTACHED: Mutex<Option<Vec<Arc<Device>>>> = Mutex::new(Option::None);
 }
 
 pub fn find_device(hdevice: HANDLE) -> Result<Arc<Device>, RekeyError> {
@@ -35,23 +170,212 @@ pub fn find_device(hdevice: HANDLE) -> Result<Arc<Device>, RekeyError> {
     }
 
     // if all else fails create an unknown device and return that
-    let device = Arc::new(Device {
+    let device = Arc::new(Device::new(
         hdevice,
-        device_name: "unknown".to_string(),
-    });
+        "unknown".to_string(),
+        Option::None,
+        Option::None,
+        Option::None,
+    ));
     devices.push(device.clone());
 
     return Result::Ok(device);
 }
 
+// re-reads the attached devices, used by rekey --list-devices and getDevices()
+pub fn devices_list() -> Result<Vec<Arc<Device>>, RekeyError> {
+    let mut devices = DEVICES
+        .lock()
+        .map_err(|err| RekeyError::GenericError(format!("could not get devices lock: {}", err)))?;
+    update_device_list(&mut devices)?;
+    return Result::Ok(devices.clone());
+}
+
+pub struct DeviceChanges {
+    pub arrived: Vec<Arc<Device>>,
+    pub removed: Vec<Arc<Device>>,
+}
+
+// the attached devices, replayed as arrivals to newly loaded scripts
+pub fn devices_attached() -> Result<Vec<Arc<Device>>, RekeyError> {
+    let mut attached = lock_attached()?;
+    if let Option::Some(attached) = &*attached {
+        return Result::Ok(attached.clone());
+    }
+    let devices = devices_list()?;
+    *attached = Option::Some(devices.clone());
+    return Result::Ok(devices);
+}
+
+// re-reads the device list and returns what changed since the last call, device change
+// notifications only come for some devices and repeat arrivals when usages are registered so
+// the whole list is compared instead of trusting the notification
+pub fn devices_update_attached() -> Result<DeviceChanges, RekeyError> {
+    let mut attached = lock_attached()?;
+    let devices = devices_list()?;
+    let changes = diff_devices(attached.as_deref().unwrap_or(&[]), &devices);
+    *attached = Option::Some(devices);
+    return Result::Ok(changes);
+}
+
+fn lock_attached() -> Result<MutexGuard<'static, Option<Vec<Arc<Device>>>>, RekeyError> {
+    return ATTACHED.lock().map_err(|err| {
+        RekeyError::GenericError(format!("could not get attached devices lock: {}", err))
+    });
+}
+
+// removed devices keep their old entry since their info can't be read once they're gone
+fn diff_devices(old: &[Arc<Device>], new: &[Arc<Device>]) -> DeviceChanges {
+    let arrived = new
+        .iter()
+        .filter(|device| !old.iter().any(|o| o.hdevice == device.hdevice))
+        .cloned()
+        .collect();
+    let removed = old
+        .iter()
+        .filter(|device| !new.iter().any(|n| n.hdevice == device.hdevice))
+        .cloned()
+        .collect();
+    return DeviceChanges { arrived, removed };
+}
+
+// replaces the list with the attached devices, devices which are still attached keep their
+// entry so the product name is only read once per device
 fn update_device_list(devices: &mut MutexGuard<Vec<Arc<Device>>>) -> Result<(), RekeyError> {
     let device_list = get_raw_input_device_list()?;
+    let mut updated_devices = vec![];
     for device in device_list {
+        if let Option::Some(existing) = devices
+            .iter()
+            .find(|existing| existing.hdevice == device.hDevice && existing.device_type.is_some())
+        {
+            updated_devices.push(existing.clone());
+            continue;
+        }
         let device_name = get_raw_input_device_info_device_name(device.hDevice)?;
-        devices.push(Arc::new(Device {
-            hdevice: device.hDevice,
+        let hid_usage = if device.dwType == RIM_TYPEHID {
+            get_raw_input_device_info_hid_usage(device.hDevice)?
+        } else {
+            Option::None
+        };
+        let product_name = get_hid_product_string(&device_name);
+        updated_devices.push(Arc::new(Device::new(
+            device.hDevice,
             device_name,
-        }));
+            DeviceType::from_raw_input_type(device.dwType),
+            hid_usage,
+            product_name,
+        )));
     }
+    **devices = updated_devices;
     return Result::Ok(());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_name_ids_parse() {
+        let cases: Vec<(&str, Option<u16>, Option<u16>, Option<u16>)> = vec![
+            // usb
+            (
+                r"\\?\HID#VID_046D&PID_C52B&MI_00#8&2e6d3e6d&0&0000#{884b96c3-56ef-11d1-bc8c-00a0c91405dd}",
+                Option::Some(0x046d),
+                Option::Some(0xc52b),
+                Option::Some(0x00),
+            ),
+            (
+                r"\\?\HID#VID_1B1C&PID_1B3D&MI_02&Col01#9&1c8a1d2&0&0000#{4d1e55b2-f16f-11cf-88cb-001111000030}",
+                Option::Some(0x1b1c),
+                Option::Some(0x1b3d),
+                Option::Some(0x02),
+            ),
+            // lower case names are matched too
+            (
+                r"\\?\hid#vid_04d9&pid_a0f8#7&1b2d66e&0&0000#{884b96c3-56ef-11d1-bc8c-00a0c91405dd}",
+                Option::Some(0x04d9),
+                Option::Some(0xa0f8),
+                Option::None,
+            ),
+            // bluetooth, the vendor id carries the vendor id source in its upper bits
+            (
+                r"\\?\HID#{00001124-0000-1000-8000-00805f9b34fb}_VID&0002046d_PID&b342&Col01#8&2ad1c6e&0&0000#{884b96c3-56ef-11d1-bc8c-00a0c91405dd}",
+                Option::Some(0x046d),
+                Option::Some(0xb342),
+                Option::None,
+            ),
+            // bluetooth le
+            (
+                r"\\?\HID#{00001812-0000-1000-8000-00805f9b34fb}_Dev_VID&02046d_PID&b023_REV&0007_d1f2c3b4a596&Col01#9&3a4b5c6&0&0000#{884b96c3-56ef-11d1-bc8c-00a0c91405dd}",
+                Option::Some(0x046d),
+                Option::Some(0xb023),
+                Option::None,
+            ),
+            // a prefix without digits is skipped
+            (
+                r"\\?\HID#VID_&PID_1234#1&2&3",
+                Option::None,
+                Option::Some(0x1234),
+                Option::None,
+            ),
+            // no ids at all
+            (
+                r"\\?\Root#RDP_KBD#0000#{884b96c3-56ef-11d1-bc8c-00a0c91405dd}",
+                Option::None,
+                Option::None,
+                Option::None,
+            ),
+            ("unknown", Option::None, Option::None, Option::None),
+        ];
+        for (device_name, vendor_id, product_id, interface) in cases {
+            let device = Device::new(
+                HANDLE(0),
+                device_name.to_string(),
+                Option::None,
+                Option::None,
+                Option::None,
+            );
+            assert_eq!(device.vendor_id, vendor_id, "vendor id of {}", device_name);
+            assert_eq!(
+                device.product_id, product_id,
+                "product id of {}",
+                device_name
+            );
+            assert_eq!(device.interface, interface, "interface of {}", device_name);
+        }
+    }
+
+    #[test]
+    fn device_changes_diff_by_handle() {
+        fn device(hdevice: isize, device_name: &str) -> Arc<Device> {
+            return Arc::new(Device::new(
+                HANDLE(hdevice),
+                device_name.to_string(),
+                Option::Some(DeviceType::Keyboard),
+                Option::None,
+                Option::None,
+            ));
+        }
+        fn handles(devices: &[Arc<Device>]) -> Vec<isize> {
+            return devices.iter().map(|device| device.hdevice.0).collect();
+        }
+
+        let old = vec![device(1, "a"), device(2, "b")];
+        let new = vec![device(2, "b"), device(3, "c")];
+        let changes = diff_devices(&old, &new);
+        assert_eq!(handles(&changes.arrived), vec![3]);
+        assert_eq!(handles(&changes.removed), vec![1]);
+        assert_eq!(changes.removed[0].device_name, "a");
+
+        // listing the same devices again, e.g. after a re-registration, changes nothing
+        let changes = diff_devices(&new, &new);
+        assert!(changes.arrived.is_empty());
+        assert!(changes.removed.is_empty());
+
+        // everything is an arrival the first time
+        let changes = diff_devices(&[], &new);
+        assert_eq!(handles(&changes.arrived), vec![2, 3]);
+        assert!(changes.removed.is_empty());
+    }
+}
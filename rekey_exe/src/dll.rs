@@ -1,3 +1,7 @@
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use rekey_common::hook_protocol::FailPolicy;
 use windows::{
     core::{s, w},
     Win32::{
@@ -9,8 +13,26 @@ use windows::{
 use crate::{debug, RekeyError};
 
 type PROC = unsafe extern "system" fn() -> isize;
-type FnInstall = extern "C" fn(dll: u64, hwnd: u64) -> i32;
+type FnInstall =
+    extern "C" fn(dll: u64, hwnd: u64, hook_timeout_millis: u32, hook_fail_policy: u32) -> i32;
 type FnUninstall = extern "C" fn() -> i32;
+type FnInstallMouse = extern "C" fn(dll: u64) -> i32;
+type FnUninstallMouse = extern "C" fn() -> i32;
+
+// the mouse hook is only installed while scripts have mouse handlers, so it is toggled from
+// the window proc rather than owned by RekeyDll
+struct MouseHook {
+    dll: HMODULE,
+    install_mouse: FnInstallMouse,
+    uninstall_mouse: FnUninstallMouse,
+    installed: bool,
+}
+
+unsafe impl Send for MouseHook {}
+
+lazy_static! {
+    static ref MOUSE_HOOK: Mutex<Option<MouseHook>> = Mutex::new(Option::None);
+}
 
 pub struct RekeyDll {
     dll: HMODULE,
@@ -29,6 +51,22 @@ impl RekeyDll {
             let uninstall_bare = GetProcAddress(dll, s!("uninstall"))
                 .ok_or_else(|| RekeyError::GenericError("failed to find uninstall".to_string()))?;
             let uninstall = std::mem::transmute::<PROC, FnUninstall>(uninstall_bare);
+            let install_mouse_bare = GetProcAddress(dll, s!("install_mouse")).ok_or_else(|| {
+                RekeyError::GenericError("failed to find install_mouse".to_string())
+            })?;
+            let install_mouse = std::mem::transmute::<PROC, FnInstallMouse>(install_mouse_bare);
+            let uninstall_mouse_bare =
+                GetProcAddress(dll, s!("uninstall_mouse")).ok_or_else(|| {
+                    RekeyError::GenericError("failed to find uninstall_mouse".to_string())
+                })?;
+            let uninstall_mouse =
+                std::mem::transmute::<PROC, FnUninstallMouse>(uninstall_mouse_bare);
+            *lock_mouse_hook()? = Option::Some(MouseHook {
+                dll,
+                install_mouse,
+                uninstall_mouse,
+                installed: false,
+            });
             return Result::Ok(Self {
                 dll,
                 install: Option::Some(install),
@@ -37,9 +75,19 @@ impl RekeyDll {
         }
     }
 
-    pub fn install(&mut self, hwnd: HWND) -> Result<(), RekeyError> {
+    pub fn install(
+        &mut self,
+        hwnd: HWND,
+        hook_timeout_millis: u32,
+        hook_fail_policy: FailPolicy,
+    ) -> Result<(), RekeyError> {
         if let Option::Some(install) = self.install.take() {
-            let install_ret = install(self.dll.0 as u64, hwnd.0 as u64);
+            let install_ret = install(
+                self.dll.0 as u64,
+                hwnd.0 as u64,
+                hook_timeout_millis,
+                hook_fail_policy.to_u32(),
+            );
             if install_ret != 0 {
                 return Result::Err(RekeyError::GenericError(format!(
                     "failed to install: {}",
@@ -54,6 +102,8 @@ impl RekeyDll {
 
     pub fn uninstall(&mut self) -> Result<(), RekeyError> {
         if let Option::Some(uninstall) = self.uninstall.take() {
+            dll_set_mouse_hook_enabled(false)?;
+            *lock_mouse_hook()? = Option::None;
             let uninstall_ret = uninstall();
             if uninstall_ret != 0 {
                 return Result::Err(RekeyError::GenericError(format!(
@@ -83,3 +133,36 @@ impl Drop for RekeyDll {
         }
     }
 }
+
+fn lock_mouse_hook() -> Result<std::sync::MutexGuard<'static, Option<MouseHook>>, RekeyError> {
+    return MOUSE_HOOK.lock().map_err(|err| {
+        RekeyError::GenericError(format!("could not get mouse hook lock: {}", err))
+    });
+}
+
+pub fn dll_set_mouse_hook_enabled(enabled: bool) -> Result<(), RekeyError> {
+    let mut mouse_hook = lock_mouse_hook()?;
+    if let Option::Some(mouse_hook) = mouse_hook.as_mut() {
+        if mouse_hook.installed == enabled {
+            return Result::Ok(());
+        }
+        let ret = if enabled {
+            (mouse_hook.install_mouse)(mouse_hook.dll.0 as u64)
+        } else {
+            (mouse_hook.uninstall_mouse)()
+        };
+        if ret != 0 {
+            return Result::Err(RekeyError::GenericError(format!(
+                "failed to {} mouse hook: {}",
+                if enabled { "install" } else { "uninstall" },
+                ret
+            )));
+        }
+        mouse_hook.installed = enabled;
+        debug!(
+            "mouse hook {}",
+            if enabled { "installed" } else { "uninstalled" }
+        );
+    }
+    return Result::Ok(());
+}
@@ -0,0 +1,209 @@
+use rekey_common::RekeyError;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+// how long input from an unknown device is held for its WM_INPUT to arrive
+pub const UNKNOWN_DEVICE_BUFFER_TIMEOUT: Duration = Duration::from_millis(50);
+// how long a replayed input is expected back from the hooks
+const REPLAY_TIMEOUT: Duration = Duration::from_millis(1000);
+
+struct BufferedInput<T> {
+    input: T,
+    // false for input which is only held to keep it behind earlier buffered input
+    wait_for_device: bool,
+    deadline: Instant,
+}
+
+// input held back from the hooks until its device is known, the hooks are told to skip it and
+// whatever the scripts don't skip is replayed with SendInput, so the window proc never waits
+pub struct InputBuffer<T> {
+    pending: VecDeque<BufferedInput<T>>,
+}
+
+impl<T> Default for InputBuffer<T> {
+    fn default() -> Self {
+        return InputBuffer::new();
+    }
+}
+
+impl<T> InputBuffer<T> {
+    pub fn new() -> Self {
+        return InputBuffer {
+            pending: VecDeque::new(),
+        };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.pending.is_empty();
+    }
+
+    pub fn push(&mut self, input: T, wait_for_device: bool, now: Instant) -> () {
+        self.pending.push_back(BufferedInput {
+            input,
+            wait_for_device,
+            deadline: now + UNKNOWN_DEVICE_BUFFER_TIMEOUT,
+        });
+    }
+
+    // the earliest time pop_ready gives up waiting on a device
+    pub fn next_deadline(&self) -> Option<Instant> {
+        return self
+            .pending
+            .iter()
+            .filter(|buffered| buffered.wait_for_device)
+            .map(|buffered| buffered.deadline)
+            .min();
+    }
+
+    // removes input from the front whose device find_device identified, which didn't need a
+    // device or which waited long enough, in the order it was pushed. find_device returns
+    // whether the device is known now.
+    pub fn pop_ready(
+        &mut self,
+        now: Instant,
+        mut find_device: impl FnMut(&mut T) -> Result<bool, RekeyError>,
+    ) -> Result<Vec<T>, RekeyError> {
+        let mut ready = vec![];
+        while let Option::Some(buffered) = self.pending.front_mut() {
+            let found = find_device(&mut buffered.input)?;
+            if !found && buffered.wait_for_device && now < buffered.deadline {
+                break;
+            }
+            if let Option::Some(buffered) = self.pending.pop_front() {
+                ready.push(buffered.input);
+            }
+        }
+        return Result::Ok(ready);
+    }
+}
+
+// input replayed from the buffer comes back through the hooks as self injected input, it has
+// already been through the scripts so it is passed on without running them again
+pub struct InputReplays<K> {
+    expected: Vec<(K, Instant)>,
+}
+
+impl<K: PartialEq> Default for InputReplays<K> {
+    fn default() -> Self {
+        return InputReplays::new();
+    }
+}
+
+impl<K: PartialEq> InputReplays<K> {
+    pub fn new() -> Self {
+        return InputReplays { expected: vec![] };
+    }
+
+    pub fn add(&mut self, key: K, now: Instant) -> () {
+        self.expected.push((key, now + REPLAY_TIMEOUT));
+    }
+
+    // whether the input is a replay, a replay is only matched once
+    pub fn take(&mut self, key: &K, now: Instant) -> bool {
+        self.expected.retain(|(_, deadline)| now < *deadline);
+        if let Option::Some(i) = self.expected.iter().position(|(k, _)| k == key) {
+            self.expected.remove(i);
+            return true;
+        }
+        return false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (key, device once known)
+    type TestInput = (u16, Option<u16>);
+
+    fn no_devices(_input: &mut TestInput) -> Result<bool, RekeyError> {
+        return Result::Ok(false);
+    }
+
+    #[test]
+    fn input_waits_for_its_device() {
+        let start = Instant::now();
+        let mut buffer: InputBuffer<TestInput> = InputBuffer::new();
+        buffer.push((1, Option::None), true, start);
+        assert!(buffer.pop_ready(start, no_devices).unwrap().is_empty());
+        assert!(!buffer.is_empty());
+
+        let ready = buffer
+            .pop_ready(start, |input| {
+                input.1 = Option::Some(7);
+                return Result::Ok(true);
+            })
+            .unwrap();
+        assert_eq!(ready, vec![(1, Option::Some(7))]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn input_is_released_without_a_device_after_the_timeout() {
+        let start = Instant::now();
+        let mut buffer: InputBuffer<TestInput> = InputBuffer::new();
+        buffer.push((1, Option::None), true, start);
+        assert_eq!(
+            buffer.next_deadline(),
+            Option::Some(start + UNKNOWN_DEVICE_BUFFER_TIMEOUT)
+        );
+
+        let almost = start + UNKNOWN_DEVICE_BUFFER_TIMEOUT - Duration::from_millis(1);
+        assert!(buffer.pop_ready(almost, no_devices).unwrap().is_empty());
+        let ready = buffer
+            .pop_ready(start + UNKNOWN_DEVICE_BUFFER_TIMEOUT, no_devices)
+            .unwrap();
+        assert_eq!(ready, vec![(1, Option::None)]);
+        assert_eq!(buffer.next_deadline(), Option::None);
+    }
+
+    #[test]
+    fn later_input_stays_behind_buffered_input() {
+        let start = Instant::now();
+        let mut buffer: InputBuffer<TestInput> = InputBuffer::new();
+        buffer.push((1, Option::None), true, start);
+        buffer.push((2, Option::None), false, start);
+        buffer.push((3, Option::None), true, start);
+        assert!(buffer.pop_ready(start, no_devices).unwrap().is_empty());
+
+        // only the first key's device is found, the third keeps waiting
+        let ready = buffer
+            .pop_ready(start, |input| {
+                if input.0 == 1 {
+                    input.1 = Option::Some(7);
+                    return Result::Ok(true);
+                }
+                return Result::Ok(false);
+            })
+            .unwrap();
+        assert_eq!(ready, vec![(1, Option::Some(7)), (2, Option::None)]);
+        assert!(!buffer.is_empty());
+
+        let ready = buffer
+            .pop_ready(start + UNKNOWN_DEVICE_BUFFER_TIMEOUT, no_devices)
+            .unwrap();
+        assert_eq!(ready, vec![(3, Option::None)]);
+    }
+
+    #[test]
+    fn replays_are_matched_once() {
+        let start = Instant::now();
+        let mut replays: InputReplays<u16> = InputReplays::new();
+        replays.add(1, start);
+        replays.add(1, start);
+        assert!(!replays.take(&2, start));
+        assert!(replays.take(&1, start));
+        assert!(replays.take(&1, start));
+        assert!(!replays.take(&1, start));
+    }
+
+    #[test]
+    fn replays_expire() {
+        let start = Instant::now();
+        let mut replays: InputReplays<u16> = InputReplays::new();
+        replays.add(1, start);
+        assert!(!replays.take(&1, start + REPLAY_TIMEOUT));
+    }
+}
@@ -14,6 +14,14 @@ struct InputLogItem {
     device: Arc<Device>,
     vkey_code: u16,
     direction: KeyDirection,
+    injected: bool,
+}
+
+// raw input which matched a hook event
+pub struct LoggedInput {
+    pub device: Arc<Device>,
+    // raw input has no device handle for input sent with SendInput
+    pub injected: bool,
 }
 
 lazy_static! {
@@ -24,6 +32,7 @@ pub fn input_log_add_wm_input(
     device: Arc<Device>,
     vkey_code: u16,
     direction: KeyDirection,
+    injected: bool,
 ) -> Result<(), RekeyError> {
     let now = SystemTime::now();
     let mut input_log = INPUT_LOG.lock().map_err(|err| {
@@ -35,6 +44,7 @@ pub fn input_log_add_wm_input(
         device,
         vkey_code,
         direction,
+        injected,
     });
 
     while input_log.len() > MAX_INPUT_LOG_LENGTH {
@@ -44,10 +54,10 @@ pub fn input_log_add_wm_input(
     return Result::Ok(());
 }
 
-pub fn input_log_get_device(
+pub fn input_log_take(
     vkey_code: u16,
     direction: KeyDirection,
-) -> Result<Option<Arc<Device>>, RekeyError> {
+) -> Result<Option<LoggedInput>, RekeyError> {
     let now = SystemTime::now();
     let mut input_log = INPUT_LOG.lock().map_err(|err| {
         RekeyError::GenericError(format!("could not get input log lock: {}", err))
@@ -61,7 +71,10 @@ pub fn input_log_get_device(
                     .map_err(|err| RekeyError::GenericError(format!("time error: {}", err)))?;
                 if delta_t.as_millis() < 1000 {
                     let removed_item = input_log.remove(i);
-                    return Result::Ok(Option::Some(removed_item.device));
+                    return Result::Ok(Option::Some(LoggedInput {
+                        device: removed_item.device,
+                        injected: removed_item.injected,
+                    }));
                 }
             }
         }
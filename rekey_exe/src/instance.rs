@@ -0,0 +1,119 @@
+use std::{thread, time::Duration};
+
+use rekey_common::{debug, RekeyError, WM_USER_INSTANCE_COMMAND};
+use windows::{
+    core::{w, PCWSTR},
+    Win32::{
+        Foundation::{
+            CloseHandle, GetLastError, ERROR_ALREADY_EXISTS, HANDLE, HWND, LPARAM, WPARAM,
+        },
+        System::Threading::CreateMutexW,
+        UI::WindowsAndMessaging::{FindWindowW, PostMessageW},
+    },
+};
+
+// how long a second launch waits for the running instance to create its window
+const FIND_WINDOW_TIMEOUT: Duration = Duration::from_secs(2);
+const FIND_WINDOW_INTERVAL: Duration = Duration::from_millis(100);
+
+// what a second launch asks the running instance to do before quitting
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum InstanceCommand {
+    Reload,
+    OpenLog,
+    Exit,
+}
+
+impl InstanceCommand {
+    // rekey --reload, --open-log or --exit
+    pub fn from_arg(arg: &str) -> Option<Self> {
+        return match arg {
+            "--reload" => Option::Some(InstanceCommand::Reload),
+            "--open-log" => Option::Some(InstanceCommand::OpenLog),
+            "--exit" => Option::Some(InstanceCommand::Exit),
+            _ => Option::None,
+        };
+    }
+
+    pub fn to_wparam(&self) -> WPARAM {
+        return match *self {
+            InstanceCommand::Reload => WPARAM(1),
+            InstanceCommand::OpenLog => WPARAM(2),
+            InstanceCommand::Exit => WPARAM(3),
+        };
+    }
+
+    pub fn from_wparam(wparam: WPARAM) -> Result<Self, RekeyError> {
+        return match wparam.0 {
+            1 => Result::Ok(InstanceCommand::Reload),
+            2 => Result::Ok(InstanceCommand::OpenLog),
+            3 => Result::Ok(InstanceCommand::Exit),
+            _ => Result::Err(RekeyError::GenericError(format!(
+                "invalid instance command {}",
+                wparam.0
+            ))),
+        };
+    }
+}
+
+// held for the lifetime of the running instance, "Local\" limits it to the current session
+pub struct SingleInstance {
+    mutex: HANDLE,
+}
+
+impl SingleInstance {
+    // returns none if rekey is already running
+    pub fn acquire() -> Result<Option<Self>, RekeyError> {
+        unsafe {
+            let mutex = CreateMutexW(Option::None, false, w!("Local\\rekey_single_instance"))
+                .map_err(|err| {
+                    RekeyError::Win32Error("failed to create instance mutex".to_string(), err)
+                })?;
+            if let Result::Err(err) = GetLastError() {
+                if err.code() == ERROR_ALREADY_EXISTS.to_hresult() {
+                    let _ = CloseHandle(mutex);
+                    return Result::Ok(Option::None);
+                }
+            }
+            return Result::Ok(Option::Some(SingleInstance { mutex }));
+        }
+    }
+}
+
+impl Drop for SingleInstance {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = CloseHandle(self.mutex);
+        }
+    }
+}
+
+// posts the command to the running instance's window, which may still be starting up
+pub fn instance_send_command(command: InstanceCommand) -> Result<(), RekeyError> {
+    let mut waited = Duration::ZERO;
+    loop {
+        let hwnd = unsafe { FindWindowW(w!("rekey"), PCWSTR::null()) };
+        if hwnd != HWND(0) {
+            debug!("sending {:?} to running instance", command);
+            unsafe {
+                PostMessageW(
+                    hwnd,
+                    WM_USER_INSTANCE_COMMAND,
+                    command.to_wparam(),
+                    LPARAM(0),
+                )
+                .map_err(|err| {
+                    RekeyError::Win32Error("failed to signal running instance".to_string(), err)
+                })?;
+            }
+            return Result::Ok(());
+        }
+        if waited >= FIND_WINDOW_TIMEOUT {
+            return Result::Err(RekeyError::GenericError(
+                "could not find the running rekey window".to_string(),
+            ));
+        }
+        thread::sleep(FIND_WINDOW_INTERVAL);
+        waited += FIND_WINDOW_INTERVAL;
+    }
+}
@@ -1,2 +1,3 @@
 pub mod console;
-pub mod timer;
\ No newline at end of file
+pub mod module_loader;
+pub mod timer;
@@ -0,0 +1,123 @@
+// based on https://github.com/boa-dev/boa/blob/v0.17.3/boa_engine/src/module/loader.rs
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use boa_engine::{
+    js_string,
+    module::{Module, ModuleLoader, Referrer},
+    Context, JsError, JsNativeError, JsResult, JsString, Source,
+};
+use rekey_common::RekeyError;
+
+// resolves relative import specifiers against the importing module's directory and others
+// against the scripts directory, imports can't reach outside the scripts directory. Each script
+// context gets its own loader since modules can't be shared between contexts.
+pub struct ScriptModuleLoader {
+    root: PathBuf,
+    modules: RefCell<HashMap<PathBuf, Module>>,
+}
+
+impl ScriptModuleLoader {
+    pub fn new(root: &Path) -> Result<Self, RekeyError> {
+        let root = root.canonicalize().map_err(|err| {
+            RekeyError::GenericError(format!(
+                "could not canonicalize scripts dir {}: {}",
+                root.display(),
+                err
+            ))
+        })?;
+        return Result::Ok(Self {
+            root,
+            modules: RefCell::new(HashMap::new()),
+        });
+    }
+
+    pub fn insert(&self, path: PathBuf, module: Module) {
+        self.modules.borrow_mut().insert(path, module);
+    }
+
+    pub fn get(&self, path: &Path) -> Option<Module> {
+        return self.modules.borrow().get(path).cloned();
+    }
+
+    // the path a module was loaded from, None for modules this loader didn't load
+    fn path_of(&self, module: &Module) -> Option<PathBuf> {
+        return self
+            .modules
+            .borrow()
+            .iter()
+            .find(|(_, m)| *m == module)
+            .map(|(path, _)| path.clone());
+    }
+
+    pub fn paths(&self) -> Vec<PathBuf> {
+        return self.modules.borrow().keys().cloned().collect();
+    }
+
+    pub fn clear(&self) {
+        self.modules.borrow_mut().clear();
+    }
+
+    pub fn resolve(&self, referrer: &Referrer, specifier: &str) -> JsResult<PathBuf> {
+        let base = if specifier.starts_with("./") || specifier.starts_with("../") {
+            let referrer_path = match referrer {
+                Referrer::Module(module) => self.path_of(module),
+                _ => Option::None,
+            };
+            referrer_path
+                .as_deref()
+                .and_then(Path::parent)
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| self.root.clone())
+        } else {
+            self.root.clone()
+        };
+        let path = base.join(specifier).canonicalize().map_err(|err| {
+            JsNativeError::typ()
+                .with_message(format!("could not resolve module {}", specifier))
+                .with_cause(JsError::from_opaque(js_string!(err.to_string()).into()))
+        })?;
+        if !path.starts_with(&self.root) {
+            return Result::Err(JsError::from(JsNativeError::typ().with_message(format!(
+                "module {} is outside of the scripts directory",
+                specifier
+            ))));
+        }
+        return Result::Ok(path);
+    }
+}
+
+impl ModuleLoader for ScriptModuleLoader {
+    fn load_imported_module(
+        &self,
+        referrer: Referrer,
+        specifier: JsString,
+        finish_load: Box<dyn FnOnce(JsResult<Module>, &mut Context<'_>)>,
+        context: &mut Context<'_>,
+    ) {
+        let result = (|| -> JsResult<Module> {
+            let specifier = specifier
+                .to_std_string()
+                .map_err(|err| JsNativeError::typ().with_message(err.to_string()))?;
+            let path = self.resolve(&referrer, &specifier)?;
+            if let Option::Some(module) = self.get(&path) {
+                return Result::Ok(module);
+            }
+
+            let source = Source::from_filepath(&path).map_err(|err| {
+                JsNativeError::typ()
+                    .with_message(format!("could not open module {}", path.display()))
+                    .with_cause(JsError::from_opaque(js_string!(err.to_string()).into()))
+            })?;
+            let module = Module::parse(source, Option::None, context)?;
+            self.insert(path, module.clone());
+            return Result::Ok(module);
+        })();
+
+        finish_load(result, context);
+    }
+}
@@ -0,0 +1,77 @@
+// helpers evaluated in every script context before the script itself
+
+async function sendKeySequence(keyExpressions, delayMillis) {
+  for (let i = 0; i < keyExpressions.length; i++) {
+    if (i > 0 && delayMillis) {
+      await sleep(delayMillis);
+    }
+    sendKey(keyExpressions[i]);
+  }
+}
+
+async function sendKeys(macro) {
+  const steps = typeof macro === 'string' ? parseKeyMacro(macro) : macro;
+  if (!Array.isArray(steps)) {
+    throw new Error('invalid arguments, expected sendKeys(macro: string | KeyMacroStep[])');
+  }
+  // let the caller, usually a key handler, return before any keys are sent
+  await sleep(0);
+  for (const step of steps) {
+    if (typeof step === 'string') {
+      sendKey(step);
+    } else if (typeof step.delay === 'number') {
+      await sleep(step.delay);
+    } else if (typeof step.text === 'string') {
+      sendText(step.text);
+    } else if (typeof step.key === 'string') {
+      if (step.direction === undefined) {
+        sendKey(step.key);
+      } else {
+        sendKey(step.key, step.direction);
+      }
+    } else {
+      throw new Error(`invalid sendKeys step ${JSON.stringify(step)}`);
+    }
+  }
+}
+
+// "{ctrl down}c{ctrl up}{wait 50}{enter}" into steps for sendKeys, text outside of braces is
+// typed as is and "{{}" and "{}}" type a literal brace
+function parseKeyMacro(macro) {
+  const steps = [];
+  let i = 0;
+  while (i < macro.length) {
+    const start = macro.indexOf('{', i);
+    if (start < 0) {
+      steps.push({ text: macro.substring(i) });
+      break;
+    }
+    if (start > i) {
+      steps.push({ text: macro.substring(i, start) });
+    }
+    // allow "{}}" by searching for the closing brace after the first character
+    const end = macro.indexOf('}', start + 2);
+    if (end < 0) {
+      throw new Error(`unterminated '{' at ${start} in key macro`);
+    }
+    const command = macro.substring(start + 1, end).trim();
+    const parts = command.split(/\s+/);
+    if (command === '{' || command === '}') {
+      steps.push({ text: command });
+    } else if (parts.length === 2 && (parts[0] === 'wait' || parts[0] === 'delay')) {
+      const delay = Number(parts[1]);
+      if (isNaN(delay)) {
+        throw new Error(`invalid delay '${command}' in key macro`);
+      }
+      steps.push({ delay });
+    } else if (parts.length === 2 && (parts[1] === 'down' || parts[1] === 'up')) {
+      steps.push({ key: parts[0], direction: parts[1] });
+    } else if (parts.length === 1) {
+      steps.push(parts[0]);
+    } else {
+      throw new Error(`invalid command '${command}' in key macro`);
+    }
+    i = end + 1;
+  }
+  return steps;
+}
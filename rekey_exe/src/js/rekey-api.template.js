@@ -10,21 +10,143 @@
  * @name rekeyRegister
  * @param {RegisterOptions} options Options to filter keys
  * @param {keyCallback} callback Callback to be called on each key event
+ * @returns {RegisterHandle} Handle which can be used to unregister the callback
  */
 function rekeyRegister(options, callback) { }
 
+/**
+ * Register a callback for each mouse button and wheel event. Mouse moves are not reported. Mouse input is only
+ * intercepted while at least one script has a mouse handler registered.
+ * 
+ * @global
+ * @function
+ * @name rekeyRegisterMouse
+ * @param {MouseRegisterOptions} options Options to filter mouse events
+ * @param {mouseCallback} callback Callback to be called on each mouse event
+ * @returns {RegisterHandle} Handle which can be used to unregister the callback with rekeyUnregister
+ */
+function rekeyRegisterMouse(options, callback) { }
+
+/**
+ * Register a callback for the input reports of HID devices with the given usage, e.g. media keys on the consumer
+ * control usage page or vendor defined pages of macro pads and jog wheels. The reports are only observed, they can't
+ * be filtered. Example: rekeyRegisterHid({ usagePage: 0x0C, usage: 0x01 }, (event) => console.log(event.data))
+ * 
+ * @global
+ * @function
+ * @name rekeyRegisterHid
+ * @param {HidRegisterOptions} options The usage to register and options to filter devices
+ * @param {hidCallback} callback Callback to be called for each input report
+ * @returns {RegisterHandle} Handle which can be used to unregister the callback with rekeyUnregister
+ */
+function rekeyRegisterHid(options, callback) { }
+
+/**
+ * Register a callback for keyboards, mice and HID devices being attached or detached. When the callback is registered,
+ * including after the script is reloaded, an arrival is also sent for each device which is already attached. Example:
+ * rekeyOnDeviceChange({ deviceFilter: { vendorId: 0x046D } }, (event) => console.log(event.type, event.device.productName))
+ * 
+ * @global
+ * @function
+ * @name rekeyOnDeviceChange
+ * @param {DeviceChangeOptions} [options] Options to filter devices, can be left out
+ * @param {deviceChangeCallback} callback Callback to be called for each device change
+ * @returns {RegisterHandle} Handle which can be used to unregister the callback with rekeyUnregister
+ */
+function rekeyOnDeviceChange(options, callback) { }
+
+/**
+ * Unregister a callback previously registered with rekeyRegister.
+ * 
+ * @global
+ * @function
+ * @name rekeyUnregister
+ * @param {number|RegisterHandle} id The id of the handler or the handle returned from rekeyRegister
+ * @returns {boolean} true, if a handler was unregistered
+ */
+function rekeyUnregister(id) { }
+
+/**
+ * Set the unknownDevice policy used by handlers in this script which don't specify one, overriding unknown_device
+ * from rekey.ini for this script only.
+ * 
+ * @global
+ * @function
+ * @name rekeySetDefaultUnknownDevice
+ * @param {UnknownDevicePolicy} policy The default policy
+ */
+function rekeySetDefaultUnknownDevice(policy) { }
+
 /**
  * Send a key event
  * 
  * @global
  * @function
  * @name sendKey
- * @param {string} keyExpression The key expression to send. Examples: 'ctrl+esc', 'a', 'alt+f4'
- * @param {'up'|'down'} [direction] If specified only send the given key direction, otherwise send both down
- *                                  and up events.
+ * @param {string} keyExpression The key expression to send. Examples: 'ctrl+esc', 'a', 'alt+f4', 'ctrl++'
+ * @param {'up'|'down'|'both'} [direction] If 'up' or 'down' only send the given key direction, otherwise send
+ *                                         both down and up events.
  */
 function sendKey(keyExpression, direction) { }
 
+/**
+ * Type a string. Characters are sent as unicode input so caps lock and the keyboard layout don't change them, line
+ * breaks are sent as enter and tabs as tab.
+ * 
+ * @global
+ * @function
+ * @name sendText
+ * @param {string} text The text to type, e.g. 'Grüße €5'
+ */
+function sendText(text) { }
+
+/**
+ * Send mouse input. Example: sendMouse({ x: 100, y: 200, absolute: true, button: 'left' }) clicks at 100,200
+ * 
+ * @global
+ * @function
+ * @name sendMouse
+ * @param {SendMouseOptions} options What to send, the move is sent first then the button then the wheels
+ */
+function sendMouse(options) { }
+
+/**
+ * Send a sequence of key expressions, one after the other.
+ * 
+ * @global
+ * @function
+ * @name sendKeySequence
+ * @param {string[]} keyExpressions The key expressions to send, see sendKey
+ * @param {number} [delayMillis] Time to wait between each key expression
+ * @returns {Promise<void>} Resolves once all keys have been sent
+ */
+async function sendKeySequence(keyExpressions, delayMillis) { }
+
+/**
+ * Send a macro of keys, text and delays. The keys are sent from the script thread after the calling handler returns.
+ * A macro string is text to type with commands in braces: '{ctrl down}', '{ctrl up}', '{wait 50}', a key expression
+ * like '{enter}' or '{ctrl+v}', and '{{}' or '{}}' for a literal brace.
+ * Example: sendKeys(['ctrl+c', { delay: 50 }, 'alt+tab', 'ctrl+v']) or sendKeys('{ctrl down}c{ctrl up}{wait 50}')
+ * 
+ * @global
+ * @function
+ * @name sendKeys
+ * @param {string|KeyMacroStep[]} macro The macro to send
+ * @returns {Promise<void>} Resolves once the whole macro has been sent
+ */
+async function sendKeys(macro) { }
+
+/**
+ * Wait for the given amount of time.
+ * 
+ * @global
+ * @function
+ * @name sleep
+ * @param {number} timeMillis Time to wait in milliseconds
+ * @returns {Promise<void>} Resolves after the time has passed
+ */
+function sleep(timeMillis) { }
+
 /**
  * Get the state of a key
  * 
@@ -36,6 +158,16 @@ function sendKey(keyExpression, direction) { }
  */
 function getKeyState(vKeyCode) { }
 
+/**
+ * Get the keyboards, mice and HID devices which are currently attached.
+ * 
+ * @global
+ * @function
+ * @name getDevices
+ * @returns {DeviceInfo[]}
+ */
+function getDevices() { }
+
 /**
  * Data passed to the rekeyRegister callback.
  * 
@@ -45,20 +177,205 @@ function getKeyState(vKeyCode) { }
  * @property {string} [ch] String representation of the key.
  * @property {'up'|'down'} direction The direction of the key event
  * @property {string} [deviceName] The device name from which the event was generated.
+ * @property {number} scanCode The hardware scan code of the key
+ * @property {boolean} extended true, if the key is an extended key, e.g. the right ctrl or the arrow keys
+ * @property {boolean} isRepeat true, if the key was already down, i.e. the event is an auto repeat
+ * @property {boolean} injected true, if the event was sent by software rather than a device
+ * @property {boolean} selfInjected true, if the event was sent by rekey, e.g. with sendKey. See selfInjected of
+ *                                  RegisterOptions.
+ * @property {number} timestamp The time the event was received in milliseconds since the epoch, like Date.now()
+ * @property {KeyModifiers} modifiers The state of the modifier keys when the event was received
+ */
+
+/**
+ * Data passed to the rekeyRegisterMouse callback.
+ * 
+ * @typedef {Object} MouseEvent
+ * @property {'left'|'right'|'middle'|'x1'|'x2'|'wheel'|'hwheel'} button The button or wheel of the event
+ * @property {'up'|'down'} direction The direction of the button event, always 'down' for wheels
+ * @property {number} [wheel] For wheel events the number of notches scrolled, positive is up or right. Can be a
+ *                                  fraction for high resolution wheels.
+ * @property {string} [deviceName] The device name from which the event was generated.
+ * @property {boolean} injected true, if the event was sent by software rather than a device
+ * @property {boolean} selfInjected true, if the event was sent by rekey, e.g. with sendKey. See selfInjected of
+ *                                  RegisterOptions.
+ * @property {number} timestamp The time the event was received in milliseconds since the epoch, like Date.now()
+ * @property {KeyModifiers} modifiers The state of the modifier keys when the event was received
+ */
+
+/**
+ * Data passed to the rekeyRegisterHid callback.
+ * 
+ * @typedef {Object} HidEvent
+ * @property {number} usagePage The usage page of the device
+ * @property {number} usage The usage of the device
+ * @property {number[]} data The bytes of the input report, the first byte is the report id if the device uses them
+ * @property {string} deviceName The device name from which the report was received
+ * @property {number} timestamp The time the report was received in milliseconds since the epoch, like Date.now()
+ */
+
+/**
+ * A device returned by getDevices and passed to the rekeyOnDeviceChange callback.
+ * 
+ * @typedef {Object} DeviceInfo
+ * @property {number} handle The raw input handle of the device, only valid while the device is attached
+ * @property {string} path The device name, the same as the deviceName of key events
+ * @property {'keyboard'|'mouse'|'hid'} [type] The kind of device
+ * @property {number} [vendorId] The USB vendor id found in the device name
+ * @property {number} [productId] The USB product id found in the device name
+ * @property {number} [interface] The USB interface number found in the device name
+ * @property {string} [productName] The product name the device reports, e.g. 'USB Receiver'
+ * @property {number} [usagePage] The usage page of HID devices
+ * @property {number} [usage] The usage of HID devices
+ */
+
+/**
+ * Data passed to the rekeyOnDeviceChange callback.
+ * 
+ * @typedef {Object} DeviceChangeEvent
+ * @property {'arrival'|'removal'} type Whether the device was attached or detached
+ * @property {DeviceInfo} device The device
+ */
+
+/**
+ * @typedef {Object} SendMouseOptions
+ * @property {number} [x] Move the mouse horizontally by this many pixels, or to this screen position if absolute
+ * @property {number} [y] Move the mouse vertically by this many pixels, or to this screen position if absolute
+ * @property {boolean} [absolute] If true x and y are a screen position rather than relative to the current position
+ * @property {'left'|'right'|'middle'|'x1'|'x2'} [button] The button to press
+ * @property {'up'|'down'} [direction] If specified only send the given button direction, otherwise click
+ * @property {number} [wheel] Scroll the wheel this many notches, positive scrolls up
+ * @property {number} [hwheel] Scroll the horizontal wheel this many notches, positive scrolls right
+ */
+
+/**
+ * A step of a sendKeys macro. A string is a key expression sent with sendKey, { delay } waits, { text } is typed with
+ * sendText and { key, direction } sends a single key direction, e.g. { key: 'ctrl', direction: 'down' }.
+ * 
+ * @typedef {string|{delay: number}|{text: string}|{key: string, direction?: 'up'|'down'}} KeyMacroStep
+ */
+
+/**
+ * @typedef {Object} KeyModifiers
+ * @property {boolean} shift Either shift key is down
+ * @property {boolean} ctrl Either ctrl key is down
+ * @property {boolean} alt Either alt key is down
+ * @property {boolean} win Either windows key is down
+ * @property {boolean} leftShift
+ * @property {boolean} rightShift
+ * @property {boolean} leftCtrl
+ * @property {boolean} rightCtrl
+ * @property {boolean} leftAlt
+ * @property {boolean} rightAlt
+ * @property {boolean} leftWin
+ * @property {boolean} rightWin
  */
 
 /**
  * @typedef {Object} RegisterOptions
- * @param {string} [deviceFilter] If present no device filtering will be done. If a string is passed and the
- *                                  device name contains that string the callback will be called.
- * @param {'*'} [keyFilter] Currently must be '*' and no key filtering will be done.
+ * @param {'*'|string|RegExp|DeviceIdFilter|Array<string|RegExp|DeviceIdFilter>} [deviceFilter] If not present or '*'
+ *                                  no device filtering will be done. If a string is passed and the device name contains
+ *                                  that string the callback will be called. If a RegExp is passed it is tested against
+ *                                  the device name. An array matches if any of its items match.
+ * @param {'*'|string|number|Array<string|number>} [keyFilter] If not present or '*' no key filtering will be done.
+ *                                  Otherwise a key name (e.g. 'numpad1'), a virtual key code (e.g. VK_ESC), a key
+ *                                  expression (e.g. 'ctrl+shift+k' or 'ctrl++') or an array of those. Key expressions only match
+ *                                  when exactly the given modifiers (shift, ctrl, alt, windows) are held.
+ * @param {UnknownDevicePolicy} [unknownDevice] What to do when the device of a key event could not be identified and
+ *                                  a deviceFilter is given. Defaults to the value set by rekeySetDefaultUnknownDevice,
+ *                                  then unknown_device from rekey.ini.
+ * @param {number} [priority] Handlers with a higher priority are called first, defaults to 0. Handlers with the same
+ *                                  priority are called in script file name order then registration order.
  * @param {boolean} [intercept] If true the result of the callback can effect if the key gets passed on to the system.
+ * @param {boolean} [selfInjected] If true the callback is also called for keys sent by rekey, e.g. with sendKey.
+ *                                  Defaults to false so that mapping keys to each other doesn't loop.
+ */
+
+/**
+ * @typedef {Object} MouseRegisterOptions
+ * @param {'*'|string|RegExp|DeviceIdFilter|Array<string|RegExp|DeviceIdFilter>} [deviceFilter] Same as the
+ *                                  deviceFilter of RegisterOptions
+ * @param {'*'|'left'|'right'|'middle'|'x1'|'x2'|'wheel'|'hwheel'|Array<string>} [buttonFilter] If not present or '*'
+ *                                  all buttons and wheels are passed to the callback.
+ * @param {UnknownDevicePolicy} [unknownDevice] Same as the unknownDevice of RegisterOptions
+ * @param {number} [priority] Same as the priority of RegisterOptions
+ * @param {boolean} [intercept] If true the result of the callback can effect if the mouse event gets passed on to the
+ *                                  system.
+ * @param {boolean} [selfInjected] Same as the selfInjected of RegisterOptions
+ */
+
+/**
+ * @typedef {Object} HidRegisterOptions
+ * @param {number} usagePage The HID usage page, e.g. 0x0C for consumer control or 0xFF00 and above for vendor defined
+ * @param {number} usage The HID usage, e.g. 0x01 for consumer control
+ * @param {'*'|string|RegExp|DeviceIdFilter|Array<string|RegExp|DeviceIdFilter>} [deviceFilter] Same as the
+ *                                  deviceFilter of RegisterOptions
+ * @param {number} [priority] Same as the priority of RegisterOptions
+ */
+
+/**
+ * @typedef {Object} DeviceChangeOptions
+ * @param {'*'|string|RegExp|DeviceIdFilter|Array<string|RegExp|DeviceIdFilter>} [deviceFilter] Same as the
+ *                                  deviceFilter of RegisterOptions
+ * @param {number} [priority] Same as the priority of RegisterOptions
+ */
+
+/**
+ * What to do with a handler that has a deviceFilter when the device of a key event could not be identified.
+ * 'match' calls the handler as if the device matched, 'skip' does not call the handler, 'buffer' waits briefly for
+ * the device to be identified and does not call the handler if it still is not. With the ll-hook backend devices are
+ * rarely known and 'buffer' acts like 'skip'.
+ * 
+ * @typedef {'match'|'skip'|'buffer'} UnknownDevicePolicy
+ */
+
+/**
+ * Matches devices by the ids found in the device name. Ids not given are not checked.
+ * 
+ * @typedef {Object} DeviceIdFilter
+ * @property {number|string} [vendorId] The USB vendor id, e.g. 0x046D or '046D'
+ * @property {number|string} [productId] The USB product id, e.g. 0x026C or '026C'
+ * @property {number|string} [interface] The USB interface number, e.g. 0 for MI_00
+ */
+
+/**
+ * The results from rekeyRegister
+ * 
+ * @typedef {Object} RegisterHandle
+ * @property {number} id Id of the registered handler
+ * @property {function(): boolean} unregister Unregister the handler, returns true if the handler was still registered
  */
 
 /**
  * @callback keyCallback
  * @param {KeyEvent} event Data about the key press
- * @returns {boolean} true, if the keyboard event should be filtered. false, if the keyboard event should not be filterd.
+ * @returns {boolean|KeyCallbackResult} true, if the keyboard event should be filtered. false, if the keyboard event
+ *                                       should not be filterd. Async callbacks cannot filter the keyboard event
+ *                                       since the result is needed before the promise resolves.
+ */
+
+/**
+ * @callback mouseCallback
+ * @param {MouseEvent} event Data about the mouse event
+ * @returns {boolean|KeyCallbackResult} true, if the mouse event should be filtered. Same as keyCallback.
+ */
+
+/**
+ * @callback hidCallback
+ * @param {HidEvent} event The input report
+ */
+
+/**
+ * @callback deviceChangeCallback
+ * @param {DeviceChangeEvent} event The device which was attached or detached
+ */
+
+/**
+ * The result of a keyCallback when more control is needed than returning a boolean.
+ * 
+ * @typedef {Object} KeyCallbackResult
+ * @property {boolean} [skip] true, if the keyboard event should be filtered
+ * @property {boolean} [stopPropagation] true, if lower priority handlers should not be called for this event
  */
 
 /**
@@ -1,141 +1,227 @@
 use std::{
-    cmp,
-    ops::Add,
-    sync::{
-        atomic::{AtomicU16, Ordering},
-        Arc, Mutex,
-    },
-    time::{Duration, SystemTime},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
-use boa_engine::{Context, JsError, JsNativeError, JsObject, JsValue, NativeFunction};
-use rekey_common::{debug, RekeyError};
+use boa_engine::{
+    object::builtins::JsPromise, Context, JsError, JsNativeError, JsObject, JsValue, NativeFunction,
+};
+use rekey_common::{clock::Clock, debug, timers::TimerQueue, RekeyError};
 
 use crate::scripts::Script;
 
-static NEXT_ID: AtomicU16 = AtomicU16::new(1);
+pub type ScriptTimers = Arc<Mutex<TimerQueue<TimerCallback>>>;
 
-pub struct Timer {
-    id: i32,
-    time: Duration,
+// what a script timer calls when it is due
+#[derive(Clone)]
+pub struct TimerCallback {
     callback: JsObject,
+    args: Vec<JsValue>,
 }
 
+pub struct Timer {}
+
 impl Timer {
     pub fn init(
         context: &mut Context<'_>,
-        timers: &Arc<Mutex<Vec<Timer>>>,
+        timers: &ScriptTimers,
+        clock: &Arc<dyn Clock>,
+    ) -> Result<(), RekeyError> {
+        Timer::register_set(context, timers, clock, "setTimeout", false)?;
+        Timer::register_set(context, timers, clock, "setInterval", true)?;
+        Timer::register_clear(context, timers, "clearTimeout")?;
+        // timeouts and intervals share the same ids so either clear function can be used
+        Timer::register_clear(context, timers, "clearInterval")?;
+        Timer::register_sleep(context, timers, clock)?;
+        return Result::Ok(());
+    }
+
+    fn register_sleep(
+        context: &mut Context<'_>,
+        timers: &ScriptTimers,
+        clock: &Arc<dyn Clock>,
+    ) -> Result<(), RekeyError> {
+        let sleep_timers = Arc::clone(timers);
+        let sleep_clock = Arc::clone(clock);
+        context
+            .register_global_callable("sleep", 0, unsafe {
+                NativeFunction::from_closure(move |this, args, context| {
+                    return handle_sleep(
+                        this,
+                        args,
+                        context,
+                        sleep_clock.now(),
+                        Arc::clone(&sleep_timers),
+                    );
+                })
+            })
+            .map_err(|err| {
+                RekeyError::GenericError(format!("failed to register sleep: {}", err))
+            })?;
+        return Result::Ok(());
+    }
+
+    fn register_set(
+        context: &mut Context<'_>,
+        timers: &ScriptTimers,
+        clock: &Arc<dyn Clock>,
+        name: &'static str,
+        repeat: bool,
     ) -> Result<(), RekeyError> {
-        let set_timeout_timers = Arc::clone(timers);
+        let set_timers = Arc::clone(timers);
+        let set_clock = Arc::clone(clock);
         context
-            .register_global_callable("setTimeout", 0, unsafe {
+            .register_global_callable(name, 0, unsafe {
                 NativeFunction::from_closure(move |this, args, context| {
                     return handle_set_timeout(
                         this,
                         args,
                         context,
-                        Arc::clone(&set_timeout_timers),
+                        name,
+                        repeat,
+                        set_clock.now(),
+                        Arc::clone(&set_timers),
                     );
                 })
             })
             .map_err(|err| {
-                RekeyError::GenericError(format!("failed to register rekeyRegister: {}", err))
+                RekeyError::GenericError(format!("failed to register {}: {}", name, err))
             })?;
+        return Result::Ok(());
+    }
 
-        let clear_timeout_timers = Arc::clone(timers);
+    fn register_clear(
+        context: &mut Context<'_>,
+        timers: &ScriptTimers,
+        name: &'static str,
+    ) -> Result<(), RekeyError> {
+        let clear_timers = Arc::clone(timers);
         context
-            .register_global_callable("clearTimeout", 0, unsafe {
+            .register_global_callable(name, 0, unsafe {
                 NativeFunction::from_closure(move |this, args, context| {
                     return handle_clear_timeout(
                         this,
                         args,
                         context,
-                        Arc::clone(&clear_timeout_timers),
+                        name,
+                        Arc::clone(&clear_timers),
                     );
                 })
             })
             .map_err(|err| {
-                RekeyError::GenericError(format!("failed to register rekeyRegister: {}", err))
+                RekeyError::GenericError(format!("failed to register {}: {}", name, err))
             })?;
-
         return Result::Ok(());
     }
 
-    pub fn get_nearest_duration(scripts: &Vec<Script<'_>>) -> Result<Option<Duration>, RekeyError> {
-        let mut results = Option::None;
+    pub fn get_nearest_duration(
+        scripts: &Vec<Script<'_>>,
+        clock: &dyn Clock,
+    ) -> Result<Option<Duration>, RekeyError> {
+        let mut results: Option<Instant> = Option::None;
         for script in scripts {
             let timers = script.timers.lock().map_err(|err| {
                 RekeyError::GenericError(format!("could not get timers lock: {}", err))
             })?;
-            for timer in timers.iter() {
-                if results.is_none() {
-                    results = Option::Some(timer.time);
-                } else if let Option::Some(d) = results {
-                    if timer.time.lt(&d) {
-                        results = Option::Some(timer.time);
-                    }
-                }
+            if let Option::Some(time) = timers.next_time() {
+                results = Option::Some(results.map_or(time, |nearest| nearest.min(time)));
             }
         }
 
-        if let Option::Some(d) = results {
-            let now = SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .map_err(|err| RekeyError::GenericError(format!("failed to get now: {}", err)))?
-                .as_millis();
-            let d = d.as_millis();
-            let dt = cmp::max(0, d - now) as u64;
-            return Result::Ok(Option::Some(Duration::from_millis(dt)));
-        } else {
-            return Result::Ok(Option::None);
-        }
+        return Result::Ok(results.map(|time| clock.duration_until(time)));
     }
 
-    pub fn run_timers(scripts: &Vec<Script<'_>>) -> Result<(), RekeyError> {
-        let now = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .map_err(|err| RekeyError::GenericError(format!("failed to get now: {}", err)))?;
+    pub fn run_timers(scripts: &Vec<Script<'_>>, clock: &dyn Clock) -> Result<(), RekeyError> {
+        let now = clock.now();
 
         for script in scripts {
-            let mut timers = script.timers.lock().map_err(|err| {
-                RekeyError::GenericError(format!("could not get timers lock: {}", err))
-            })?;
-            timers.retain(|timer| {
-                if now.ge(&timer.time) {
-                    run_timer(&script, &timer)
-                        .unwrap_or_else(|err| debug!("failed to run timeout: {}", err));
-                    return false;
+            if script.is_disabled() {
+                continue;
+            }
+            // collect the due timers and release the lock so callbacks can set and clear timers
+            let due_ids = script
+                .timers
+                .lock()
+                .map_err(|err| {
+                    RekeyError::GenericError(format!("could not get timers lock: {}", err))
+                })?
+                .due_ids(now);
+
+            for id in due_ids {
+                // an earlier callback may have cleared this timer
+                let due_timer = script
+                    .timers
+                    .lock()
+                    .map_err(|err| {
+                        RekeyError::GenericError(format!("could not get timers lock: {}", err))
+                    })?
+                    .take_due(id, now);
+                if let Option::Some(timer) = due_timer {
+                    run_timer(&script, &timer.callback, &timer.args)
+                        .unwrap_or_else(|err| debug!("failed to run timer: {}", err));
                 }
-                return true;
-            });
+            }
         }
         return Result::Ok(());
     }
 }
 
-fn run_timer(script: &Script<'_>, timer: &Timer) -> Result<(), RekeyError> {
+fn run_timer(script: &Script<'_>, callback: &JsObject, args: &[JsValue]) -> Result<(), RekeyError> {
     let mut context = script
         .context
         .lock()
         .map_err(|err| RekeyError::GenericError(format!("failed to lock context: {}", err)))?;
-    let args: [JsValue; 0] = [];
     let this = JsValue::Undefined;
 
-    timer
-        .callback
-        .call(&this, &args, &mut context)
-        .map_err(|err| RekeyError::GenericError(format!("{}", err)))?;
+    let result = callback
+        .call(&this, args, &mut context)
+        .map_err(|err| RekeyError::GenericError(format!("{}", err)));
+    // run promise jobs even on error, other callbacks may have queued jobs
+    context.run_jobs();
+    result?;
 
     return Result::Ok(());
 }
 
+fn handle_sleep(
+    _this: &JsValue,
+    args: &[JsValue],
+    context: &mut Context<'_>,
+    now: Instant,
+    timers: ScriptTimers,
+) -> Result<JsValue, JsError> {
+    if args.len() != 1 || !args.get(0).unwrap().is_number() {
+        return Result::Err(JsError::from(
+            JsNativeError::error()
+                .with_message("invalid arguments, expected sleep(timeMillis: number)"),
+        ));
+    }
+    let ms = args.get(0).unwrap().as_number().unwrap().max(0.0);
+
+    let (promise, resolvers) = JsPromise::new_pending(context);
+    let mut timers = timers.lock().map_err(|err| {
+        JsNativeError::error().with_message(format!("could not get timers lock: {}", err))
+    })?;
+    timers.add(
+        now,
+        Duration::from_millis(ms as u64),
+        false,
+        TimerCallback {
+            callback: resolvers.resolve.into(),
+            args: vec![],
+        },
+    );
+    return Result::Ok(JsValue::from(promise));
+}
+
 fn handle_clear_timeout(
     this: &JsValue,
     args: &[JsValue],
     context: &mut Context<'_>,
-    timers: Arc<Mutex<Vec<Timer>>>,
+    name: &str,
+    timers: ScriptTimers,
 ) -> Result<JsValue, JsError> {
-    match _handle_clear_timeout(this, args, context, timers) {
+    match _handle_clear_timeout(this, args, context, name, timers) {
         Result::Ok(()) => {
             return Result::Ok(JsValue::undefined());
         }
@@ -151,7 +237,8 @@ fn _handle_clear_timeout(
     _this: &JsValue,
     args: &[JsValue],
     _context: &mut Context<'_>,
-    timers: Arc<Mutex<Vec<Timer>>>,
+    name: &str,
+    timers: ScriptTimers,
 ) -> Result<(), RekeyError> {
     if args.len() == 1 {
         let id = args.get(0).unwrap();
@@ -161,16 +248,18 @@ fn _handle_clear_timeout(
             })?;
 
             let id = id.as_number().unwrap() as i32;
-            timers.retain(|timer| timer.id != id);
+            timers.remove(id);
             return Result::Ok(());
         } else {
-            return Result::Err(RekeyError::GenericError(
-                "invalid arguments, expected clearTimeout(timer: number)".to_string(),
-            ));
+            return Result::Err(RekeyError::GenericError(format!(
+                "invalid arguments, expected {}(timer: number)",
+                name
+            )));
         }
     } else {
         return Result::Err(RekeyError::GenericError(format!(
-            "invalid arguments for clearTimeout, expected 1 found {}",
+            "invalid arguments for {}, expected 1 found {}",
+            name,
             args.len()
         )));
     }
@@ -180,15 +269,17 @@ fn handle_set_timeout(
     this: &JsValue,
     args: &[JsValue],
     context: &mut Context<'_>,
-    timers: Arc<Mutex<Vec<Timer>>>,
+    name: &str,
+    repeat: bool,
+    now: Instant,
+    timers: ScriptTimers,
 ) -> Result<JsValue, JsError> {
-    match _handle_set_timeout(this, args, context) {
-        Result::Ok(timer) => {
+    match _handle_set_timeout(this, args, context, name) {
+        Result::Ok((delay, callback)) => {
             let mut timers = timers.lock().map_err(|err| {
                 JsNativeError::error().with_message(format!("could not get timers lock: {}", err))
             })?;
-            let id = timer.id;
-            timers.push(timer);
+            let id = timers.add(now, delay, repeat, callback);
             return Result::Ok(JsValue::Integer(id));
         }
         Result::Err(err) => {
@@ -202,34 +293,44 @@ fn handle_set_timeout(
 fn _handle_set_timeout(
     _this: &JsValue,
     args: &[JsValue],
-    _context: &mut Context<'_>,
-) -> Result<Timer, RekeyError> {
-    if args.len() == 2 {
+    context: &mut Context<'_>,
+    name: &str,
+) -> Result<(Duration, TimerCallback), RekeyError> {
+    if args.len() >= 1 {
         let callback = args.get(0).unwrap();
-        let ms = args.get(1).unwrap();
 
-        if callback.is_callable() && ms.is_number() {
-            let ms = ms.as_number().unwrap();
+        if callback.is_callable() {
+            // like browsers the delay is converted to a number, NaN and negative delays are 0 and
+            // the queue clamps long ones to MAX_DELAY
+            let ms = args
+                .get(1)
+                .cloned()
+                .unwrap_or(JsValue::Integer(0))
+                .to_number(context)
+                .map_err(|err| {
+                    RekeyError::GenericError(format!("invalid delay for {}: {}", name, err))
+                })?;
+            let ms = ms.max(0.0);
             let callback = callback.as_callable().unwrap();
-            let now = SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .map_err(|err| RekeyError::GenericError(format!("failed to get now: {}", err)))?;
-            let mut id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
-            // javascript treats 0 as false so lets avoid that
-            if id == 0 {
-                id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
-            }
-            return Result::Ok(Timer {
-                id: id as i32,
-                time: now.add(Duration::from_millis(ms as u64)),
-                callback: callback.clone(),
-            });
+            // like browsers, any arguments after the delay are passed to the callback
+            let callback_args: Vec<JsValue> = args.iter().skip(2).cloned().collect();
+            return Result::Ok((
+                Duration::from_millis(ms as u64),
+                TimerCallback {
+                    callback: callback.clone(),
+                    args: callback_args,
+                },
+            ));
         } else {
-            return Result::Err(RekeyError::GenericError("invalid arguments, expected setTimeout(callback: () => unknown, timeMillis: number)".to_string()));
+            return Result::Err(RekeyError::GenericError(format!(
+                "invalid arguments, expected {}(callback: (...args) => unknown, timeMillis?: number, ...args)",
+                name
+            )));
         }
     } else {
         return Result::Err(RekeyError::GenericError(format!(
-            "invalid arguments for setTimeout, expected 2 found {}",
+            "invalid arguments for {}, expected at least 1 found {}",
+            name,
             args.len()
         )));
     }
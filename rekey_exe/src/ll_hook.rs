@@ -0,0 +1,189 @@
+use std::{
+    sync::{
+        atomic::{AtomicIsize, Ordering},
+        Mutex,
+    },
+    time::SystemTime,
+};
+
+use lazy_static::lazy_static;
+use rekey_common::{debug, KeyDirection, RekeyError, REKEY_INPUT_EXTRA_INFO};
+use windows::Win32::{
+    Foundation::{HWND, LPARAM, LRESULT, WPARAM},
+    UI::WindowsAndMessaging::{
+        CallNextHookEx, SetWindowsHookExW, UnhookWindowsHookEx, HC_ACTION, HHOOK, KBDLLHOOKSTRUCT,
+        LLKHF_EXTENDED, LLKHF_INJECTED, LLKHF_UP, LLMHF_INJECTED, MSLLHOOKSTRUCT, WH_KEYBOARD_LL,
+        WH_MOUSE_LL,
+    },
+};
+
+use crate::{
+    scripts::KeyDetails,
+    window::{get_hinstance, handle_key_input, handle_mouse_input},
+    SkipInput,
+};
+
+// the low level hooks run in rekey_exe on the thread which installed them, so unlike the dll
+// hook they also see elevated windows and processes of the other bitness
+pub struct LowLevelHook {}
+
+// the window used to look up devices, 0 while the low level hook isn't installed
+static LL_HOOK_WINDOW: AtomicIsize = AtomicIsize::new(0);
+
+lazy_static! {
+    static ref LL_KEYBOARD_HHOOK: Mutex<Option<HHOOK>> = Mutex::new(Option::None);
+    static ref LL_MOUSE_HHOOK: Mutex<Option<HHOOK>> = Mutex::new(Option::None);
+    // the low level hook doesn't report auto repeat, so track which keys are down
+    static ref KEYS_DOWN: Mutex<[bool; 256]> = Mutex::new([false; 256]);
+}
+
+impl LowLevelHook {
+    pub fn install(window: HWND) -> Result<Self, RekeyError> {
+        let mut keyboard_hhook = LL_KEYBOARD_HHOOK.lock().map_err(|err| {
+            RekeyError::GenericError(format!("could not get keyboard hook lock: {}", err))
+        })?;
+        if keyboard_hhook.is_some() {
+            return Result::Err(RekeyError::GenericError("already installed".to_string()));
+        }
+        unsafe {
+            let hhook = SetWindowsHookExW(
+                WH_KEYBOARD_LL,
+                Option::Some(ll_keyboard_proc),
+                get_hinstance()?,
+                0,
+            )
+            .map_err(|err| {
+                RekeyError::Win32Error("failed to set low level keyboard hook".to_string(), err)
+            })?;
+            *keyboard_hhook = Option::Some(hhook);
+        }
+        LL_HOOK_WINDOW.store(window.0, Ordering::Relaxed);
+        debug("low level keyboard hook installed");
+        return Result::Ok(LowLevelHook {});
+    }
+
+    pub fn uninstall(&mut self) -> Result<(), RekeyError> {
+        ll_hook_set_mouse_enabled(false)?;
+        LL_HOOK_WINDOW.store(0, Ordering::Relaxed);
+        let mut keyboard_hhook = LL_KEYBOARD_HHOOK.lock().map_err(|err| {
+            RekeyError::GenericError(format!("could not get keyboard hook lock: {}", err))
+        })?;
+        if let Option::Some(hhook) = keyboard_hhook.take() {
+            unsafe {
+                UnhookWindowsHookEx(hhook).map_err(|err| {
+                    RekeyError::Win32Error("failed to unhook low level keyboard".to_string(), err)
+                })?;
+            }
+        }
+        return Result::Ok(());
+    }
+}
+
+pub fn ll_hook_is_installed() -> bool {
+    return LL_HOOK_WINDOW.load(Ordering::Relaxed) != 0;
+}
+
+// the low level mouse hook is only installed while scripts have mouse handlers and the low
+// level backend is in use
+pub fn ll_hook_set_mouse_enabled(enabled: bool) -> Result<(), RekeyError> {
+    let mut mouse_hhook = LL_MOUSE_HHOOK.lock().map_err(|err| {
+        RekeyError::GenericError(format!("could not get mouse hook lock: {}", err))
+    })?;
+    let enabled = enabled && LL_HOOK_WINDOW.load(Ordering::Relaxed) != 0;
+    if mouse_hhook.is_some() == enabled {
+        return Result::Ok(());
+    }
+    unsafe {
+        if let Option::Some(hhook) = mouse_hhook.take() {
+            UnhookWindowsHookEx(hhook).map_err(|err| {
+                RekeyError::Win32Error("failed to unhook low level mouse".to_string(), err)
+            })?;
+            debug("low level mouse hook uninstalled");
+        } else {
+            let hhook = SetWindowsHookExW(
+                WH_MOUSE_LL,
+                Option::Some(ll_mouse_proc),
+                get_hinstance()?,
+                0,
+            )
+            .map_err(|err| {
+                RekeyError::Win32Error("failed to set low level mouse hook".to_string(), err)
+            })?;
+            *mouse_hhook = Option::Some(hhook);
+            debug("low level mouse hook installed");
+        }
+    }
+    return Result::Ok(());
+}
+
+unsafe extern "system" fn ll_keyboard_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    if code == HC_ACTION as i32 {
+        match ll_keyboard_hook(&*(lparam.0 as *const KBDLLHOOKSTRUCT)) {
+            Result::Ok(SkipInput::Skip) => {
+                return LRESULT(1);
+            }
+            Result::Ok(SkipInput::DontSkip) => {}
+            Result::Err(err) => {
+                debug!("low level keyboard hook failed {}", err);
+            }
+        }
+    }
+    return CallNextHookEx(HHOOK::default(), code, wparam, lparam);
+}
+
+fn ll_keyboard_hook(kbd: &KBDLLHOOKSTRUCT) -> Result<SkipInput, RekeyError> {
+    let timestamp = SystemTime::now();
+    let vkey_code = kbd.vkCode as u16;
+    let direction = if kbd.flags.0 & LLKHF_UP.0 != 0 {
+        KeyDirection::Up
+    } else {
+        KeyDirection::Down
+    };
+    let is_repeat = {
+        let mut keys_down = KEYS_DOWN.lock().map_err(|err| {
+            RekeyError::GenericError(format!("could not get keys down lock: {}", err))
+        })?;
+        let key_down = &mut keys_down[(vkey_code & 0xff) as usize];
+        let is_repeat = direction == KeyDirection::Down && *key_down;
+        *key_down = direction == KeyDirection::Down;
+        is_repeat
+    };
+    let details = KeyDetails {
+        scan_code: kbd.scanCode as u16,
+        extended: kbd.flags.0 & LLKHF_EXTENDED.0 != 0,
+        is_repeat,
+        injected: kbd.flags.0 & LLKHF_INJECTED.0 != 0,
+        // keys sent by scripts come back through the hook, scripts only see them if they ask
+        // to so a mapping like a -> b, b -> a doesn't loop
+        self_injected: kbd.dwExtraInfo == REKEY_INPUT_EXTRA_INFO,
+        timestamp,
+    };
+    let hwnd = HWND(LL_HOOK_WINDOW.load(Ordering::Relaxed));
+    return handle_key_input(hwnd, vkey_code, direction, details);
+}
+
+unsafe extern "system" fn ll_mouse_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    if code == HC_ACTION as i32 {
+        let mouse = &*(lparam.0 as *const MSLLHOOKSTRUCT);
+        let hwnd = HWND(LL_HOOK_WINDOW.load(Ordering::Relaxed));
+        // the high word of mouseData is the x button or the wheel delta
+        let mouse_data = (mouse.mouseData >> 16) as u16 as i16;
+        match handle_mouse_input(
+            hwnd,
+            wparam.0 as u32,
+            mouse_data,
+            mouse.flags & LLMHF_INJECTED != 0,
+            mouse.dwExtraInfo == REKEY_INPUT_EXTRA_INFO,
+            SystemTime::now(),
+        ) {
+            Result::Ok(SkipInput::Skip) => {
+                return LRESULT(1);
+            }
+            Result::Ok(SkipInput::DontSkip) => {}
+            Result::Err(err) => {
+                debug!("low level mouse hook failed {}", err);
+            }
+        }
+    }
+    return CallNextHookEx(HHOOK::default(), code, wparam, lparam);
+}
@@ -1,57 +1,199 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod cli;
 mod devices;
 mod dll;
+mod input_buffer;
 mod input_log;
+mod instance;
 mod js;
+mod ll_hook;
 mod raw_input;
 mod scripts;
+mod scripts_watcher;
+mod send_input;
 mod win32hal;
 mod window;
 
-use std::fs;
+use std::{env, fs, process};
 
+use cli::{CliArgs, USAGE};
+use devices::devices_list;
 use dll::RekeyDll;
+use instance::{instance_send_command, InstanceCommand, SingleInstance};
+use ll_hook::LowLevelHook;
 use raw_input::RawInput;
-use rekey_common::{debug, get_log_filename, RekeyError};
+use rekey_common::{
+    config::{Config, InputBackend},
+    debug, error, get_config_filename, get_log_filename, info, set_log_filename, set_log_level,
+    set_scripts_dir, RekeyError, WM_USER_INPUT_REGISTRATIONS_CHANGED,
+};
+use scripts::{
+    scripts_check, scripts_set_default_unknown_device, scripts_set_handler_timeout,
+    scripts_set_input_registrations_listener,
+};
+use scripts_watcher::scripts_watcher_start;
 use window::{
     add_systray_icon, create_window, delete_systray_icon, load_scripts_notify_on_error,
     message_loop,
 };
+use windows::Win32::{
+    Foundation::{LPARAM, WPARAM},
+    System::Console::{AttachConsole, ATTACH_PARENT_PROCESS},
+    UI::WindowsAndMessaging::PostMessageW,
+};
 
 fn main() {
     match _main() {
         Result::Ok(()) => {}
         Result::Err(err) => {
-            debug!("main failed: {}", err);
+            error!("main failed: {}", err);
+            attach_parent_console();
+            eprintln!("rekey: {}", err);
+            process::exit(1);
         }
     };
 }
 
 fn _main() -> Result<(), RekeyError> {
+    let args = CliArgs::parse(&env::args().skip(1).collect::<Vec<String>>())?;
+    if let Option::Some(log_file) = &args.log_file {
+        set_log_filename(log_file.clone())?;
+    }
+    if let Option::Some(log_level) = args.log_level {
+        set_log_level(log_level);
+    }
+    if let Option::Some(scripts_dir) = &args.scripts_dir {
+        set_scripts_dir(scripts_dir.clone())?;
+    }
+
+    // these print to the console rekey was started from and exit without hooking anything
+    if args.help {
+        attach_parent_console();
+        print!("{}", USAGE);
+        return Result::Ok(());
+    }
+    if let Option::Some(script) = &args.check {
+        attach_parent_console();
+        scripts_check(script)?;
+        println!("{}: ok", script.display());
+        return Result::Ok(());
+    }
+    if args.list_devices {
+        attach_parent_console();
+        for device in devices_list()? {
+            println!("{}", device);
+        }
+        return Result::Ok(());
+    }
+
+    // a second launch only signals the running instance, it must not touch its log or hooks
+    let _instance = match SingleInstance::acquire()? {
+        Option::Some(instance) => instance,
+        Option::None => {
+            let startup_options = args.startup_options();
+            if !startup_options.is_empty() {
+                return Result::Err(RekeyError::GenericError(format!(
+                    "rekey is already running, {} only apply when it starts, exit it first with rekey --exit",
+                    startup_options.join(", ")
+                )));
+            }
+            return instance_send_command(args.command.unwrap_or(InstanceCommand::Reload));
+        }
+    };
+    if let Option::Some(command) = args.command {
+        return Result::Err(RekeyError::GenericError(format!(
+            "rekey is not running, can't send {:?}",
+            command
+        )));
+    }
+
     reset_log_file()?;
-    debug("BEGIN");
+    info("BEGIN");
+
+    let mut config = Config::load(&get_config_filename()?)?;
+    if let Option::Some(backend) = args.backend {
+        config.backend = backend;
+    }
+    if let Option::Some(handler_timeout_millis) = args.handler_timeout_millis {
+        config.handler_timeout_millis = handler_timeout_millis;
+    }
+    config.validate()?;
+    info!(
+        "input backend {}, handler timeout {}ms, hook timeout {}ms, hook fail policy {}, unknown device {}",
+        config.backend,
+        config.handler_timeout_millis,
+        config.resolve_hook_timeout_millis(),
+        config.hook_fail_policy,
+        config.unknown_device
+    );
+    scripts_set_handler_timeout(config.handler_timeout_millis);
+    scripts_set_default_unknown_device(config.unknown_device)?;
 
     let window = create_window()?;
-    add_systray_icon(window)?;
+    if !args.no_tray {
+        add_systray_icon(window)?;
+    }
+
+    // mouse and HID input are only registered while scripts have handlers for them
+    scripts_set_input_registrations_listener(Box::new(move || unsafe {
+        PostMessageW(
+            window,
+            WM_USER_INPUT_REGISTRATIONS_CHANGED,
+            WPARAM(0),
+            LPARAM(0),
+        )
+        .unwrap_or_else(|err| {
+            debug!("failed to post input registrations changed: {}", err);
+        });
+    }))?;
 
     load_scripts_notify_on_error(window);
+    scripts_watcher_start(window)?;
 
-    let mut dll = RekeyDll::new()?;
-    dll.install(window)?;
+    let mut dll: Option<RekeyDll> = Option::None;
+    let mut ll_hook: Option<LowLevelHook> = Option::None;
+    match config.backend {
+        InputBackend::Hook => {
+            let mut d = RekeyDll::new()?;
+            d.install(
+                window,
+                config.resolve_hook_timeout_millis(),
+                config.hook_fail_policy,
+            )?;
+            dll = Option::Some(d);
+        }
+        InputBackend::LowLevelHook => {
+            ll_hook = Option::Some(LowLevelHook::install(window)?);
+        }
+    }
 
     let mut raw_input = RawInput::new(window)?;
 
     message_loop()?;
 
-    dll.uninstall()?;
+    if let Option::Some(mut dll) = dll {
+        dll.uninstall()?;
+    }
+    if let Option::Some(mut ll_hook) = ll_hook {
+        ll_hook.uninstall()?;
+    }
     raw_input.uninstall()?;
-    delete_systray_icon(window)?;
+    if !args.no_tray {
+        delete_systray_icon(window)?;
+    }
 
-    debug("END");
+    info("END");
     return Result::Ok(());
 }
 
+// release builds have no console of their own, output only shows when started from one
+fn attach_parent_console() -> () {
+    unsafe {
+        let _ = AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
 fn reset_log_file() -> Result<(), RekeyError> {
     let log_filename = get_log_filename()?;
     fs::create_dir_all(log_filename.parent().unwrap_or(&log_filename))?;
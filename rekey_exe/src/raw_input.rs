@@ -1,32 +1,49 @@
-use std::mem::size_of;
+use std::{
+    mem::size_of,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use lazy_static::lazy_static;
 
 use windows::Win32::{
     Foundation::HWND,
-    UI::Input::{RegisterRawInputDevices, RAWINPUTDEVICE, RIDEV_INPUTSINK},
+    UI::Input::{
+        RegisterRawInputDevices, RAWINPUTDEVICE, RAWINPUTDEVICE_FLAGS, RIDEV_DEVNOTIFY,
+        RIDEV_INPUTSINK, RIDEV_REMOVE,
+    },
 };
 
-use crate::RekeyError;
+use crate::{debug, devices::HidUsage, RekeyError};
 
 pub struct RawInput {}
 
-const HID_KEYBOARD_USAGE_PAGE: u16 = 1;
+const HID_GENERIC_DESKTOP_USAGE_PAGE: u16 = 1;
+const HID_MOUSE_USAGE: u16 = 2;
 const HID_KEYBOARD_USAGE: u16 = 6;
 
+// RIDEV_DEVNOTIFY sends WM_INPUT_DEVICE_CHANGE when a device of a registered usage is attached
+// or detached, and an arrival for each device already attached when it is registered, the
+// window compares the device list on each one so repeated arrivals aren't passed on
+const REGISTER_FLAGS: RAWINPUTDEVICE_FLAGS =
+    RAWINPUTDEVICE_FLAGS(RIDEV_INPUTSINK.0 | RIDEV_DEVNOTIFY.0);
+
+static MOUSE_REGISTERED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref REGISTERED_HID_USAGES: Mutex<Vec<HidUsage>> = Mutex::new(vec![]);
+}
+
 impl RawInput {
     pub fn new(window: HWND) -> Result<Self, RekeyError> {
-        let raw_input_device: RAWINPUTDEVICE = RAWINPUTDEVICE {
-            usUsagePage: HID_KEYBOARD_USAGE_PAGE,
-            usUsage: HID_KEYBOARD_USAGE,
-            dwFlags: RIDEV_INPUTSINK,
-            hwndTarget: window,
-        };
-        let raw_input_devices: [RAWINPUTDEVICE; 1] = [raw_input_device];
-        let cbsize = size_of::<RAWINPUTDEVICE>();
-        unsafe {
-            RegisterRawInputDevices(&raw_input_devices, cbsize as u32).map_err(|err| {
-                RekeyError::Win32Error("failed to register raw input devices".to_string(), err)
-            })?;
-        }
+        register_raw_input_device(
+            HID_GENERIC_DESKTOP_USAGE_PAGE,
+            HID_KEYBOARD_USAGE,
+            REGISTER_FLAGS,
+            window,
+        )?;
         return Result::Ok(RawInput {});
     }
 
@@ -34,3 +51,102 @@ impl RawInput {
         return Result::Ok(());
     }
 }
+
+// mouse raw input is only registered while scripts have mouse handlers, every mouse move
+// would otherwise be sent to the window
+pub fn raw_input_set_mouse_enabled(window: HWND, enabled: bool) -> Result<(), RekeyError> {
+    if MOUSE_REGISTERED.load(Ordering::Relaxed) == enabled {
+        return Result::Ok(());
+    }
+    if enabled {
+        register_raw_input_device(
+            HID_GENERIC_DESKTOP_USAGE_PAGE,
+            HID_MOUSE_USAGE,
+            REGISTER_FLAGS,
+            window,
+        )?;
+    } else {
+        // RIDEV_REMOVE requires a null target window
+        register_raw_input_device(
+            HID_GENERIC_DESKTOP_USAGE_PAGE,
+            HID_MOUSE_USAGE,
+            RIDEV_REMOVE,
+            HWND(0),
+        )?;
+    }
+    MOUSE_REGISTERED.store(enabled, Ordering::Relaxed);
+    debug!(
+        "raw mouse input {}",
+        if enabled { "registered" } else { "removed" }
+    );
+    return Result::Ok(());
+}
+
+// registers raw input for the usages scripts have HID handlers for and removes the ones they
+// no longer have handlers for
+pub fn raw_input_set_hid_usages(window: HWND, hid_usages: &[HidUsage]) -> Result<(), RekeyError> {
+    let mut registered_hid_usages = REGISTERED_HID_USAGES.lock().map_err(|err| {
+        RekeyError::GenericError(format!("could not get hid usages lock: {}", err))
+    })?;
+
+    for hid_usage in registered_hid_usages.iter() {
+        if !hid_usages.contains(hid_usage) {
+            register_raw_input_device(
+                hid_usage.usage_page,
+                hid_usage.usage,
+                RIDEV_REMOVE,
+                HWND(0),
+            )?;
+            debug!(
+                "raw hid input removed {:04X}:{:04X}",
+                hid_usage.usage_page, hid_usage.usage
+            );
+        }
+    }
+    registered_hid_usages.retain(|hid_usage| hid_usages.contains(hid_usage));
+
+    for hid_usage in hid_usages {
+        if !registered_hid_usages.contains(hid_usage) {
+            register_raw_input_device(
+                hid_usage.usage_page,
+                hid_usage.usage,
+                REGISTER_FLAGS,
+                window,
+            )?;
+            registered_hid_usages.push(*hid_usage);
+            debug!(
+                "raw hid input registered {:04X}:{:04X}",
+                hid_usage.usage_page, hid_usage.usage
+            );
+        }
+    }
+    return Result::Ok(());
+}
+
+// usages which are registered for keyboard and mouse input and can't be used for HID handlers
+pub fn raw_input_is_reserved_usage(hid_usage: &HidUsage) -> bool {
+    return hid_usage.usage_page == HID_GENERIC_DESKTOP_USAGE_PAGE
+        && (hid_usage.usage == HID_KEYBOARD_USAGE || hid_usage.usage == HID_MOUSE_USAGE);
+}
+
+fn register_raw_input_device(
+    usage_page: u16,
+    usage: u16,
+    flags: RAWINPUTDEVICE_FLAGS,
+    window: HWND,
+) -> Result<(), RekeyError> {
+    let raw_input_device: RAWINPUTDEVICE = RAWINPUTDEVICE {
+        usUsagePage: usage_page,
+        usUsage: usage,
+        dwFlags: flags,
+        hwndTarget: window,
+    };
+    let raw_input_devices: [RAWINPUTDEVICE; 1] = [raw_input_device];
+    let cbsize = size_of::<RAWINPUTDEVICE>();
+    unsafe {
+        RegisterRawInputDevices(&raw_input_devices, cbsize as u32).map_err(|err| {
+            RekeyError::Win32Error("failed to register raw input devices".to_string(), err)
+        })?;
+    }
+    return Result::Ok(());
+}
@@ -1,73 +1,555 @@
 use boa_engine::{
-    js_string, property::Attribute, Context, JsError, JsNativeError, JsObject, JsValue,
-    NativeFunction, Source,
+    builtins::promise::PromiseState,
+    js_string,
+    module::{Module, ModuleLoader},
+    object::{
+        builtins::{JsArray, JsRegExp},
+        ObjectInitializer,
+    },
+    property::Attribute,
+    Context, JsError, JsNativeError, JsObject, JsValue, NativeFunction, Source,
 };
 use lazy_static::lazy_static;
 use rekey_common::{
-    char_from_vcode, debug, get_scripts_dir, to_virtual_key,
+    char_from_vcode,
+    clock::{Clock, SystemClock},
+    config::DEFAULT_HANDLER_TIMEOUT_MILLIS,
+    debug, error, get_scripts_dir, info, split_key_expr,
+    timers::TimerQueue,
+    to_virtual_key,
+    unknown_device::{device_filter_decision, DeviceFilterDecision, UnknownDevicePolicy},
     vkeys::{VKEY_LOOKUP_BY_CODE, VKEY_LOOKUP_BY_NAME},
     KeyDirection, RekeyError, REKEY_API_JS_FILENAME,
 };
 use std::{
-    fs,
-    mem::size_of,
+    cell::Cell,
+    fmt, fs,
     path::PathBuf,
+    rc::Rc,
     sync::{
+        atomic::{AtomicBool, AtomicI32, AtomicU32, Ordering},
         mpsc::{self, RecvTimeoutError},
         Arc, Mutex,
     },
     thread,
+    time::{Duration, Instant, SystemTime},
 };
-use windows::Win32::UI::Input::KeyboardAndMouse::{
-    GetKeyState, SendInput, INPUT, INPUT_0, INPUT_KEYBOARD, KEYBDINPUT, KEYEVENTF_KEYUP,
-    VIRTUAL_KEY, VK_CONTROL, VK_MENU, VK_SHIFT,
+use windows::Win32::UI::{
+    Input::KeyboardAndMouse::{
+        GetAsyncKeyState, GetKeyState, VIRTUAL_KEY, VK_CONTROL, VK_LBUTTON, VK_LCONTROL, VK_LMENU,
+        VK_LSHIFT, VK_LWIN, VK_MBUTTON, VK_MENU, VK_RBUTTON, VK_RCONTROL, VK_RMENU, VK_RSHIFT,
+        VK_RWIN, VK_SHIFT, VK_XBUTTON1, VK_XBUTTON2,
+    },
+    WindowsAndMessaging::WHEEL_DELTA,
 };
 
 use crate::{
-    devices::Device,
-    js::{self, timer::Timer},
+    devices::{devices_attached, devices_list, Device, HidUsage},
+    js::{
+        self,
+        module_loader::ScriptModuleLoader,
+        timer::{ScriptTimers, Timer},
+    },
+    raw_input::raw_input_is_reserved_usage,
+    send_input::{
+        build_key_expr_inputs, build_mouse_inputs, build_text_inputs, get_virtual_screen_rect,
+        send_input_set_enabled, send_inputs, MouseAction, MouseButton, MouseMove, SendKeyDirection,
+    },
     SkipInput,
 };
 
-#[derive(PartialEq, Eq)]
 enum KeyHandlerDeviceFilter {
     All,
     Contains(String),
+    RegExp(JsRegExp),
+    Ids {
+        vendor_id: Option<u16>,
+        product_id: Option<u16>,
+        interface: Option<u16>,
+    },
+    Any(Vec<KeyHandlerDeviceFilter>),
 }
 
 #[derive(PartialEq, Eq)]
 enum KeyHandlerKeyFilter {
     All,
+    Keys(Vec<KeyFilterKey>),
+}
+
+#[derive(PartialEq, Eq)]
+struct KeyFilterKey {
+    vkey_code: u16,
+    // None matches the key regardless of which modifiers are held
+    modifiers: Option<KeyFilterModifiers>,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+struct KeyFilterModifiers {
+    shift: bool,
+    ctrl: bool,
+    alt: bool,
+    win: bool,
+}
+
+// state of the modifier keys when a key event was received
+#[derive(Clone, Copy)]
+struct KeyModifiers {
+    left_shift: bool,
+    right_shift: bool,
+    left_ctrl: bool,
+    right_ctrl: bool,
+    left_alt: bool,
+    right_alt: bool,
+    left_win: bool,
+    right_win: bool,
+}
+
+impl KeyModifiers {
+    fn capture() -> Self {
+        fn is_key_down(vkey: VIRTUAL_KEY) -> bool {
+            return unsafe { (GetAsyncKeyState(vkey.0 as i32) as u16 & 0x8000) == 0x8000 };
+        }
+
+        return KeyModifiers {
+            left_shift: is_key_down(VK_LSHIFT),
+            right_shift: is_key_down(VK_RSHIFT),
+            left_ctrl: is_key_down(VK_LCONTROL),
+            right_ctrl: is_key_down(VK_RCONTROL),
+            left_alt: is_key_down(VK_LMENU),
+            right_alt: is_key_down(VK_RMENU),
+            left_win: is_key_down(VK_LWIN),
+            right_win: is_key_down(VK_RWIN),
+        };
+    }
+
+    fn to_key_filter_modifiers(&self) -> KeyFilterModifiers {
+        return KeyFilterModifiers {
+            shift: self.left_shift || self.right_shift,
+            ctrl: self.left_ctrl || self.right_ctrl,
+            alt: self.left_alt || self.right_alt,
+            win: self.left_win || self.right_win,
+        };
+    }
+
+    fn to_js_object(&self, context: &mut Context<'_>) -> JsObject {
+        let modifiers = self.to_key_filter_modifiers();
+        return ObjectInitializer::new(context)
+            .property(js_string!("shift"), modifiers.shift, Attribute::all())
+            .property(js_string!("ctrl"), modifiers.ctrl, Attribute::all())
+            .property(js_string!("alt"), modifiers.alt, Attribute::all())
+            .property(js_string!("win"), modifiers.win, Attribute::all())
+            .property(js_string!("leftShift"), self.left_shift, Attribute::all())
+            .property(js_string!("rightShift"), self.right_shift, Attribute::all())
+            .property(js_string!("leftCtrl"), self.left_ctrl, Attribute::all())
+            .property(js_string!("rightCtrl"), self.right_ctrl, Attribute::all())
+            .property(js_string!("leftAlt"), self.left_alt, Attribute::all())
+            .property(js_string!("rightAlt"), self.right_alt, Attribute::all())
+            .property(js_string!("leftWin"), self.left_win, Attribute::all())
+            .property(js_string!("rightWin"), self.right_win, Attribute::all())
+            .build();
+    }
+}
+
+fn parse_unknown_device_policy(value: &JsValue) -> Result<UnknownDevicePolicy, JsError> {
+    if value.is_string() {
+        if let Result::Ok(policy) =
+            UnknownDevicePolicy::parse(&value.as_string().unwrap().to_std_string_escaped())
+        {
+            return Result::Ok(policy);
+        }
+    }
+    return Result::Err(JsError::from(JsNativeError::error().with_message(
+        "invalid unknownDevice, expected 'match', 'skip' or 'buffer'",
+    )));
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum InputKind {
+    Keyboard,
+    Mouse,
+    Hid,
+    // device arrivals and removals, not input
+    DeviceChange,
+}
+
+impl InputKind {
+    fn register_function_name(&self) -> &'static str {
+        match *self {
+            InputKind::Keyboard => "rekeyRegister",
+            InputKind::Mouse => "rekeyRegisterMouse",
+            InputKind::Hid => "rekeyRegisterHid",
+            InputKind::DeviceChange => "rekeyOnDeviceChange",
+        }
+    }
+}
+
+// mouse events use the virtual key codes of the buttons, the wheels don't have one so they use
+// codes above the virtual key range
+pub const MOUSE_WHEEL_CODE: u16 = 0x100;
+pub const MOUSE_HWHEEL_CODE: u16 = 0x101;
+const MOUSE_BUTTON_NAMES: [(u16, &str); 7] = [
+    (VK_LBUTTON.0, "left"),
+    (VK_RBUTTON.0, "right"),
+    (VK_MBUTTON.0, "middle"),
+    (VK_XBUTTON1.0, "x1"),
+    (VK_XBUTTON2.0, "x2"),
+    (MOUSE_WHEEL_CODE, "wheel"),
+    (MOUSE_HWHEEL_CODE, "hwheel"),
+];
+
+fn mouse_button_name(code: u16) -> Option<&'static str> {
+    return MOUSE_BUTTON_NAMES
+        .iter()
+        .find(|(c, _)| *c == code)
+        .map(|(_, name)| *name);
 }
 
 struct KeyHandler {
+    id: i32,
+    // keyboard handlers only see key events and mouse handlers only see mouse events
+    kind: InputKind,
+    // only set for HID handlers, which see the reports of devices with this usage
+    hid_usage: Option<HidUsage>,
+    // handlers with a higher priority are called first
+    priority: i32,
     device_filter: KeyHandlerDeviceFilter,
     key_filter: KeyHandlerKeyFilter,
+    // None uses the default of the script
+    unknown_device: Option<UnknownDevicePolicy>,
     intercept: bool,
+    // also call the handler for input sent by rekey
+    self_injected: bool,
     callback: JsObject,
 }
 
+impl KeyHandler {
+    fn get_unknown_device_policy(
+        &self,
+        script: &Script,
+    ) -> Result<UnknownDevicePolicy, RekeyError> {
+        let script_default = script.default_unknown_device.lock().map_err(|err| {
+            RekeyError::GenericError(format!("failed to lock unknown device policy: {}", err))
+        })?;
+        let config_default = DEFAULT_UNKNOWN_DEVICE.lock().map_err(|err| {
+            RekeyError::GenericError(format!("failed to lock unknown device policy: {}", err))
+        })?;
+        return Result::Ok(UnknownDevicePolicy::resolve(
+            self.unknown_device,
+            *script_default,
+            *config_default,
+        ));
+    }
+
+    fn device_filter_decision(
+        &self,
+        script: &Script,
+        device_known: bool,
+        can_buffer: bool,
+    ) -> Result<DeviceFilterDecision, RekeyError> {
+        let has_device_filter = !matches!(self.device_filter, KeyHandlerDeviceFilter::All);
+        return Result::Ok(device_filter_decision(
+            device_known,
+            has_device_filter,
+            self.get_unknown_device_policy(script)?,
+            can_buffer,
+        ));
+    }
+}
+
+struct KeyHandlerResult {
+    skip: SkipInput,
+    stop_propagation: bool,
+    // false when the handler didn't match the input and returned without calling its callback
+    called: bool,
+}
+
+impl KeyHandlerResult {
+    fn not_handled() -> Self {
+        return KeyHandlerResult {
+            skip: SkipInput::DontSkip,
+            stop_propagation: false,
+            called: false,
+        };
+    }
+}
+
+// the time the callbacks of each script took for one event, so a script gets at most one
+// overrun per event and handlers which didn't call their callback aren't timed, otherwise a
+// handler filtered out in no time would reset the overruns of a slow one
+struct EventHandlerTimes<'s, S> {
+    times: Vec<(&'s S, Duration)>,
+}
+
+impl<'s, S> EventHandlerTimes<'s, S> {
+    fn new() -> Self {
+        return EventHandlerTimes { times: vec![] };
+    }
+
+    fn add(&mut self, script: &'s S, elapsed: Duration) -> () {
+        if let Option::Some((_, total)) = self
+            .times
+            .iter_mut()
+            .find(|(s, _)| std::ptr::eq(*s, script))
+        {
+            *total += elapsed;
+        } else {
+            self.times.push((script, elapsed));
+        }
+    }
+
+    fn take(self) -> Vec<(&'s S, Duration)> {
+        return self.times;
+    }
+}
+
+// the number of overruns in a row after a script's handlers took `elapsed` for an event
+fn next_overruns(overruns: u32, elapsed: Duration, timeout: Duration) -> u32 {
+    if elapsed <= timeout {
+        return 0;
+    }
+    return overruns + 1;
+}
+
 pub struct Script<'a> {
+    path: PathBuf,
+    files: Vec<(PathBuf, Option<SystemTime>)>,
     pub context: Arc<Mutex<Context<'a>>>,
-    key_handlers: Arc<Mutex<Vec<KeyHandler>>>,
-    pub timers: Arc<Mutex<Vec<Timer>>>,
+    module_loader: Rc<ScriptModuleLoader>,
+    key_handlers: Arc<Mutex<Vec<Arc<KeyHandler>>>>,
+    // set with rekeySetDefaultUnknownDevice, None uses the default from rekey.ini
+    default_unknown_device: Arc<Mutex<Option<UnknownDevicePolicy>>>,
+    pub timers: ScriptTimers,
+    // number of handler runs in a row which exceeded the handler timeout
+    overruns: Cell<u32>,
+    disabled: Cell<bool>,
+}
+
+impl Script<'_> {
+    pub fn is_disabled(&self) -> bool {
+        return self.disabled.get();
+    }
+
+    // counts events whose handlers ran over the timeout, an event within the timeout resets the
+    // count so only a script which is slow again and again is disabled
+    fn handler_finished(&self, elapsed: Duration, timeout: Duration) -> Result<(), RekeyError> {
+        let overruns = next_overruns(self.overruns.get(), elapsed, timeout);
+        self.overruns.set(overruns);
+        if overruns == 0 {
+            return Result::Ok(());
+        }
+        debug!(
+            "script {} key handler took {}ms, overrun {} of {}",
+            self.path.display(),
+            elapsed.as_millis(),
+            overruns,
+            MAX_HANDLER_OVERRUNS
+        );
+        if overruns >= MAX_HANDLER_OVERRUNS {
+            return self.disable(format!(
+                "key handlers exceeded the time budget {} times",
+                overruns
+            ));
+        }
+        return update_script_status(
+            &self.path,
+            ScriptState::Loaded,
+            Option::Some(format!(
+                "key handler exceeded the time budget ({}ms)",
+                elapsed.as_millis()
+            )),
+        );
+    }
+
+    // stops the script from receiving key events and running timers until it is reloaded
+    fn disable(&self, reason: String) -> Result<(), RekeyError> {
+        debug!("disabling script {}: {}", self.path.display(), reason);
+        self.disabled.set(true);
+        self.key_handlers
+            .lock()
+            .map_err(|err| {
+                RekeyError::GenericError(format!("failed to lock key handlers: {}", err))
+            })?
+            .clear();
+        self.timers
+            .lock()
+            .map_err(|err| RekeyError::GenericError(format!("could not get timers lock: {}", err)))?
+            .clear();
+        return update_script_status(&self.path, ScriptState::Disabled, Option::Some(reason));
+    }
+}
+
+impl Drop for Script<'_> {
+    fn drop(&mut self) {
+        self.module_loader.clear();
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ScriptState {
+    Loaded,
+    Failed,
+    Disabled,
+}
+
+impl fmt::Display for ScriptState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ScriptState::Loaded => write!(f, "loaded"),
+            ScriptState::Failed => write!(f, "failed"),
+            ScriptState::Disabled => write!(f, "disabled"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ScriptStatus {
+    pub path: PathBuf,
+    pub state: ScriptState,
+    // the most recent load error, a loaded script keeps running its previous version if a
+    // reload fails
+    pub error: Option<String>,
+    // the line of the error in the script or module it came from, if the error has a position
+    pub line: Option<u32>,
+}
+
+impl ScriptStatus {
+    fn new(path: PathBuf, state: ScriptState, error: Option<String>) -> Self {
+        let line = error.as_deref().and_then(ScriptStatus::error_line);
+        return ScriptStatus {
+            path,
+            state,
+            error,
+            line,
+        };
+    }
+
+    // boa only knows the position of syntax errors, which end their message with
+    // "at line <line>, col <column>"
+    fn error_line(error: &str) -> Option<u32> {
+        let (_, position) = error.rsplit_once(" at line ")?;
+        let (line, _) = position.split_once(", col ")?;
+        return line.trim().parse().ok();
+    }
+
+    pub fn name(&self) -> String {
+        return self
+            .path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+    }
+}
+
+impl fmt::Display for ScriptStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let (Option::Some(error), Option::Some(line)) = (&self.error, self.line) {
+            write!(
+                f,
+                "{}: {} (line {}: {})",
+                self.name(),
+                self.state,
+                line,
+                error
+            )
+        } else if let Option::Some(error) = &self.error {
+            write!(f, "{}: {} ({})", self.name(), self.state, error)
+        } else {
+            write!(f, "{}: {}", self.name(), self.state)
+        }
+    }
+}
+
+// details of a key event which come from the hook and raw input
+pub struct KeyDetails {
+    pub scan_code: u16,
+    pub extended: bool,
+    // the key was already down, i.e. this is an auto repeat
+    pub is_repeat: bool,
+    // the key was sent by software, e.g. SendInput, rather than a device
+    pub injected: bool,
+    // the key was sent by rekey itself, e.g. sendKey, these are only passed to handlers which
+    // ask for them so remapping keys to each other doesn't loop
+    pub self_injected: bool,
+    pub timestamp: SystemTime,
 }
 
 struct InputMessage {
+    kind: InputKind,
+    // for mouse events the button code, see MOUSE_BUTTON_NAMES
     vkey_code: u16,
     direction: KeyDirection,
     device: Option<Arc<Device>>,
+    details: KeyDetails,
+    modifiers: KeyModifiers,
+    // wheel rotation of mouse wheel events, a multiple of WHEEL_DELTA
+    wheel: i32,
+    // the caller stops waiting for a response after this time and lets the key through
+    deadline: Instant,
+}
+
+// HID reports can't be skipped so nothing waits for the handlers
+struct HidMessage {
+    device: Arc<Device>,
+    hid_usage: HidUsage,
+    reports: Vec<Vec<u8>>,
+    timestamp: SystemTime,
+}
+
+// a device was attached or detached, like HID reports nothing waits for the handlers
+struct DeviceChangeMessage {
+    device: Arc<Device>,
+    arrived: bool,
+}
+
+// what the window needs to register for to get the input the handlers of all scripts want
+#[derive(Clone, PartialEq, Eq, Default)]
+pub struct InputRegistrations {
+    pub mouse: bool,
+    pub hid_usages: Vec<HidUsage>,
 }
 
 enum ThreadMessage {
     Exit,
     HandleInput(mpsc::Sender<ThreadResponseMessage>, InputMessage),
+    HandleHid(HidMessage),
+    HandleDeviceChange(DeviceChangeMessage),
+    ShouldBufferUnknownDevice(
+        mpsc::Sender<Result<bool, RekeyError>>,
+        InputKind,
+        u16,
+        KeyModifiers,
+    ),
+    ReloadChanged(mpsc::Sender<Result<Vec<RekeyError>, RekeyError>>),
 }
 
 type ThreadResponseMessage = Result<SkipInput, RekeyError>;
 
+static NEXT_KEY_HANDLER_ID: AtomicI32 = AtomicI32::new(1);
+static MOUSE_HANDLERS_REGISTERED: AtomicBool = AtomicBool::new(false);
+static SELF_INJECTED_HANDLERS_REGISTERED: AtomicBool = AtomicBool::new(false);
+// set by rekey --check, scripts see no devices and no keys down and nothing is registered
+static CHECK_MODE: AtomicBool = AtomicBool::new(false);
+
+// the input hook blocks all keyboard input while waiting on scripts so a key event gets
+// this long to run all of its handlers before the key is passed through, set from rekey.ini
+static HANDLER_TIMEOUT_MILLIS: AtomicU32 = AtomicU32::new(DEFAULT_HANDLER_TIMEOUT_MILLIS);
+// a script is disabled after its handlers exceed the timeout this many times in a row
+const MAX_HANDLER_OVERRUNS: u32 = 3;
+// boa throws an uncatchable error when a single loop runs more iterations than this, which
+// stops an infinite loop from hanging the scripts thread
+const LOOP_ITERATION_LIMIT: u64 = 10_000_000;
+
 lazy_static! {
     static ref CHANNEL: Mutex<Option<mpsc::Sender<ThreadMessage>>> = Mutex::new(Option::None);
+    static ref SCRIPT_STATUSES: Mutex<Vec<ScriptStatus>> = Mutex::new(vec![]);
+    // unknown_device from rekey.ini
+    static ref DEFAULT_UNKNOWN_DEVICE: Mutex<UnknownDevicePolicy> =
+        Mutex::new(UnknownDevicePolicy::Match);
+    static ref INPUT_REGISTRATIONS: Mutex<InputRegistrations> =
+        Mutex::new(InputRegistrations::default());
+    static ref INPUT_REGISTRATIONS_LISTENER: Mutex<Option<Box<dyn Fn() + Send>>> =
+        Mutex::new(Option::None);
 }
 
 pub fn scripts_load() -> Result<(), RekeyError> {
@@ -82,7 +564,7 @@ pub fn scripts_load() -> Result<(), RekeyError> {
         *channel = Option::None;
     }
 
-    debug("loading scripts");
+    info!("loading scripts from {}", get_scripts_dir()?.display());
 
     let (tx, rx) = mpsc::channel::<ThreadMessage>();
 
@@ -115,9 +597,10 @@ fn scripts_thread(
     script_dir: PathBuf,
 ) -> () {
     debug("script thread starting");
-    let scripts = match load_scripts(script_dir) {
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock {});
+    let mut scripts = match load_scripts(&script_dir, &clock) {
         Result::Err(err) => {
-            debug!("init error: {}", err);
+            error!("init error: {}", err);
             tx.send(Result::Err(err))
                 .unwrap_or_else(move |err| debug!("failed to send init error: {}", err));
             return;
@@ -127,8 +610,14 @@ fn scripts_thread(
     tx.send(Result::Ok(()))
         .unwrap_or_else(|err| debug!("failed to send init: {}", err));
 
+    let mut input_registrations: Option<InputRegistrations> = Option::None;
+    let mut replayed_device_handler_ids: Vec<i32> = vec![];
     loop {
-        let timeout = Timer::get_nearest_duration(&scripts).unwrap_or_else(|err| {
+        thread_update_input_registrations(&scripts, &mut input_registrations)
+            .unwrap_or_else(|err| debug!("failed to update input registrations: {}", err));
+        thread_replay_attached_devices(&scripts, &mut replayed_device_handler_ids)
+            .unwrap_or_else(|err| debug!("failed to replay attached devices: {}", err));
+        let timeout = Timer::get_nearest_duration(&scripts, clock.as_ref()).unwrap_or_else(|err| {
             debug!("failed to get nearest duration: {}", err);
             return Option::None;
         });
@@ -164,9 +653,46 @@ fn scripts_thread(
                                     return ();
                                 });
                         }
+                        ThreadMessage::HandleHid(msg) => {
+                            thread_handle_hid_message(msg, &scripts).unwrap_or_else(|err| {
+                                debug!("failed to handle hid message: {}", err);
+                            });
+                        }
+                        ThreadMessage::HandleDeviceChange(msg) => {
+                            thread_handle_device_change_message(msg, &scripts).unwrap_or_else(
+                                |err| {
+                                    debug!("failed to handle device change message: {}", err);
+                                },
+                            );
+                        }
+                        ThreadMessage::ShouldBufferUnknownDevice(
+                            tx,
+                            kind,
+                            vkey_code,
+                            modifiers,
+                        ) => {
+                            tx.send(thread_should_buffer_unknown_device(
+                                kind, vkey_code, &modifiers, &scripts,
+                            ))
+                            .unwrap_or_else(|err| {
+                                debug!("failed to send message: {}", err);
+                                return ();
+                            });
+                        }
+                        ThreadMessage::ReloadChanged(tx) => {
+                            tx.send(thread_reload_changed_scripts(
+                                &mut scripts,
+                                &script_dir,
+                                &clock,
+                            ))
+                            .unwrap_or_else(|err| {
+                                debug!("failed to send message: {}", err);
+                                return ();
+                            });
+                        }
                     }
                 }
-                Timer::run_timers(&scripts).unwrap_or_else(|err| {
+                Timer::run_timers(&scripts, clock.as_ref()).unwrap_or_else(|err| {
                     debug!("failed to run timers: {}", err);
                     return ();
                 });
@@ -177,173 +703,1023 @@ fn scripts_thread(
 }
 
 fn thread_handle_input_message(msg: InputMessage, scripts: &Vec<Script>) -> ThreadResponseMessage {
-    let mut result = SkipInput::DontSkip;
+    // copy the handlers so callbacks are free to register and unregister handlers
+    let mut key_handlers: Vec<(&Script, Arc<KeyHandler>)> = vec![];
     for script in scripts {
-        if thread_run_script_callbacks(&msg, script)? == SkipInput::Skip {
-            result = SkipInput::Skip;
+        let script_key_handlers = script.key_handlers.lock().map_err(|err| {
+            RekeyError::GenericError(format!("failed to lock key handlers: {}", err))
+        })?;
+        for key_handler in script_key_handlers.iter() {
+            key_handlers.push((script, Arc::clone(key_handler)));
         }
     }
-    return Result::Ok(result);
-}
+    // the sort is stable so handlers with the same priority run in script then registration order
+    key_handlers.sort_by(|(_, a), (_, b)| b.priority.cmp(&a.priority));
 
-fn thread_run_script_callbacks(msg: &InputMessage, script: &Script) -> ThreadResponseMessage {
+    let mut handler_times = EventHandlerTimes::new();
     let mut result = SkipInput::DontSkip;
-    let key_handlers = script
-        .key_handlers
-        .lock()
-        .map_err(|err| RekeyError::GenericError(format!("failed to lock key handlers: {}", err)))?;
-    for key_handler in key_handlers.iter() {
-        if thread_run_key_handler_callbacks(&msg, script, &key_handler)? == SkipInput::Skip {
+    for (script, key_handler) in key_handlers {
+        // the caller has already let the key through, e.g. an earlier handler ran too long
+        if Instant::now() >= msg.deadline {
+            debug!(
+                "key {} {} missed its deadline",
+                msg.vkey_code, msg.direction
+            );
+            result = SkipInput::DontSkip;
+            break;
+        }
+        if script.is_disabled() || !is_key_handler_registered(&script.key_handlers, key_handler.id)?
+        {
+            continue;
+        }
+        let start = Instant::now();
+        let key_handler_result = thread_run_key_handler_callbacks(&msg, script, &key_handler);
+        if !matches!(&key_handler_result, Result::Ok(r) if !r.called) {
+            handler_times.add(script, start.elapsed());
+        }
+        // a failing handler, including one stopped by the runtime limits, doesn't stop the others
+        let key_handler_result = match key_handler_result {
+            Result::Ok(key_handler_result) => key_handler_result,
+            Result::Err(err) => {
+                debug!("key handler failed in {}: {}", script.path.display(), err);
+                continue;
+            }
+        };
+        if key_handler_result.skip == SkipInput::Skip {
             result = SkipInput::Skip;
         }
+        if key_handler_result.stop_propagation {
+            break;
+        }
     }
+    thread_handlers_finished(handler_times)?;
     return Result::Ok(result);
 }
 
-fn thread_run_key_handler_callbacks(
-    msg: &InputMessage,
-    script: &Script,
-    key_handler: &KeyHandler,
-) -> ThreadResponseMessage {
-    match key_handler.key_filter {
-        KeyHandlerKeyFilter::All => {}
+fn thread_handlers_finished(handler_times: EventHandlerTimes<Script>) -> Result<(), RekeyError> {
+    let handler_timeout = get_handler_timeout();
+    for (script, elapsed) in handler_times.take() {
+        script.handler_finished(elapsed, handler_timeout)?;
     }
+    return Result::Ok(());
+}
 
-    match &key_handler.device_filter {
-        KeyHandlerDeviceFilter::All => {}
-        KeyHandlerDeviceFilter::Contains(contains_str) => {
-            if let Option::Some(device) = &msg.device {
-                if !device.device_name.contains(contains_str) {
-                    return Result::Ok(SkipInput::DontSkip);
-                }
+fn thread_handle_hid_message(msg: HidMessage, scripts: &Vec<Script>) -> Result<(), RekeyError> {
+    // copy the handlers so callbacks are free to register and unregister handlers
+    let mut key_handlers: Vec<(&Script, Arc<KeyHandler>)> = vec![];
+    for script in scripts {
+        let script_key_handlers = script.key_handlers.lock().map_err(|err| {
+            RekeyError::GenericError(format!("failed to lock key handlers: {}", err))
+        })?;
+        for key_handler in script_key_handlers.iter() {
+            if key_handler.hid_usage == Option::Some(msg.hid_usage) {
+                key_handlers.push((script, Arc::clone(key_handler)));
             }
         }
     }
+    key_handlers.sort_by(|(_, a), (_, b)| b.priority.cmp(&a.priority));
+
+    let mut handler_times = EventHandlerTimes::new();
+    for (script, key_handler) in key_handlers {
+        if script.is_disabled() || !is_key_handler_registered(&script.key_handlers, key_handler.id)?
+        {
+            continue;
+        }
+        let start = Instant::now();
+        let result = thread_run_hid_handler_callback(&msg, script, &key_handler);
+        if !matches!(result, Result::Ok(false)) {
+            handler_times.add(script, start.elapsed());
+        }
+        if let Result::Err(err) = result {
+            debug!("hid handler failed in {}: {}", script.path.display(), err);
+        }
+    }
+    return thread_handlers_finished(handler_times);
+}
 
+// returns whether the callback was called
+fn thread_run_hid_handler_callback(
+    msg: &HidMessage,
+    script: &Script,
+    key_handler: &KeyHandler,
+) -> Result<bool, RekeyError> {
     let mut context = script
         .context
         .lock()
         .map_err(|err| RekeyError::GenericError(format!("failed to lock context: {}", err)))?;
 
-    let direction = format!("{}", msg.direction);
-    let direction = direction.to_lowercase();
+    if !device_filter_matches(&key_handler.device_filter, &msg.device, &mut context)? {
+        return Result::Ok(false);
+    }
+
+    let mut result = Result::Ok(true);
+    for report in &msg.reports {
+        let event = create_hid_event(msg, report, &mut context)?;
+        let args: [JsValue; 1] = [JsValue::Object(event)];
+        if let Result::Err(err) =
+            key_handler
+                .callback
+                .call(&JsValue::Undefined, &args, &mut context)
+        {
+            result = Result::Err(RekeyError::GenericError(format!(
+                "failed to run callback: {}",
+                err
+            )));
+            break;
+        }
+    }
+    // run promise jobs even on error, other callbacks may have queued jobs
+    context.run_jobs();
+    return result;
+}
 
+fn create_hid_event(
+    msg: &HidMessage,
+    report: &[u8],
+    context: &mut Context<'_>,
+) -> Result<JsObject, RekeyError> {
     let ctx = JsObject::default();
 
     ctx.set(
-        js_string!("vKeyCode"),
-        JsValue::from(msg.vkey_code),
+        js_string!("usagePage"),
+        JsValue::from(msg.hid_usage.usage_page),
         false,
-        &mut context,
+        context,
     )
     .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
 
-    if let Option::Some(key) = VKEY_LOOKUP_BY_CODE.get(&msg.vkey_code) {
-        ctx.set(
-            js_string!("key"),
-            JsValue::from(key.name.to_string()),
-            false,
-            &mut context,
-        )
-        .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
-    }
+    ctx.set(
+        js_string!("usage"),
+        JsValue::from(msg.hid_usage.usage),
+        false,
+        context,
+    )
+    .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
 
-    if let Option::Some(ch) = char_from_vcode(msg.vkey_code) {
-        ctx.set(js_string!("ch"), JsValue::from(ch), false, &mut context)
-            .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
-    }
+    let data = JsArray::from_iter(report.iter().map(|b| JsValue::from(*b)), context);
+    ctx.set(
+        js_string!("data"),
+        JsValue::Object(data.into()),
+        false,
+        context,
+    )
+    .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
 
     ctx.set(
-        js_string!("direction"),
-        JsValue::from(js_string!(direction)),
+        js_string!("deviceName"),
+        JsValue::from(js_string!(msg.device.device_name.clone())),
         false,
-        &mut context,
+        context,
     )
     .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
 
-    if let Option::Some(device) = &msg.device {
-        ctx.set(
-            js_string!("deviceName"),
-            JsValue::from(js_string!(device.device_name.clone())),
-            false,
-            &mut context,
-        )
-        .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
-    }
+    let timestamp = msg
+        .timestamp
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0.0, |d| d.as_millis() as f64);
+    ctx.set(
+        js_string!("timestamp"),
+        JsValue::from(timestamp),
+        false,
+        context,
+    )
+    .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
 
-    let args: [JsValue; 1] = [JsValue::Object(ctx)];
-    let this = JsValue::Undefined;
+    return Result::Ok(ctx);
+}
 
-    let results = key_handler
-        .callback
-        .call(&this, &args, &mut context)
-        .map_err(|err| RekeyError::GenericError(format!("failed to run callback: {}", err)))?;
-    if key_handler.intercept == false || results.to_boolean() == false {
-        return Result::Ok(SkipInput::DontSkip);
-    } else {
-        return Result::Ok(SkipInput::Skip);
+fn thread_handle_device_change_message(
+    msg: DeviceChangeMessage,
+    scripts: &Vec<Script>,
+) -> Result<(), RekeyError> {
+    let key_handlers = get_device_change_handlers(scripts)?;
+    return thread_run_device_change_handlers(&msg, key_handlers);
+}
+
+// handlers registered since the last call get an arrival for each attached device, so scripts
+// loaded or reloaded after their devices were attached see them too
+fn thread_replay_attached_devices(
+    scripts: &Vec<Script>,
+    replayed_handler_ids: &mut Vec<i32>,
+) -> Result<(), RekeyError> {
+    let key_handlers = get_device_change_handlers(scripts)?;
+    replayed_handler_ids.retain(|id| key_handlers.iter().any(|(_, h)| h.id == *id));
+    let key_handlers: Vec<(&Script, Arc<KeyHandler>)> = key_handlers
+        .into_iter()
+        .filter(|(_, key_handler)| !replayed_handler_ids.contains(&key_handler.id))
+        .collect();
+    if key_handlers.is_empty() || CHECK_MODE.load(Ordering::Relaxed) {
+        return Result::Ok(());
     }
+    replayed_handler_ids.extend(key_handlers.iter().map(|(_, key_handler)| key_handler.id));
+    for device in devices_attached()? {
+        let msg = DeviceChangeMessage {
+            device,
+            arrived: true,
+        };
+        thread_run_device_change_handlers(&msg, key_handlers.clone())?;
+    }
+    return Result::Ok(());
 }
 
-fn load_scripts<'a>(script_dir: PathBuf) -> Result<Vec<Script<'a>>, RekeyError> {
-    let mut results: Vec<Script> = vec![];
-    for entry in fs::read_dir(&script_dir)? {
-        let entry = entry?;
-        let entry_path = entry.path();
-        if entry_path
-            .extension()
-            .unwrap_or_default()
-            .to_str()
-            .unwrap_or_default()
-            != "js"
+// copies the handlers so callbacks are free to register and unregister handlers
+fn get_device_change_handlers(
+    scripts: &Vec<Script>,
+) -> Result<Vec<(&Script, Arc<KeyHandler>)>, RekeyError> {
+    let mut key_handlers: Vec<(&Script, Arc<KeyHandler>)> = vec![];
+    for script in scripts {
+        let script_key_handlers = script.key_handlers.lock().map_err(|err| {
+            RekeyError::GenericError(format!("failed to lock key handlers: {}", err))
+        })?;
+        for key_handler in script_key_handlers.iter() {
+            if key_handler.kind == InputKind::DeviceChange {
+                key_handlers.push((script, Arc::clone(key_handler)));
+            }
+        }
+    }
+    key_handlers.sort_by(|(_, a), (_, b)| b.priority.cmp(&a.priority));
+    return Result::Ok(key_handlers);
+}
+
+fn thread_run_device_change_handlers(
+    msg: &DeviceChangeMessage,
+    key_handlers: Vec<(&Script, Arc<KeyHandler>)>,
+) -> Result<(), RekeyError> {
+    let mut handler_times = EventHandlerTimes::new();
+    for (script, key_handler) in key_handlers {
+        if script.is_disabled() || !is_key_handler_registered(&script.key_handlers, key_handler.id)?
         {
             continue;
         }
-        debug!("loading script: {}", entry_path.display());
-
-        let mut context = Context::default();
-        let key_handlers: Arc<Mutex<Vec<KeyHandler>>> = Arc::new(Mutex::new(vec![]));
-        let timers: Arc<Mutex<Vec<Timer>>> = Arc::new(Mutex::new(vec![]));
-        initialize_context(&mut context, &key_handlers, &timers)?;
-
-        let script_path = &entry_path.as_path();
-        let source = Source::from_filepath(script_path)
-            .map_err(|err| RekeyError::GenericError(format!("failed to load script: {}", err)))?;
-        context.eval(source).map_err(|err| {
-            RekeyError::GenericError(format!(
-                "failed to evaluate script {}: {}",
-                entry_path.display(),
+        let start = Instant::now();
+        let result = thread_run_device_change_handler_callback(msg, script, &key_handler);
+        if !matches!(result, Result::Ok(false)) {
+            handler_times.add(script, start.elapsed());
+        }
+        if let Result::Err(err) = result {
+            debug!(
+                "device change handler failed in {}: {}",
+                script.path.display(),
                 err
-            ))
-        })?;
-        results.push(Script {
-            context: Arc::new(Mutex::new(context)),
-            key_handlers,
-            timers,
-        });
+            );
+        }
     }
-    return Result::Ok(results);
+    return thread_handlers_finished(handler_times);
 }
 
-fn initialize_context(
-    context: &mut Context<'_>,
-    key_handlers: &Arc<Mutex<Vec<KeyHandler>>>,
-    timers: &Arc<Mutex<Vec<Timer>>>,
-) -> Result<(), RekeyError> {
-    let console = js::console::Console::init(context);
-    context
-        .register_global_property(
-            js_string!(js::console::Console::NAME),
-            console,
-            Attribute::all(),
-        )
-        .map_err(|err| RekeyError::GenericError(format!("failed to register console: {}", err)))?;
+// returns whether the callback was called
+fn thread_run_device_change_handler_callback(
+    msg: &DeviceChangeMessage,
+    script: &Script,
+    key_handler: &KeyHandler,
+) -> Result<bool, RekeyError> {
+    let mut context = script
+        .context
+        .lock()
+        .map_err(|err| RekeyError::GenericError(format!("failed to lock context: {}", err)))?;
 
-    js::timer::Timer::init(context, timers)?;
+    if !device_filter_matches(&key_handler.device_filter, &msg.device, &mut context)? {
+        return Result::Ok(false);
+    }
 
-    for vkey in VKEY_LOOKUP_BY_NAME.values() {
-        let name = format!("VK_{}", vkey.name.to_ascii_uppercase());
-        context
+    let event = JsObject::default();
+    event
+        .set(
+            js_string!("type"),
+            JsValue::from(js_string!(if msg.arrived { "arrival" } else { "removal" })),
+            false,
+            &mut context,
+        )
+        .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
+    let device = create_device_object(&msg.device, &mut context)?;
+    event
+        .set(
+            js_string!("device"),
+            JsValue::Object(device),
+            false,
+            &mut context,
+        )
+        .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
+
+    let args: [JsValue; 1] = [JsValue::Object(event)];
+    let result = key_handler
+        .callback
+        .call(&JsValue::Undefined, &args, &mut context)
+        .map_err(|err| RekeyError::GenericError(format!("failed to run callback: {}", err)));
+    // run promise jobs even on error, other callbacks may have queued jobs
+    context.run_jobs();
+    result?;
+    return Result::Ok(true);
+}
+
+// the DeviceInfo of rekey-api.js, properties which aren't known are undefined
+fn create_device_object(
+    device: &Device,
+    context: &mut Context<'_>,
+) -> Result<JsObject, RekeyError> {
+    fn optional<T: Into<JsValue>>(value: Option<T>) -> JsValue {
+        return value.map(|v| v.into()).unwrap_or(JsValue::Undefined);
+    }
+
+    let properties: [(&str, JsValue); 9] = [
+        ("handle", JsValue::from(device.hdevice.0 as f64)),
+        (
+            "path",
+            JsValue::from(js_string!(device.device_name.clone())),
+        ),
+        (
+            "type",
+            optional(
+                device
+                    .device_type
+                    .map(|device_type| js_string!(device_type.to_string())),
+            ),
+        ),
+        ("vendorId", optional(device.vendor_id)),
+        ("productId", optional(device.product_id)),
+        ("interface", optional(device.interface)),
+        (
+            "productName",
+            optional(device.product_name.clone().map(|name| js_string!(name))),
+        ),
+        (
+            "usagePage",
+            optional(device.hid_usage.map(|hid_usage| hid_usage.usage_page)),
+        ),
+        (
+            "usage",
+            optional(device.hid_usage.map(|hid_usage| hid_usage.usage)),
+        ),
+    ];
+
+    let obj = JsObject::default();
+    for (name, value) in properties {
+        obj.set(js_string!(name), value, false, context)
+            .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
+    }
+    return Result::Ok(obj);
+}
+
+fn thread_should_buffer_unknown_device(
+    kind: InputKind,
+    vkey_code: u16,
+    modifiers: &KeyModifiers,
+    scripts: &Vec<Script>,
+) -> Result<bool, RekeyError> {
+    for script in scripts {
+        let key_handlers = script.key_handlers.lock().map_err(|err| {
+            RekeyError::GenericError(format!("failed to lock key handlers: {}", err))
+        })?;
+        for key_handler in key_handlers.iter() {
+            if key_handler.kind == kind
+                && key_filter_matches(&key_handler.key_filter, vkey_code, modifiers)
+                && key_handler.device_filter_decision(script, false, true)?
+                    == DeviceFilterDecision::Buffer
+            {
+                return Result::Ok(true);
+            }
+        }
+    }
+    return Result::Ok(false);
+}
+
+fn is_key_handler_registered(
+    key_handlers: &Arc<Mutex<Vec<Arc<KeyHandler>>>>,
+    id: i32,
+) -> Result<bool, RekeyError> {
+    let key_handlers = key_handlers
+        .lock()
+        .map_err(|err| RekeyError::GenericError(format!("failed to lock key handlers: {}", err)))?;
+    return Result::Ok(key_handlers.iter().any(|key_handler| key_handler.id == id));
+}
+
+fn thread_run_key_handler_callbacks(
+    msg: &InputMessage,
+    script: &Script,
+    key_handler: &KeyHandler,
+) -> Result<KeyHandlerResult, RekeyError> {
+    if key_handler.kind != msg.kind
+        || (msg.details.self_injected && !key_handler.self_injected)
+        || !key_filter_matches(&key_handler.key_filter, msg.vkey_code, &msg.modifiers)
+    {
+        return Result::Ok(KeyHandlerResult::not_handled());
+    }
+
+    // events from an unknown device have already had a chance to be buffered
+    let decision = key_handler.device_filter_decision(script, msg.device.is_some(), false)?;
+    if decision == DeviceFilterDecision::Skip {
+        return Result::Ok(KeyHandlerResult::not_handled());
+    }
+
+    let mut context = script
+        .context
+        .lock()
+        .map_err(|err| RekeyError::GenericError(format!("failed to lock context: {}", err)))?;
+
+    if let Option::Some(device) = &msg.device {
+        if decision == DeviceFilterDecision::TestFilter
+            && !device_filter_matches(&key_handler.device_filter, device, &mut context)?
+        {
+            return Result::Ok(KeyHandlerResult::not_handled());
+        }
+    }
+
+    let event = match msg.kind {
+        InputKind::Keyboard => create_key_event(msg, &mut context)?,
+        InputKind::Mouse => create_mouse_event(msg, &mut context)?,
+        InputKind::Hid | InputKind::DeviceChange => {
+            return Result::Ok(KeyHandlerResult::not_handled());
+        }
+    };
+    let args: [JsValue; 1] = [JsValue::Object(event)];
+    let this = JsValue::Undefined;
+
+    let results = key_handler
+        .callback
+        .call(&this, &args, &mut context)
+        .map_err(|err| RekeyError::GenericError(format!("failed to run callback: {}", err)));
+    // run promise jobs even on error, other callbacks may have queued jobs
+    context.run_jobs();
+    let results = results?;
+
+    // callbacks can either return a boolean or { skip: boolean, stopPropagation: boolean }
+    let (skip, stop_propagation) = if let Option::Some(results) = results.as_object() {
+        let skip = results
+            .get("skip", &mut context)
+            .map_err(|err| RekeyError::GenericError(format!("failed to get skip: {}", err)))?;
+        let stop_propagation = results
+            .get("stopPropagation", &mut context)
+            .map_err(|err| {
+                RekeyError::GenericError(format!("failed to get stopPropagation: {}", err))
+            })?;
+        (skip.to_boolean(), stop_propagation.to_boolean())
+    } else {
+        (results.to_boolean(), false)
+    };
+
+    return Result::Ok(KeyHandlerResult {
+        skip: if key_handler.intercept && skip {
+            SkipInput::Skip
+        } else {
+            SkipInput::DontSkip
+        },
+        stop_propagation,
+        called: true,
+    });
+}
+
+fn create_key_event(msg: &InputMessage, context: &mut Context<'_>) -> Result<JsObject, RekeyError> {
+    let ctx = JsObject::default();
+
+    ctx.set(
+        js_string!("vKeyCode"),
+        JsValue::from(msg.vkey_code),
+        false,
+        context,
+    )
+    .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
+
+    if let Option::Some(key) = VKEY_LOOKUP_BY_CODE.get(&msg.vkey_code) {
+        ctx.set(
+            js_string!("key"),
+            JsValue::from(key.name.to_string()),
+            false,
+            context,
+        )
+        .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
+    }
+
+    if let Option::Some(ch) = char_from_vcode(msg.vkey_code) {
+        ctx.set(js_string!("ch"), JsValue::from(ch), false, context)
+            .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
+    }
+
+    ctx.set(
+        js_string!("scanCode"),
+        JsValue::from(msg.details.scan_code),
+        false,
+        context,
+    )
+    .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
+
+    ctx.set(
+        js_string!("extended"),
+        JsValue::from(msg.details.extended),
+        false,
+        context,
+    )
+    .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
+
+    ctx.set(
+        js_string!("isRepeat"),
+        JsValue::from(msg.details.is_repeat),
+        false,
+        context,
+    )
+    .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
+
+    set_common_event_properties(&ctx, msg, context)?;
+    return Result::Ok(ctx);
+}
+
+fn create_mouse_event(
+    msg: &InputMessage,
+    context: &mut Context<'_>,
+) -> Result<JsObject, RekeyError> {
+    let ctx = JsObject::default();
+
+    if let Option::Some(button) = mouse_button_name(msg.vkey_code) {
+        ctx.set(
+            js_string!("button"),
+            JsValue::from(js_string!(button)),
+            false,
+            context,
+        )
+        .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
+    }
+
+    if msg.vkey_code == MOUSE_WHEEL_CODE || msg.vkey_code == MOUSE_HWHEEL_CODE {
+        ctx.set(
+            js_string!("wheel"),
+            JsValue::from(msg.wheel as f64 / WHEEL_DELTA as f64),
+            false,
+            context,
+        )
+        .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
+    }
+
+    set_common_event_properties(&ctx, msg, context)?;
+    return Result::Ok(ctx);
+}
+
+fn set_common_event_properties(
+    ctx: &JsObject,
+    msg: &InputMessage,
+    context: &mut Context<'_>,
+) -> Result<(), RekeyError> {
+    let direction = format!("{}", msg.direction);
+    let direction = direction.to_lowercase();
+
+    ctx.set(
+        js_string!("direction"),
+        JsValue::from(js_string!(direction)),
+        false,
+        context,
+    )
+    .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
+
+    if let Option::Some(device) = &msg.device {
+        ctx.set(
+            js_string!("deviceName"),
+            JsValue::from(js_string!(device.device_name.clone())),
+            false,
+            context,
+        )
+        .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
+    }
+
+    ctx.set(
+        js_string!("injected"),
+        JsValue::from(msg.details.injected),
+        false,
+        context,
+    )
+    .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
+
+    ctx.set(
+        js_string!("selfInjected"),
+        JsValue::from(msg.details.self_injected),
+        false,
+        context,
+    )
+    .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
+
+    // milliseconds since the unix epoch, the same as Date.now()
+    let timestamp = msg
+        .details
+        .timestamp
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0.0, |d| d.as_millis() as f64);
+    ctx.set(
+        js_string!("timestamp"),
+        JsValue::from(timestamp),
+        false,
+        context,
+    )
+    .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
+
+    let modifiers = msg.modifiers.to_js_object(context);
+    ctx.set(
+        js_string!("modifiers"),
+        JsValue::Object(modifiers),
+        false,
+        context,
+    )
+    .map_err(|err| RekeyError::GenericError(format!("failed to set: {}", err)))?;
+
+    return Result::Ok(());
+}
+
+fn device_filter_matches(
+    device_filter: &KeyHandlerDeviceFilter,
+    device: &Device,
+    context: &mut Context<'_>,
+) -> Result<bool, RekeyError> {
+    match device_filter {
+        KeyHandlerDeviceFilter::All => {
+            return Result::Ok(true);
+        }
+        KeyHandlerDeviceFilter::Contains(contains_str) => {
+            return Result::Ok(device.device_name.contains(contains_str));
+        }
+        KeyHandlerDeviceFilter::RegExp(regexp) => {
+            return regexp
+                .test(js_string!(device.device_name.clone()), context)
+                .map_err(|err| {
+                    RekeyError::GenericError(format!("failed to test device filter: {}", err))
+                });
+        }
+        KeyHandlerDeviceFilter::Ids {
+            vendor_id,
+            product_id,
+            interface,
+        } => {
+            fn id_matches(expected: &Option<u16>, actual: &Option<u16>) -> bool {
+                return expected.is_none() || expected == actual;
+            }
+            return Result::Ok(
+                id_matches(vendor_id, &device.vendor_id)
+                    && id_matches(product_id, &device.product_id)
+                    && id_matches(interface, &device.interface),
+            );
+        }
+        KeyHandlerDeviceFilter::Any(device_filters) => {
+            for device_filter in device_filters {
+                if device_filter_matches(device_filter, device, context)? {
+                    return Result::Ok(true);
+                }
+            }
+            return Result::Ok(false);
+        }
+    }
+}
+
+fn key_filter_matches(
+    key_filter: &KeyHandlerKeyFilter,
+    vkey_code: u16,
+    modifiers: &KeyModifiers,
+) -> bool {
+    match key_filter {
+        KeyHandlerKeyFilter::All => {
+            return true;
+        }
+        KeyHandlerKeyFilter::Keys(keys) => {
+            return keys
+                .iter()
+                .any(|key| key_filter_key_matches(key, vkey_code, modifiers));
+        }
+    }
+}
+
+fn key_filter_key_matches(key: &KeyFilterKey, vkey_code: u16, modifiers: &KeyModifiers) -> bool {
+    if !vkey_code_matches(key.vkey_code, vkey_code) {
+        return false;
+    }
+
+    if let Option::Some(expected) = key.modifiers {
+        let mut actual = modifiers.to_key_filter_modifiers();
+        let mut expected = expected;
+
+        // the key being pressed may itself be a modifier, don't require it to be held as well
+        if vkey_code_matches(VK_SHIFT.0, vkey_code) {
+            actual.shift = false;
+            expected.shift = false;
+        } else if vkey_code_matches(VK_CONTROL.0, vkey_code) {
+            actual.ctrl = false;
+            expected.ctrl = false;
+        } else if vkey_code_matches(VK_MENU.0, vkey_code) {
+            actual.alt = false;
+            expected.alt = false;
+        } else if vkey_code == VK_LWIN.0 || vkey_code == VK_RWIN.0 {
+            actual.win = false;
+            expected.win = false;
+        }
+
+        return actual == expected;
+    }
+
+    return true;
+}
+
+fn vkey_code_matches(filter_vkey_code: u16, vkey_code: u16) -> bool {
+    if filter_vkey_code == vkey_code {
+        return true;
+    }
+
+    // the generic modifier keys match both their left and right variants
+    if filter_vkey_code == VK_SHIFT.0 {
+        return vkey_code == VK_LSHIFT.0 || vkey_code == VK_RSHIFT.0;
+    }
+    if filter_vkey_code == VK_CONTROL.0 {
+        return vkey_code == VK_LCONTROL.0 || vkey_code == VK_RCONTROL.0;
+    }
+    if filter_vkey_code == VK_MENU.0 {
+        return vkey_code == VK_LMENU.0 || vkey_code == VK_RMENU.0;
+    }
+    return false;
+}
+
+// scripts that fail to load are recorded in the script statuses and don't stop other scripts
+fn load_scripts<'a>(
+    script_dir: &PathBuf,
+    clock: &Arc<dyn Clock>,
+) -> Result<Vec<Script<'a>>, RekeyError> {
+    let mut results: Vec<Script> = vec![];
+    let mut statuses: Vec<ScriptStatus> = vec![];
+    for entry_path in list_entry_scripts(script_dir)? {
+        match load_script(script_dir, entry_path.clone(), clock) {
+            Result::Ok(script) => {
+                statuses.push(ScriptStatus::new(
+                    entry_path,
+                    ScriptState::Loaded,
+                    Option::None,
+                ));
+                results.push(script);
+            }
+            Result::Err(err) => {
+                statuses.push(ScriptStatus::new(
+                    entry_path,
+                    ScriptState::Failed,
+                    Option::Some(format!("{}", err)),
+                ));
+            }
+        }
+    }
+    set_script_statuses(statuses)?;
+    return Result::Ok(results);
+}
+
+fn set_script_statuses(statuses: Vec<ScriptStatus>) -> Result<(), RekeyError> {
+    for status in &statuses {
+        info!("script status: {}", status);
+    }
+    let mut script_statuses = SCRIPT_STATUSES.lock().map_err(|err| {
+        RekeyError::GenericError(format!("could not get script statuses lock: {}", err))
+    })?;
+    *script_statuses = statuses;
+    return Result::Ok(());
+}
+
+fn update_script_status(
+    path: &PathBuf,
+    state: ScriptState,
+    error: Option<String>,
+) -> Result<(), RekeyError> {
+    let mut script_statuses = SCRIPT_STATUSES.lock().map_err(|err| {
+        RekeyError::GenericError(format!("could not get script statuses lock: {}", err))
+    })?;
+    if let Option::Some(status) = script_statuses
+        .iter_mut()
+        .find(|status| status.path == *path)
+    {
+        status.line = error.as_deref().and_then(ScriptStatus::error_line);
+        status.state = state;
+        status.error = error;
+        info!("script status: {}", status);
+    }
+    return Result::Ok(());
+}
+
+pub fn scripts_get_statuses() -> Result<Vec<ScriptStatus>, RekeyError> {
+    let script_statuses = SCRIPT_STATUSES.lock().map_err(|err| {
+        RekeyError::GenericError(format!("could not get script statuses lock: {}", err))
+    })?;
+    return Result::Ok(script_statuses.clone());
+}
+
+// sorted so scripts, and handlers with the same priority, run in a deterministic order
+fn list_entry_scripts(script_dir: &PathBuf) -> Result<Vec<PathBuf>, RekeyError> {
+    let mut entry_paths: Vec<PathBuf> = vec![];
+    for entry in fs::read_dir(script_dir)? {
+        let entry_path = entry?.path();
+        // only top-level files are entry points, subdirectories hold modules to be imported
+        if !entry_path.is_file()
+            || entry_path
+                .extension()
+                .unwrap_or_default()
+                .to_str()
+                .unwrap_or_default()
+                != "js"
+            || entry_path.file_name().unwrap_or_default() == REKEY_API_JS_FILENAME
+        {
+            continue;
+        }
+        entry_paths.push(entry_path);
+    }
+    entry_paths.sort();
+    return Result::Ok(entry_paths);
+}
+
+fn load_script<'a>(
+    script_dir: &PathBuf,
+    entry_path: PathBuf,
+    clock: &Arc<dyn Clock>,
+) -> Result<Script<'a>, RekeyError> {
+    debug!("loading script: {}", entry_path.display());
+
+    // shared by the context and the script, the modules it holds are released when the script
+    // is dropped
+    let module_loader = Rc::new(ScriptModuleLoader::new(script_dir)?);
+    let context_module_loader: Rc<dyn ModuleLoader> = module_loader.clone();
+    let mut context = Context::builder()
+        .module_loader(context_module_loader)
+        .build()
+        .map_err(|err| RekeyError::GenericError(format!("failed to create context: {}", err)))?;
+    context
+        .runtime_limits_mut()
+        .set_loop_iteration_limit(LOOP_ITERATION_LIMIT);
+    let key_handlers: Arc<Mutex<Vec<Arc<KeyHandler>>>> = Arc::new(Mutex::new(vec![]));
+    let default_unknown_device: Arc<Mutex<Option<UnknownDevicePolicy>>> =
+        Arc::new(Mutex::new(Option::None));
+    let timers: ScriptTimers = Arc::new(Mutex::new(TimerQueue::new()));
+    initialize_context(
+        &mut context,
+        &key_handlers,
+        &default_unknown_device,
+        &timers,
+        clock,
+    )?;
+
+    let result = evaluate_module(&entry_path, &module_loader, &mut context).map_err(|err| {
+        RekeyError::GenericError(format!(
+            "failed to evaluate script {}: {}",
+            entry_path.display(),
+            err
+        ))
+    });
+    let files = get_script_files(&entry_path, &module_loader);
+    let script = Script {
+        path: entry_path,
+        files,
+        context: Arc::new(Mutex::new(context)),
+        module_loader,
+        key_handlers,
+        default_unknown_device,
+        timers,
+        overruns: Cell::new(0),
+        disabled: Cell::new(false),
+    };
+    result?;
+    return Result::Ok(script);
+}
+
+// the entry script and every module it imported, with their modified times to detect changes
+fn get_script_files(
+    entry_path: &PathBuf,
+    module_loader: &ScriptModuleLoader,
+) -> Vec<(PathBuf, Option<SystemTime>)> {
+    let mut paths = module_loader.paths();
+    // the entry may not have been registered with the loader if it failed to parse
+    paths.push(entry_path.clone());
+    return paths
+        .into_iter()
+        .map(|path| {
+            let modified = get_modified_time(&path);
+            return (path, modified);
+        })
+        .collect();
+}
+
+fn get_modified_time(path: &PathBuf) -> Option<SystemTime> {
+    return fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok();
+}
+
+fn thread_reload_changed_scripts<'a>(
+    scripts: &mut Vec<Script<'a>>,
+    script_dir: &PathBuf,
+    clock: &Arc<dyn Clock>,
+) -> Result<Vec<RekeyError>, RekeyError> {
+    let entry_paths = list_entry_scripts(script_dir)?;
+    let mut errors: Vec<RekeyError> = vec![];
+    let previous_statuses = scripts_get_statuses()?;
+    let mut statuses: Vec<ScriptStatus> = vec![];
+
+    scripts.retain(|script| {
+        if entry_paths.contains(&script.path) {
+            return true;
+        }
+        debug!("unloading script: {}", script.path.display());
+        return false;
+    });
+
+    for entry_path in entry_paths {
+        let previous_status = previous_statuses
+            .iter()
+            .find(|status| status.path == entry_path);
+        let existing = scripts.iter().position(|script| script.path == entry_path);
+        if let Option::Some(i) = existing {
+            let changed = scripts[i]
+                .files
+                .iter()
+                .any(|(path, modified)| get_modified_time(path) != *modified);
+            if !changed {
+                if let Option::Some(previous_status) = previous_status {
+                    statuses.push(previous_status.clone());
+                }
+                continue;
+            }
+        }
+
+        // keep the previous version of the script running if the new version fails to load
+        match load_script(script_dir, entry_path.clone(), clock) {
+            Result::Ok(script) => {
+                if let Option::Some(i) = existing {
+                    scripts[i] = script;
+                } else {
+                    scripts.push(script);
+                }
+                statuses.push(ScriptStatus::new(
+                    entry_path,
+                    ScriptState::Loaded,
+                    Option::None,
+                ));
+            }
+            Result::Err(err) => {
+                let error = format!("{}", err);
+                // scripts which failed before are retried on every change, only report new errors
+                if previous_status.and_then(|status| status.error.as_ref()) != Option::Some(&error)
+                {
+                    errors.push(err);
+                }
+                let state = if existing.is_some() {
+                    ScriptState::Loaded
+                } else {
+                    ScriptState::Failed
+                };
+                statuses.push(ScriptStatus::new(entry_path, state, Option::Some(error)));
+            }
+        }
+    }
+    scripts.sort_by(|a, b| a.path.cmp(&b.path));
+    set_script_statuses(statuses)?;
+
+    return Result::Ok(errors);
+}
+
+fn evaluate_module(
+    path: &PathBuf,
+    module_loader: &ScriptModuleLoader,
+    context: &mut Context<'_>,
+) -> Result<(), JsError> {
+    let source = Source::from_filepath(path).map_err(|err| {
+        JsNativeError::error().with_message(format!("failed to load script: {}", err))
+    })?;
+    let module = Module::parse(source, Option::None, context)?;
+    // register the entry module so imports of it get the same instance
+    if let Result::Ok(path) = path.canonicalize() {
+        module_loader.insert(path, module.clone());
+    }
+
+    let promise = module.load_link_evaluate(context)?;
+    context.run_jobs();
+    match promise.state()? {
+        PromiseState::Rejected(err) => {
+            return Result::Err(JsError::from_opaque(err));
+        }
+        // a top-level await on a timer or input never finishes without the scripts thread
+        PromiseState::Pending if CHECK_MODE.load(Ordering::Relaxed) => {
+            return Result::Err(JsError::from(JsNativeError::error().with_message(
+                "script is still waiting on a top-level await, --check doesn't run timers or input",
+            )));
+        }
+        _ => {}
+    }
+    return Result::Ok(());
+}
+
+fn initialize_context(
+    context: &mut Context<'_>,
+    key_handlers: &Arc<Mutex<Vec<Arc<KeyHandler>>>>,
+    default_unknown_device: &Arc<Mutex<Option<UnknownDevicePolicy>>>,
+    timers: &ScriptTimers,
+    clock: &Arc<dyn Clock>,
+) -> Result<(), RekeyError> {
+    let console = js::console::Console::init(context);
+    context
+        .register_global_property(
+            js_string!(js::console::Console::NAME),
+            console,
+            Attribute::all(),
+        )
+        .map_err(|err| RekeyError::GenericError(format!("failed to register console: {}", err)))?;
+
+    js::timer::Timer::init(context, timers, clock)?;
+
+    for vkey in VKEY_LOOKUP_BY_NAME.values() {
+        let name = format!("VK_{}", vkey.name.to_ascii_uppercase());
+        context
             .register_global_property(
                 js_string!(name),
                 JsValue::Integer(vkey.code.0 as i32),
@@ -354,33 +1730,100 @@ fn initialize_context(
             })?;
     }
 
+    register_register_function(context, key_handlers, InputKind::Keyboard)?;
+    register_register_function(context, key_handlers, InputKind::Mouse)?;
+    register_register_function(context, key_handlers, InputKind::Hid)?;
+    register_register_function(context, key_handlers, InputKind::DeviceChange)?;
+
     context
-        .register_global_callable("rekeyRegister", 0, unsafe {
+        .register_global_callable("rekeyUnregister", 0, unsafe {
             let my_key_handlers = Arc::clone(key_handlers);
-            NativeFunction::from_closure(move |this, args, context| {
-                match handle_register(this, args, context) {
-                    Result::Ok(key_handler) => {
-                        let mut my_key_handlers = my_key_handlers.lock().map_err(|err| {
-                            JsNativeError::error()
-                                .with_message(format!("could not get key handlers lock: {}", err))
-                        })?;
-                        my_key_handlers.push(key_handler);
-                        return Result::Ok(JsValue::Undefined);
-                    }
-                    Result::Err(err) => {
-                        return Result::Err(err);
-                    }
+            NativeFunction::from_closure(move |_this, args, context| {
+                if args.len() != 1 {
+                    return Result::Err(JsError::from(JsNativeError::error().with_message(
+                        format!(
+                            "invalid arguments for rekeyUnregister, expected 1 found {}",
+                            args.len()
+                        ),
+                    )));
+                }
+
+                let arg0 = args.get(0).unwrap();
+                let id = if arg0.is_number() {
+                    arg0.to_i32(context)?
+                } else if let Option::Some(handle) = arg0.as_object() {
+                    handle.get("id", context)?.to_i32(context)?
+                } else {
+                    return Result::Err(JsError::from(JsNativeError::error().with_message(
+                        "invalid arguments, expected rekeyUnregister(id: number | RegisterHandle)",
+                    )));
                 };
+                return unregister_key_handler(&my_key_handlers, id);
             })
         })
         .map_err(|err| {
-            RekeyError::GenericError(format!("failed to register rekeyRegister: {}", err))
+            RekeyError::GenericError(format!("failed to register rekeyUnregister: {}", err))
+        })?;
+
+    context
+        .register_global_callable("rekeySetDefaultUnknownDevice", 0, unsafe {
+            let my_default_unknown_device = Arc::clone(default_unknown_device);
+            NativeFunction::from_closure(move |_this, args, _context| {
+                if args.len() != 1 {
+                    return Result::Err(JsError::from(JsNativeError::error().with_message(
+                        format!(
+                            "invalid arguments for rekeySetDefaultUnknownDevice, expected 1 found {}",
+                            args.len()
+                        ),
+                    )));
+                }
+                let policy = parse_unknown_device_policy(args.get(0).unwrap())?;
+                let mut my_default_unknown_device =
+                    my_default_unknown_device.lock().map_err(|err| {
+                        JsNativeError::error().with_message(format!(
+                            "could not get unknown device policy lock: {}",
+                            err
+                        ))
+                    })?;
+                *my_default_unknown_device = Option::Some(policy);
+                return Result::Ok(JsValue::Undefined);
+            })
+        })
+        .map_err(|err| {
+            RekeyError::GenericError(format!(
+                "failed to register rekeySetDefaultUnknownDevice: {}",
+                err
+            ))
         })?;
 
     context
         .register_global_callable("sendKey", 0, NativeFunction::from_fn_ptr(handle_send_key))
         .map_err(|err| RekeyError::GenericError(format!("failed to register sendKey: {}", err)))?;
 
+    context
+        .register_global_callable("sendText", 0, NativeFunction::from_fn_ptr(handle_send_text))
+        .map_err(|err| RekeyError::GenericError(format!("failed to register sendText: {}", err)))?;
+
+    context
+        .register_global_callable(
+            "sendMouse",
+            0,
+            NativeFunction::from_fn_ptr(handle_send_mouse),
+        )
+        .map_err(|err| {
+            RekeyError::GenericError(format!("failed to register sendMouse: {}", err))
+        })?;
+
+    context
+        .register_global_callable(
+            "getDevices",
+            0,
+            NativeFunction::from_fn_ptr(handle_get_devices),
+        )
+        .map_err(|err| {
+            RekeyError::GenericError(format!("failed to register getDevices: {}", err))
+        })?;
+
     context
         .register_global_callable(
             "getKeyState",
@@ -391,9 +1834,139 @@ fn initialize_context(
             RekeyError::GenericError(format!("failed to register getKeyState: {}", err))
         })?;
 
+    context
+        .eval(Source::from_bytes(include_str!("js/prelude.js")))
+        .map_err(|err| RekeyError::GenericError(format!("failed to evaluate prelude: {}", err)))?;
+
     return Result::Ok(());
 }
 
+pub fn scripts_set_handler_timeout(millis: u32) -> () {
+    HANDLER_TIMEOUT_MILLIS.store(millis, Ordering::Relaxed);
+}
+
+fn get_handler_timeout() -> Duration {
+    return Duration::from_millis(HANDLER_TIMEOUT_MILLIS.load(Ordering::Relaxed) as u64);
+}
+
+// waits for a response from the scripts thread without blocking input for longer than the
+// handler timeout
+fn recv_before_deadline<T>(
+    rx: &mpsc::Receiver<Result<T, RekeyError>>,
+    deadline: Instant,
+    default: T,
+) -> Result<T, RekeyError> {
+    match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        Result::Ok(result) => {
+            return result;
+        }
+        Result::Err(RecvTimeoutError::Timeout) => {
+            debug("timeout waiting for response from scripts thread");
+            return Result::Ok(default);
+        }
+        Result::Err(err) => {
+            return Result::Err(RekeyError::GenericError(format!(
+                "failed to receive response from input thread: {}",
+                err
+            )));
+        }
+    }
+}
+
+fn register_register_function(
+    context: &mut Context<'_>,
+    key_handlers: &Arc<Mutex<Vec<Arc<KeyHandler>>>>,
+    kind: InputKind,
+) -> Result<(), RekeyError> {
+    let name = kind.register_function_name();
+    context
+        .register_global_callable(name, 0, unsafe {
+            let my_key_handlers = Arc::clone(key_handlers);
+            NativeFunction::from_closure(move |this, args, context| {
+                match handle_register(this, args, context, kind) {
+                    Result::Ok(key_handler) => {
+                        let id = key_handler.id;
+                        {
+                            let mut my_key_handlers = my_key_handlers.lock().map_err(|err| {
+                                JsNativeError::error().with_message(format!(
+                                    "could not get key handlers lock: {}",
+                                    err
+                                ))
+                            })?;
+                            my_key_handlers.push(Arc::new(key_handler));
+                        }
+                        return Result::Ok(create_register_handle(id, &my_key_handlers, context));
+                    }
+                    Result::Err(err) => {
+                        return Result::Err(err);
+                    }
+                };
+            })
+        })
+        .map_err(|err| RekeyError::GenericError(format!("failed to register {}: {}", name, err)))?;
+    return Result::Ok(());
+}
+
+fn create_register_handle(
+    id: i32,
+    key_handlers: &Arc<Mutex<Vec<Arc<KeyHandler>>>>,
+    context: &mut Context<'_>,
+) -> JsValue {
+    let unregister = unsafe {
+        let my_key_handlers = Arc::clone(key_handlers);
+        NativeFunction::from_closure(move |_this, _args, _context| {
+            return unregister_key_handler(&my_key_handlers, id);
+        })
+    };
+
+    let handle = ObjectInitializer::new(context)
+        .property(js_string!("id"), JsValue::Integer(id), Attribute::READONLY)
+        .function(unregister, "unregister", 0)
+        .build();
+    return JsValue::Object(handle);
+}
+
+fn unregister_key_handler(
+    key_handlers: &Arc<Mutex<Vec<Arc<KeyHandler>>>>,
+    id: i32,
+) -> Result<JsValue, JsError> {
+    let mut key_handlers = key_handlers.lock().map_err(|err| {
+        JsNativeError::error().with_message(format!("could not get key handlers lock: {}", err))
+    })?;
+    let len = key_handlers.len();
+    key_handlers.retain(|key_handler| key_handler.id != id);
+    return Result::Ok(JsValue::from(key_handlers.len() != len));
+}
+
+fn handle_get_devices(
+    _this: &JsValue,
+    args: &[JsValue],
+    context: &mut Context<'_>,
+) -> Result<JsValue, JsError> {
+    if !args.is_empty() {
+        return Result::Err(JsError::from(JsNativeError::error().with_message(format!(
+            "invalid arguments for getDevices, expected 0 found {}",
+            args.len()
+        ))));
+    }
+    let devices = if CHECK_MODE.load(Ordering::Relaxed) {
+        vec![]
+    } else {
+        devices_list().map_err(|err| {
+            JsNativeError::error().with_message(format!("failed to list devices: {}", err))
+        })?
+    };
+    let mut device_objects = vec![];
+    for device in devices {
+        let device_object = create_device_object(&device, context).map_err(|err| {
+            JsNativeError::error().with_message(format!("failed to create device: {}", err))
+        })?;
+        device_objects.push(JsValue::Object(device_object));
+    }
+    let arr = JsArray::from_iter(device_objects, context);
+    return Result::Ok(JsValue::Object(arr.into()));
+}
+
 fn handle_get_key_state(
     _this: &JsValue,
     args: &[JsValue],
@@ -414,7 +1987,11 @@ fn handle_get_key_state(
     }
 
     let arg0 = arg0.to_uint16(context)?;
-    let key_state = unsafe { GetKeyState(arg0 as i32) as u32 };
+    let key_state = if CHECK_MODE.load(Ordering::Relaxed) {
+        0
+    } else {
+        unsafe { GetKeyState(arg0 as i32) as u32 }
+    };
 
     let result = JsObject::default();
 
@@ -475,95 +2052,132 @@ fn handle_send_key(
         }
         key_direction = arg1.as_string().unwrap().to_std_string_escaped();
     }
-    if key_direction != "both" && key_direction != "up" && key_direction != "down" {
-        return Result::Err(JsError::from(JsNativeError::error().with_message(format!(
-            "invalid second argument, expected sendKey(expr: string, direction?: 'up' | 'down')"
-        ))));
+    let key_expr = arg0.as_string().unwrap().to_std_string_escaped();
+    let direction = SendKeyDirection::parse(&key_direction)
+        .map_err(|err| JsError::from(JsNativeError::error().with_message(format!("{}", err))))?;
+
+    build_key_expr_inputs(&key_expr, direction)
+        .and_then(|inputs| send_inputs(&inputs))
+        .map_err(|err| JsError::from(JsNativeError::error().with_message(format!("{}", err))))?;
+
+    return Result::Ok(JsValue::Undefined);
+}
+
+fn handle_send_text(
+    _this: &JsValue,
+    args: &[JsValue],
+    _context: &mut Context<'_>,
+) -> Result<JsValue, JsError> {
+    if args.len() != 1 || !args.get(0).unwrap().is_string() {
+        return Result::Err(JsError::from(
+            JsNativeError::error()
+                .with_message("invalid arguments, expected sendText(text: string)"),
+        ));
     }
+    let text = args
+        .get(0)
+        .unwrap()
+        .as_string()
+        .unwrap()
+        .to_std_string_escaped();
 
-    let key_expr = arg0.as_string().unwrap().to_std_string_escaped();
+    send_inputs(&build_text_inputs(&text))
+        .map_err(|err| JsError::from(JsNativeError::error().with_message(format!("{}", err))))?;
 
-    let mut inputs: Vec<INPUT> = vec![];
-
-    fn add_key_to_input(
-        inputs: &mut Vec<INPUT>,
-        key_expr_part: &str,
-        up: bool,
-    ) -> Result<(), JsError> {
-        let r = to_virtual_key(key_expr_part).map_err(|err| {
-            JsError::from(
-                JsNativeError::error()
-                    .with_message(format!("could not covert key {}: {}", key_expr_part, err)),
-            )
-        })?;
+    return Result::Ok(JsValue::Undefined);
+}
 
-        if up {
-            inputs.push(create_input(r.vkey, true));
-        }
-        if r.ctrl {
-            inputs.push(create_input(VK_CONTROL, up));
-        }
-        if r.alt {
-            inputs.push(create_input(VK_MENU, up));
-        }
-        if r.shift {
-            inputs.push(create_input(VK_SHIFT, up));
-        }
-        if r.hankaku {
-            return Result::Err(JsError::from(
-                JsNativeError::error().with_message("could not handle hankaku"),
-            ));
+fn handle_send_mouse(
+    _this: &JsValue,
+    args: &[JsValue],
+    context: &mut Context<'_>,
+) -> Result<JsValue, JsError> {
+    if args.len() != 1 || !args.get(0).unwrap().is_object() {
+        return Result::Err(JsError::from(JsNativeError::error().with_message(
+            "invalid arguments, expected sendMouse(options: SendMouseOptions)",
+        )));
+    }
+    let options = args.get(0).unwrap().as_object().unwrap();
+
+    fn get_number(
+        options: &JsObject,
+        name: &str,
+        context: &mut Context<'_>,
+    ) -> Result<Option<i32>, JsError> {
+        let value = options.get(name, context)?;
+        if value.is_undefined() {
+            return Result::Ok(Option::None);
         }
-        if !up {
-            inputs.push(create_input(r.vkey, false));
+        if let Option::Some(n) = value.as_number() {
+            return Result::Ok(Option::Some(n as i32));
         }
-        return Result::Ok(());
+        return Result::Err(JsError::from(
+            JsNativeError::error().with_message(format!("invalid {}, expected a number", name)),
+        ));
     }
 
-    let key_expr_parts: Vec<&str> = key_expr.split("+").collect();
-    if key_direction == "both" || key_direction == "down" {
-        for key_expr_part in &key_expr_parts {
-            add_key_to_input(&mut inputs, key_expr_part, false)?;
-        }
-    }
+    let x = get_number(&options, "x", context)?;
+    let y = get_number(&options, "y", context)?;
+    let move_to = if x.is_some() || y.is_some() {
+        Option::Some(MouseMove {
+            x: x.unwrap_or(0),
+            y: y.unwrap_or(0),
+            absolute: options.get("absolute", context)?.to_boolean(),
+        })
+    } else {
+        Option::None
+    };
 
-    if key_direction == "both" || key_direction == "up" {
-        let key_expr_parts_rev: Vec<&str> = key_expr_parts.iter().copied().rev().collect();
-        for key_expr_part in key_expr_parts_rev {
-            add_key_to_input(&mut inputs, key_expr_part, true)?;
-        }
-    }
+    let button = options.get("button", context)?;
+    let button = if button.is_undefined() {
+        Option::None
+    } else {
+        let button = button.to_string(context)?.to_std_string_escaped();
+        let button = MouseButton::parse(&button).map_err(|err| {
+            JsError::from(JsNativeError::error().with_message(format!("{}", err)))
+        })?;
+        let direction = options.get("direction", context)?;
+        let direction = if direction.is_undefined() {
+            SendKeyDirection::Both
+        } else {
+            SendKeyDirection::parse(&direction.to_string(context)?.to_std_string_escaped())
+                .map_err(|err| {
+                    JsError::from(JsNativeError::error().with_message(format!("{}", err)))
+                })?
+        };
+        Option::Some((button, direction))
+    };
 
-    let input_size = size_of::<INPUT>();
-    unsafe {
-        let r = SendInput(&inputs, input_size as i32) as usize;
-        if r != inputs.len() {
-            return Result::Err(JsError::from(
-                JsNativeError::error().with_message("failed to send all inputs"),
-            ));
-        }
-    }
+    let action = MouseAction {
+        move_to,
+        button,
+        wheel: get_number(&options, "wheel", context)?.unwrap_or(0),
+        hwheel: get_number(&options, "hwheel", context)?.unwrap_or(0),
+    };
+    send_inputs(&build_mouse_inputs(&action, &get_virtual_screen_rect()))
+        .map_err(|err| JsError::from(JsNativeError::error().with_message(format!("{}", err))))?;
 
     return Result::Ok(JsValue::Undefined);
 }
 
-fn create_input(vkey: VIRTUAL_KEY, up: bool) -> INPUT {
-    let mut input = INPUT::default();
-    input.r#type = INPUT_KEYBOARD;
-    input.Anonymous = INPUT_0::default();
-    input.Anonymous.ki = KEYBDINPUT::default();
-    input.Anonymous.ki.wVk = vkey;
-    if up {
-        input.Anonymous.ki.dwFlags = KEYEVENTF_KEYUP;
-    }
-    return input;
-}
-
 fn handle_register(
     _this: &JsValue,
     args: &[JsValue],
     context: &mut Context<'_>,
+    kind: InputKind,
 ) -> Result<KeyHandler, JsError> {
+    let name = kind.register_function_name();
+    // rekeyOnDeviceChange(callback) is the same as passing empty options
+    let default_args: [JsValue; 2];
+    let args = if kind == InputKind::DeviceChange && args.len() == 1 {
+        default_args = [
+            JsValue::Object(JsObject::default()),
+            args.get(0).unwrap().clone(),
+        ];
+        &default_args[..]
+    } else {
+        args
+    };
     // options, callback
     if args.len() == 2 {
         let options = args.get(0).unwrap();
@@ -572,87 +2186,701 @@ fn handle_register(
         if options.is_object() && callback.is_callable() {
             let options = options.as_object().unwrap();
             let device_filter = options.get("deviceFilter", context)?;
-            let key_filter = options.get("keyFilter", context)?;
+            let key_filter = match kind {
+                InputKind::Keyboard => options.get("keyFilter", context)?,
+                InputKind::Mouse => options.get("buttonFilter", context)?,
+                InputKind::Hid | InputKind::DeviceChange => JsValue::Undefined,
+            };
+            let unknown_device = options.get("unknownDevice", context)?;
+            let priority = options.get("priority", context)?;
             let intercept = options.get("intercept", context)?;
+            let self_injected = options.get("selfInjected", context)?;
             let callback = callback.as_callable().unwrap();
 
-            let key_filter = if key_filter.is_undefined()
-                || (key_filter.is_string() && key_filter.as_string().unwrap() == "*")
-            {
-                KeyHandlerKeyFilter::All
+            let key_filter = match kind {
+                InputKind::Keyboard => parse_key_filter(&key_filter, context)?,
+                InputKind::Mouse => parse_button_filter(&key_filter, context)?,
+                InputKind::Hid | InputKind::DeviceChange => KeyHandlerKeyFilter::All,
+            };
+
+            let hid_usage = if kind == InputKind::Hid {
+                Option::Some(parse_hid_usage(options, context)?)
             } else {
-                return Result::Err(JsError::from(JsNativeError::error().with_message(
-                    "invalid keyFilter arguments for rekeyRegister, expected \"*\"",
-                )));
+                Option::None
             };
 
-            let device_filter = if device_filter.is_undefined() {
-                KeyHandlerDeviceFilter::All
-            } else if device_filter.is_string() {
-                let str = device_filter.as_string().unwrap().to_std_string_escaped();
-                if str == "*" {
-                    KeyHandlerDeviceFilter::All
-                } else {
-                    KeyHandlerDeviceFilter::Contains(str)
-                }
+            let device_filter = parse_device_filter(&device_filter, context)?;
+
+            let unknown_device = if unknown_device.is_undefined() {
+                Option::None
+            } else {
+                Option::Some(parse_unknown_device_policy(&unknown_device)?)
+            };
+
+            let priority = if priority.is_undefined() {
+                0
+            } else if priority.is_number() {
+                priority.to_i32(context)?
             } else {
                 return Result::Err(JsError::from(
                     JsNativeError::error()
-                        .with_message("invalid deviceFilter arguments for rekeyRegister."),
+                        .with_message(format!("invalid priority for {}, expected a number", name)),
                 ));
             };
 
             let intercept = intercept.to_boolean();
+            let self_injected = self_injected.to_boolean();
 
             return Result::Ok(KeyHandler {
+                id: NEXT_KEY_HANDLER_ID.fetch_add(1, Ordering::Relaxed),
+                kind,
+                hid_usage,
+                priority,
                 device_filter,
                 key_filter,
+                unknown_device,
                 intercept,
+                self_injected,
                 callback: callback.clone(),
             });
         } else {
-            return Result::Err(JsError::from(
-                JsNativeError::error()
-                    .with_message("invalid arguments, expected rekeyRegister(options: RegisterOptions, callback: (event) => boolean)"),
-            ));
+            return Result::Err(JsError::from(JsNativeError::error().with_message(format!(
+                "invalid arguments, expected {}(options: RegisterOptions, callback: (event) => boolean)",
+                name
+            ))));
         }
     } else {
         return Result::Err(JsError::from(JsNativeError::error().with_message(format!(
-            "invalid arguments for rekeyRegister, expected 2 found {}",
+            "invalid arguments for {}, expected 2 found {}",
+            name,
             args.len()
         ))));
     }
 }
 
+fn parse_hid_usage(options: &JsObject, context: &mut Context<'_>) -> Result<HidUsage, JsError> {
+    let usage_page = options.get("usagePage", context)?;
+    let usage = options.get("usage", context)?;
+    if !usage_page.is_number() || !usage.is_number() {
+        return Result::Err(JsError::from(JsNativeError::error().with_message(
+            "invalid arguments for rekeyRegisterHid, expected usagePage and usage numbers, e.g. { usagePage: 0x0C, usage: 0x01 }",
+        )));
+    }
+    let usage_page = usage_page.to_u32(context)?;
+    let usage = usage.to_u32(context)?;
+    if usage_page > u16::MAX as u32 || usage > u16::MAX as u32 {
+        return Result::Err(JsError::from(JsNativeError::error().with_message(
+            "invalid arguments for rekeyRegisterHid, usagePage and usage must be less than 0x10000",
+        )));
+    }
+    let hid_usage = HidUsage {
+        usage_page: usage_page as u16,
+        usage: usage as u16,
+    };
+    if raw_input_is_reserved_usage(&hid_usage) {
+        return Result::Err(JsError::from(JsNativeError::error().with_message(
+            "invalid arguments for rekeyRegisterHid, use rekeyRegister for keyboards and rekeyRegisterMouse for mice",
+        )));
+    }
+    return Result::Ok(hid_usage);
+}
+
+fn parse_device_filter(
+    device_filter: &JsValue,
+    context: &mut Context<'_>,
+) -> Result<KeyHandlerDeviceFilter, JsError> {
+    if device_filter.is_undefined() {
+        return Result::Ok(KeyHandlerDeviceFilter::All);
+    }
+
+    if device_filter.is_string() {
+        let str = device_filter.as_string().unwrap().to_std_string_escaped();
+        if str == "*" {
+            return Result::Ok(KeyHandlerDeviceFilter::All);
+        } else {
+            return Result::Ok(KeyHandlerDeviceFilter::Contains(str));
+        }
+    }
+
+    if let Option::Some(obj) = device_filter.as_object() {
+        if obj.is_regexp() {
+            return Result::Ok(KeyHandlerDeviceFilter::RegExp(JsRegExp::from_object(
+                obj.clone(),
+            )?));
+        }
+
+        if obj.is_array() {
+            let arr = JsArray::from_object(obj.clone())?;
+            let len = arr.length(context)?;
+            let mut device_filters: Vec<KeyHandlerDeviceFilter> = vec![];
+            for i in 0..len {
+                let item = arr.get(i, context)?;
+                if item.is_undefined() {
+                    return Result::Err(JsError::from(JsNativeError::error().with_message(
+                        "invalid deviceFilter arguments for rekeyRegister, array items cannot be undefined",
+                    )));
+                }
+                device_filters.push(parse_device_filter(&item, context)?);
+            }
+            return Result::Ok(KeyHandlerDeviceFilter::Any(device_filters));
+        }
+
+        let vendor_id = parse_device_filter_id(obj, "vendorId", context)?;
+        let product_id = parse_device_filter_id(obj, "productId", context)?;
+        let interface = parse_device_filter_id(obj, "interface", context)?;
+        return Result::Ok(KeyHandlerDeviceFilter::Ids {
+            vendor_id,
+            product_id,
+            interface,
+        });
+    }
+
+    return Result::Err(JsError::from(JsNativeError::error().with_message(
+        "invalid deviceFilter arguments for rekeyRegister, expected \"*\", a string, a RegExp, { vendorId, productId, interface } or an array of those",
+    )));
+}
+
+fn parse_device_filter_id(
+    obj: &JsObject,
+    name: &str,
+    context: &mut Context<'_>,
+) -> Result<Option<u16>, JsError> {
+    let value = obj.get(name, context)?;
+    if value.is_undefined() {
+        return Result::Ok(Option::None);
+    }
+    if value.is_number() {
+        return Result::Ok(Option::Some(value.to_uint16(context)?));
+    }
+    if value.is_string() {
+        // allow ids to be copied from the device name, e.g. "046D" or "0x046D"
+        let str = value.as_string().unwrap().to_std_string_escaped();
+        let str = str.trim_start_matches("0x").trim_start_matches("0X");
+        if let Result::Ok(id) = u16::from_str_radix(str, 16) {
+            return Result::Ok(Option::Some(id));
+        }
+    }
+    return Result::Err(JsError::from(JsNativeError::error().with_message(format!(
+        "invalid deviceFilter.{} for rekeyRegister, expected a number or a hex string",
+        name
+    ))));
+}
+
+fn parse_key_filter(
+    key_filter: &JsValue,
+    context: &mut Context<'_>,
+) -> Result<KeyHandlerKeyFilter, JsError> {
+    if key_filter.is_undefined()
+        || (key_filter.is_string() && key_filter.as_string().unwrap() == "*")
+    {
+        return Result::Ok(KeyHandlerKeyFilter::All);
+    }
+
+    let mut keys: Vec<KeyFilterKey> = vec![];
+    if let Option::Some(arr) = key_filter.as_object().filter(|obj| obj.is_array()) {
+        let arr = JsArray::from_object(arr.clone())?;
+        let len = arr.length(context)?;
+        for i in 0..len {
+            let item = arr.get(i, context)?;
+            keys.push(parse_key_filter_key(&item, context)?);
+        }
+    } else {
+        keys.push(parse_key_filter_key(key_filter, context)?);
+    }
+    return Result::Ok(KeyHandlerKeyFilter::Keys(keys));
+}
+
+fn parse_button_filter(
+    button_filter: &JsValue,
+    context: &mut Context<'_>,
+) -> Result<KeyHandlerKeyFilter, JsError> {
+    if button_filter.is_undefined()
+        || (button_filter.is_string() && button_filter.as_string().unwrap() == "*")
+    {
+        return Result::Ok(KeyHandlerKeyFilter::All);
+    }
+
+    fn parse_button(button: &JsValue) -> Result<KeyFilterKey, JsError> {
+        let name = button
+            .as_string()
+            .map(|name| name.to_std_string_escaped())
+            .unwrap_or_default();
+        if let Option::Some((code, _)) = MOUSE_BUTTON_NAMES.iter().find(|(_, n)| *n == name) {
+            return Result::Ok(KeyFilterKey {
+                vkey_code: *code,
+                modifiers: Option::None,
+            });
+        }
+        return Result::Err(JsError::from(JsNativeError::error().with_message(
+            "invalid buttonFilter arguments for rekeyRegisterMouse, expected \"*\", 'left', 'right', 'middle', 'x1', 'x2', 'wheel', 'hwheel' or an array of those",
+        )));
+    }
+
+    let mut buttons: Vec<KeyFilterKey> = vec![];
+    if let Option::Some(arr) = button_filter.as_object().filter(|obj| obj.is_array()) {
+        let arr = JsArray::from_object(arr.clone())?;
+        let len = arr.length(context)?;
+        for i in 0..len {
+            buttons.push(parse_button(&arr.get(i, context)?)?);
+        }
+    } else {
+        buttons.push(parse_button(button_filter)?);
+    }
+    return Result::Ok(KeyHandlerKeyFilter::Keys(buttons));
+}
+
+fn parse_key_filter_key(
+    key_filter: &JsValue,
+    context: &mut Context<'_>,
+) -> Result<KeyFilterKey, JsError> {
+    if key_filter.is_number() {
+        return Result::Ok(KeyFilterKey {
+            vkey_code: key_filter.to_uint16(context)?,
+            modifiers: Option::None,
+        });
+    }
+
+    if !key_filter.is_string() {
+        return Result::Err(JsError::from(JsNativeError::error().with_message(
+            "invalid keyFilter arguments for rekeyRegister, expected \"*\", a key name, a virtual key code, a key expression or an array of those",
+        )));
+    }
+
+    let key_expr = key_filter.as_string().unwrap().to_std_string_escaped();
+    return parse_key_filter_expr(&key_expr).map_err(|err| {
+        JsError::from(JsNativeError::error().with_message(format!(
+            "invalid keyFilter \"{}\" for rekeyRegister: {}",
+            key_expr, err
+        )))
+    });
+}
+
+fn parse_key_filter_expr(key_expr: &str) -> Result<KeyFilterKey, RekeyError> {
+    let (modifier_parts, key_part) = split_key_expr(key_expr)?;
+
+    let r = to_virtual_key(key_part)?;
+    if r.hankaku {
+        return Result::Err(RekeyError::GenericError(
+            "could not handle hankaku".to_string(),
+        ));
+    }
+    if modifier_parts.is_empty() && !r.shift && !r.ctrl && !r.alt {
+        return Result::Ok(KeyFilterKey {
+            vkey_code: r.vkey.0,
+            modifiers: Option::None,
+        });
+    }
+
+    let mut modifiers = KeyFilterModifiers {
+        shift: r.shift,
+        ctrl: r.ctrl,
+        alt: r.alt,
+        win: false,
+    };
+    for modifier_part in modifier_parts {
+        match modifier_part.to_ascii_lowercase().as_str() {
+            "shift" => modifiers.shift = true,
+            "ctrl" => modifiers.ctrl = true,
+            "alt" => modifiers.alt = true,
+            "windows" => modifiers.win = true,
+            _ => {
+                return Result::Err(RekeyError::GenericError(format!(
+                    "invalid modifier {}, expected shift, ctrl, alt or windows",
+                    modifier_part
+                )));
+            }
+        }
+    }
+    return Result::Ok(KeyFilterKey {
+        vkey_code: r.vkey.0,
+        modifiers: Option::Some(modifiers),
+    });
+}
+
+// evaluates a single script without the scripts thread and without sending input, used by
+// rekey --check, imports are resolved relative to the script's folder
+pub fn scripts_check(path: &PathBuf) -> Result<(), RekeyError> {
+    if !path.is_file() {
+        return Result::Err(RekeyError::GenericError(format!(
+            "script {} not found",
+            path.display()
+        )));
+    }
+    let script_dir = path
+        .parent()
+        .map(|dir| dir.to_path_buf())
+        .ok_or_else(|| RekeyError::GenericError(format!("invalid script {}", path.display())))?;
+    send_input_set_enabled(false);
+    CHECK_MODE.store(true, Ordering::Relaxed);
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock {});
+    load_script(&script_dir, path.clone(), &clock)?;
+    return Result::Ok(());
+}
+
+// reloads scripts whose files changed, returns the errors of scripts which failed to reload
+pub fn scripts_reload_changed() -> Result<Vec<RekeyError>, RekeyError> {
+    let mut channel = CHANNEL
+        .lock()
+        .map_err(|err| RekeyError::GenericError(format!("could not get scripts lock: {}", err)))?;
+    if let Option::Some(ch) = &mut *channel {
+        let (tx, rx) = mpsc::channel::<Result<Vec<RekeyError>, RekeyError>>();
+        ch.send(ThreadMessage::ReloadChanged(tx)).map_err(|err| {
+            RekeyError::GenericError(format!("failed to send reload message to thread: {}", err))
+        })?;
+        // don't hold the lock while scripts load, input would be blocked until the response
+        drop(channel);
+        let result = rx.recv().map_err(|err| {
+            RekeyError::GenericError(format!(
+                "failed to receive response from input thread: {}",
+                err
+            ))
+        })?;
+        return result;
+    }
+
+    return Result::Ok(vec![]);
+}
+
+pub fn scripts_should_buffer_unknown_device(
+    kind: InputKind,
+    vkey_code: u16,
+) -> Result<bool, RekeyError> {
+    let modifiers = KeyModifiers::capture();
+    let mut channel = CHANNEL
+        .lock()
+        .map_err(|err| RekeyError::GenericError(format!("could not get scripts lock: {}", err)))?;
+    if let Option::Some(ch) = &mut *channel {
+        let (tx, rx) = mpsc::channel::<Result<bool, RekeyError>>();
+        ch.send(ThreadMessage::ShouldBufferUnknownDevice(
+            tx, kind, vkey_code, modifiers,
+        ))
+        .map_err(|err| {
+            RekeyError::GenericError(format!("failed to send buffer message to thread: {}", err))
+        })?;
+        return recv_before_deadline(&rx, Instant::now() + get_handler_timeout(), false);
+    }
+
+    return Result::Ok(false);
+}
+
 pub fn scripts_handle_input(
     vkey_code: u16,
     direction: KeyDirection,
     device: Option<Arc<Device>>,
+    details: KeyDetails,
+) -> Result<SkipInput, RekeyError> {
+    return send_input_message(
+        InputKind::Keyboard,
+        vkey_code,
+        direction,
+        0,
+        device,
+        details,
+    );
+}
+
+pub fn scripts_has_mouse_handlers() -> bool {
+    return MOUSE_HANDLERS_REGISTERED.load(Ordering::Relaxed);
+}
+
+// code is one of the mouse button codes, see MOUSE_BUTTON_NAMES
+pub fn scripts_handle_mouse_input(
+    code: u16,
+    direction: KeyDirection,
+    wheel: i32,
+    device: Option<Arc<Device>>,
+    details: KeyDetails,
+) -> Result<SkipInput, RekeyError> {
+    if !scripts_has_mouse_handlers() {
+        return Result::Ok(SkipInput::DontSkip);
+    }
+    return send_input_message(InputKind::Mouse, code, direction, wheel, device, details);
+}
+
+fn send_input_message(
+    kind: InputKind,
+    vkey_code: u16,
+    direction: KeyDirection,
+    wheel: i32,
+    device: Option<Arc<Device>>,
+    details: KeyDetails,
 ) -> Result<SkipInput, RekeyError> {
+    // most input sent by scripts comes back here, skip the round trip to the scripts thread
+    if details.self_injected && !SELF_INJECTED_HANDLERS_REGISTERED.load(Ordering::Relaxed) {
+        return Result::Ok(SkipInput::DontSkip);
+    }
+    let modifiers = KeyModifiers::capture();
     let mut channel = CHANNEL
         .lock()
         .map_err(|err| RekeyError::GenericError(format!("could not get scripts lock: {}", err)))?;
     if let Option::Some(ch) = &mut *channel {
         let (tx, rx) = mpsc::channel::<ThreadResponseMessage>();
+        let deadline = Instant::now() + get_handler_timeout();
         ch.send(ThreadMessage::HandleInput(
             tx,
             InputMessage {
+                kind,
                 vkey_code,
                 direction,
                 device,
+                details,
+                modifiers,
+                wheel,
+                deadline,
             },
         ))
         .map_err(|err| {
             RekeyError::GenericError(format!("failed to send input message to thread: {}", err))
         })?;
-        let result = rx.recv().map_err(|err| {
+        // if the handlers run too long the input is let through, the scripts thread flags the
+        // script once the handler finishes or is stopped by the runtime limits
+        return recv_before_deadline(&rx, deadline, SkipInput::DontSkip);
+    }
+
+    return Result::Ok(SkipInput::DontSkip);
+}
+
+pub fn scripts_handle_hid_input(
+    device: Arc<Device>,
+    reports: Vec<Vec<u8>>,
+    timestamp: SystemTime,
+) -> Result<(), RekeyError> {
+    let hid_usage = match device.hid_usage {
+        Option::Some(hid_usage) => hid_usage,
+        Option::None => {
+            return Result::Ok(());
+        }
+    };
+    let channel = CHANNEL
+        .lock()
+        .map_err(|err| RekeyError::GenericError(format!("could not get scripts lock: {}", err)))?;
+    if let Option::Some(ch) = &*channel {
+        ch.send(ThreadMessage::HandleHid(HidMessage {
+            device,
+            hid_usage,
+            reports,
+            timestamp,
+        }))
+        .map_err(|err| {
+            RekeyError::GenericError(format!("failed to send hid message to thread: {}", err))
+        })?;
+    }
+    return Result::Ok(());
+}
+
+pub fn scripts_handle_device_change(device: Arc<Device>, arrived: bool) -> Result<(), RekeyError> {
+    let channel = CHANNEL
+        .lock()
+        .map_err(|err| RekeyError::GenericError(format!("could not get scripts lock: {}", err)))?;
+    if let Option::Some(ch) = &*channel {
+        ch.send(ThreadMessage::HandleDeviceChange(DeviceChangeMessage {
+            device,
+            arrived,
+        }))
+        .map_err(|err| {
             RekeyError::GenericError(format!(
-                "failed to receive response from input thread: {}",
+                "failed to send device change message to thread: {}",
                 err
             ))
         })?;
-        return result;
     }
+    return Result::Ok(());
+}
 
-    return Result::Ok(SkipInput::DontSkip);
+pub fn scripts_get_input_registrations() -> Result<InputRegistrations, RekeyError> {
+    let input_registrations = INPUT_REGISTRATIONS.lock().map_err(|err| {
+        RekeyError::GenericError(format!("could not get input registrations lock: {}", err))
+    })?;
+    return Result::Ok(input_registrations.clone());
+}
+
+// called from the scripts thread when the input scripts have handlers for changes, so the mouse
+// hook and raw input registrations only exist while they are needed
+pub fn scripts_set_input_registrations_listener(
+    listener: Box<dyn Fn() + Send>,
+) -> Result<(), RekeyError> {
+    let mut input_registrations_listener = INPUT_REGISTRATIONS_LISTENER.lock().map_err(|err| {
+        RekeyError::GenericError(format!(
+            "could not get input registrations listener lock: {}",
+            err
+        ))
+    })?;
+    *input_registrations_listener = Option::Some(listener);
+    return Result::Ok(());
+}
+
+// unknown_device from rekey.ini, used by handlers and scripts which don't set their own
+pub fn scripts_set_default_unknown_device(policy: UnknownDevicePolicy) -> Result<(), RekeyError> {
+    let mut default_unknown_device = DEFAULT_UNKNOWN_DEVICE.lock().map_err(|err| {
+        RekeyError::GenericError(format!("could not get unknown device policy lock: {}", err))
+    })?;
+    *default_unknown_device = policy;
+    return Result::Ok(());
+}
+
+fn thread_update_input_registrations(
+    scripts: &Vec<Script>,
+    last: &mut Option<InputRegistrations>,
+) -> Result<(), RekeyError> {
+    let mut registrations = InputRegistrations::default();
+    let mut self_injected = false;
+    for script in scripts {
+        let key_handlers = script.key_handlers.lock().map_err(|err| {
+            RekeyError::GenericError(format!("failed to lock key handlers: {}", err))
+        })?;
+        for key_handler in key_handlers.iter() {
+            self_injected = self_injected || key_handler.self_injected;
+            if key_handler.kind == InputKind::Mouse {
+                registrations.mouse = true;
+            }
+            if let Option::Some(hid_usage) = key_handler.hid_usage {
+                registrations.hid_usages.push(hid_usage);
+            }
+        }
+    }
+    registrations.hid_usages.sort();
+    registrations.hid_usages.dedup();
+    if CHECK_MODE.load(Ordering::Relaxed) {
+        return Result::Ok(());
+    }
+    SELF_INJECTED_HANDLERS_REGISTERED.store(self_injected, Ordering::Relaxed);
+    if last.as_ref() == Option::Some(&registrations) {
+        return Result::Ok(());
+    }
+
+    debug!(
+        "input registrations changed, mouse: {}, hid: {:?}",
+        registrations.mouse, registrations.hid_usages
+    );
+    MOUSE_HANDLERS_REGISTERED.store(registrations.mouse, Ordering::Relaxed);
+    *INPUT_REGISTRATIONS.lock().map_err(|err| {
+        RekeyError::GenericError(format!("could not get input registrations lock: {}", err))
+    })? = registrations.clone();
+    *last = Option::Some(registrations);
+
+    let listener = INPUT_REGISTRATIONS_LISTENER.lock().map_err(|err| {
+        RekeyError::GenericError(format!(
+            "could not get input registrations listener lock: {}",
+            err
+        ))
+    })?;
+    if let Option::Some(listener) = &*listener {
+        listener();
+    }
+    return Result::Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use windows::Win32::UI::Input::KeyboardAndMouse::{VK_ADD, VK_F1, VK_NUMPAD1};
+
+    fn mods(shift: bool, ctrl: bool, alt: bool, win: bool) -> Option<KeyFilterModifiers> {
+        return Option::Some(KeyFilterModifiers {
+            shift,
+            ctrl,
+            alt,
+            win,
+        });
+    }
+
+    #[test]
+    fn key_filter_exprs_parse() {
+        let cases = vec![
+            ("f1", VK_F1.0, Option::None),
+            ("numpad1", VK_NUMPAD1.0, Option::None),
+            ("ctrl+f1", VK_F1.0, mods(false, true, false, false)),
+            ("ctrl+shift+f1", VK_F1.0, mods(true, true, false, false)),
+            ("alt+f1", VK_F1.0, mods(false, false, true, false)),
+            ("windows+f1", VK_F1.0, mods(false, false, false, true)),
+            ("ctrl+add", VK_ADD.0, mods(false, true, false, false)),
+        ];
+        for (key_expr, vkey_code, modifiers) in cases {
+            let key = parse_key_filter_expr(key_expr).unwrap();
+            assert_eq!(key.vkey_code, vkey_code, "{}", key_expr);
+            assert!(key.modifiers == modifiers, "{}", key_expr);
+        }
+    }
+
+    #[test]
+    fn script_status_lines_come_from_syntax_errors() {
+        let cases = vec![
+            (
+                "failed to evaluate script a.js: SyntaxError: expected token ';', got 'x' in expression statement at line 3, col 5",
+                Option::Some(3),
+            ),
+            (
+                "failed to evaluate script a.js: SyntaxError: abrupt end at line 12, col 1",
+                Option::Some(12),
+            ),
+            ("failed to evaluate script a.js: Error: boom", Option::None),
+            ("at line x, col 1", Option::None),
+        ];
+        for (error, line) in cases {
+            let status = ScriptStatus::new(
+                PathBuf::from("a.js"),
+                ScriptState::Failed,
+                Option::Some(error.to_string()),
+            );
+            assert_eq!(status.line, line, "{}", error);
+        }
+        let status = ScriptStatus::new(PathBuf::from("a.js"), ScriptState::Loaded, Option::None);
+        assert_eq!(status.line, Option::None);
+    }
+
+    #[test]
+    fn key_filter_exprs_ending_in_plus_are_the_plus_key() {
+        let plus = parse_key_filter_expr("+").unwrap();
+        let ctrl_plus = parse_key_filter_expr("ctrl++").unwrap();
+        assert_eq!(plus.vkey_code, ctrl_plus.vkey_code);
+        assert!(ctrl_plus.modifiers.unwrap().ctrl);
+    }
+
+    #[test]
+    fn invalid_key_filter_exprs_are_rejected() {
+        for key_expr in ["", "ctrl+", "super+f1", "ctrl+f1+"] {
+            assert!(parse_key_filter_expr(key_expr).is_err(), "{}", key_expr);
+        }
+    }
+
+    #[test]
+    fn slow_handlers_overrun_once_per_event_next_to_non_matching_ones() {
+        struct FakeScript {
+            overruns: Cell<u32>,
+        }
+        let ms = Duration::from_millis;
+        let timeout = ms(200);
+        let slow = FakeScript {
+            overruns: Cell::new(0),
+        };
+        let fast = FakeScript {
+            overruns: Cell::new(0),
+        };
+        // (script, whether the handler called its callback, time it took) in handler order, the
+        // slow script has a non-matching handler after two slow ones
+        let runs = [
+            (&slow, true, ms(250)),
+            (&fast, true, ms(1)),
+            (&slow, true, ms(250)),
+            (&slow, false, ms(0)),
+        ];
+        for event in 1..=MAX_HANDLER_OVERRUNS {
+            let mut handler_times = EventHandlerTimes::new();
+            for (script, called, elapsed) in runs {
+                if called {
+                    handler_times.add(script, elapsed);
+                }
+            }
+            for (script, elapsed) in handler_times.take() {
+                script
+                    .overruns
+                    .set(next_overruns(script.overruns.get(), elapsed, timeout));
+            }
+            assert_eq!(slow.overruns.get(), event);
+            assert_eq!(fast.overruns.get(), 0);
+        }
+
+        // an event within the timeout starts the count again
+        assert_eq!(next_overruns(2, ms(200), timeout), 0);
+    }
 }
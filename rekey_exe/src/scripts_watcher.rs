@@ -0,0 +1,74 @@
+use std::thread;
+
+use rekey_common::{debug, get_scripts_dir, RekeyError};
+use windows::{
+    core::HSTRING,
+    Win32::{
+        Foundation::{BOOL, HWND, WAIT_OBJECT_0, WAIT_TIMEOUT},
+        Storage::FileSystem::{
+            FindCloseChangeNotification, FindFirstChangeNotificationW, FindNextChangeNotification,
+            FILE_NOTIFY_CHANGE_FILE_NAME, FILE_NOTIFY_CHANGE_LAST_WRITE,
+        },
+        System::Threading::{WaitForSingleObject, INFINITE},
+    },
+};
+
+use crate::{scripts::scripts_reload_changed, window::show_notification};
+
+// editors often write a file more than once when saving, wait for things to settle
+const DEBOUNCE_MILLIS: u32 = 250;
+
+pub fn scripts_watcher_start(hwnd: HWND) -> Result<(), RekeyError> {
+    let scripts_dir = get_scripts_dir()?;
+    let change_handle = unsafe {
+        FindFirstChangeNotificationW(
+            &HSTRING::from(scripts_dir.as_os_str()),
+            BOOL::from(true),
+            FILE_NOTIFY_CHANGE_FILE_NAME | FILE_NOTIFY_CHANGE_LAST_WRITE,
+        )
+        .map_err(|err| {
+            RekeyError::Win32Error("failed to watch scripts directory".to_string(), err)
+        })?
+    };
+
+    thread::spawn(move || {
+        debug("scripts watcher starting");
+        loop {
+            unsafe {
+                if WaitForSingleObject(change_handle, INFINITE) != WAIT_OBJECT_0 {
+                    debug("failed waiting for scripts directory change");
+                    break;
+                }
+
+                // keep waiting until there are no more changes for the debounce time
+                loop {
+                    if let Result::Err(err) = FindNextChangeNotification(change_handle) {
+                        debug!("failed to watch for next scripts directory change: {}", err);
+                        let _ = FindCloseChangeNotification(change_handle);
+                        return;
+                    }
+                    if WaitForSingleObject(change_handle, DEBOUNCE_MILLIS) == WAIT_TIMEOUT {
+                        break;
+                    }
+                }
+            }
+
+            match scripts_reload_changed() {
+                Result::Ok(errors) => {
+                    for err in errors {
+                        show_notification(hwnd, "Error Reloading Script", &format!("{}", err))
+                            .unwrap_or_else(|err| debug!("failed to show notification: {}", err));
+                    }
+                }
+                Result::Err(err) => {
+                    debug!("failed to reload changed scripts: {}", err);
+                }
+            }
+        }
+        unsafe {
+            let _ = FindCloseChangeNotification(change_handle);
+        }
+        debug("scripts watcher stopped");
+    });
+    return Result::Ok(());
+}
@@ -0,0 +1,506 @@
+use std::{
+    mem::size_of,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use rekey_common::{
+    debug, split_key_expr, to_virtual_key, KeyDirection, RekeyError, ToVirtualKeyResult,
+    REKEY_INPUT_EXTRA_INFO,
+};
+use windows::Win32::UI::{
+    Input::KeyboardAndMouse::{
+        SendInput, INPUT, INPUT_0, INPUT_KEYBOARD, INPUT_MOUSE, KEYBDINPUT, KEYBD_EVENT_FLAGS,
+        KEYEVENTF_EXTENDEDKEY, KEYEVENTF_KEYUP, KEYEVENTF_UNICODE, MOUSEEVENTF_ABSOLUTE,
+        MOUSEEVENTF_HWHEEL, MOUSEEVENTF_LEFTDOWN, MOUSEEVENTF_LEFTUP, MOUSEEVENTF_MIDDLEDOWN,
+        MOUSEEVENTF_MIDDLEUP, MOUSEEVENTF_MOVE, MOUSEEVENTF_RIGHTDOWN, MOUSEEVENTF_RIGHTUP,
+        MOUSEEVENTF_VIRTUALDESK, MOUSEEVENTF_WHEEL, MOUSEEVENTF_XDOWN, MOUSEEVENTF_XUP, MOUSEINPUT,
+        MOUSE_EVENT_FLAGS, VIRTUAL_KEY, VK_CONTROL, VK_MENU, VK_RETURN, VK_SHIFT, VK_TAB,
+    },
+    WindowsAndMessaging::{
+        GetSystemMetrics, SM_CXVIRTUALSCREEN, SM_CYVIRTUALSCREEN, SM_XVIRTUALSCREEN,
+        SM_YVIRTUALSCREEN, WHEEL_DELTA, XBUTTON1, XBUTTON2,
+    },
+};
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum SendKeyDirection {
+    Both,
+    Down,
+    Up,
+}
+
+impl SendKeyDirection {
+    pub fn parse(s: &str) -> Result<Self, RekeyError> {
+        match s {
+            "both" => Result::Ok(SendKeyDirection::Both),
+            "down" => Result::Ok(SendKeyDirection::Down),
+            "up" => Result::Ok(SendKeyDirection::Up),
+            _ => Result::Err(RekeyError::GenericError(format!(
+                "invalid direction {}, expected 'up', 'down' or 'both'",
+                s
+            ))),
+        }
+    }
+}
+
+// inputs for a key expression, e.g. "ctrl+c", modifiers go down first and come up last
+pub fn build_key_expr_inputs(
+    key_expr: &str,
+    direction: SendKeyDirection,
+) -> Result<Vec<INPUT>, RekeyError> {
+    let mut inputs: Vec<INPUT> = vec![];
+
+    let (mut key_expr_parts, key) = split_key_expr(key_expr)?;
+    key_expr_parts.push(key);
+    if direction == SendKeyDirection::Both || direction == SendKeyDirection::Down {
+        for key_expr_part in &key_expr_parts {
+            add_key_expr_part_inputs(&mut inputs, key_expr_part, false)?;
+        }
+    }
+
+    if direction == SendKeyDirection::Both || direction == SendKeyDirection::Up {
+        for key_expr_part in key_expr_parts.iter().rev() {
+            add_key_expr_part_inputs(&mut inputs, key_expr_part, true)?;
+        }
+    }
+
+    return Result::Ok(inputs);
+}
+
+fn add_key_expr_part_inputs(
+    inputs: &mut Vec<INPUT>,
+    key_expr_part: &str,
+    up: bool,
+) -> Result<(), RekeyError> {
+    let r = to_virtual_key(key_expr_part).map_err(|err| {
+        RekeyError::GenericError(format!("could not covert key {}: {}", key_expr_part, err))
+    })?;
+    if r.hankaku {
+        return Result::Err(RekeyError::GenericError(
+            "could not handle hankaku".to_string(),
+        ));
+    }
+    add_virtual_key_inputs(inputs, &r, up);
+    return Result::Ok(());
+}
+
+fn add_virtual_key_inputs(inputs: &mut Vec<INPUT>, r: &ToVirtualKeyResult, up: bool) {
+    if up {
+        inputs.push(create_key_input(r.vkey, true));
+    }
+    if r.ctrl {
+        inputs.push(create_key_input(VK_CONTROL, up));
+    }
+    if r.alt {
+        inputs.push(create_key_input(VK_MENU, up));
+    }
+    if r.shift {
+        inputs.push(create_key_input(VK_SHIFT, up));
+    }
+    if !up {
+        inputs.push(create_key_input(r.vkey, false));
+    }
+}
+
+// inputs which type the text. Characters are sent as unicode so they come out the same regardless
+// of the keyboard layout, caps lock or modifiers the user is holding, only line breaks and tabs
+// are sent as key presses so they work as enter and tab.
+pub fn build_text_inputs(text: &str) -> Vec<INPUT> {
+    let mut inputs: Vec<INPUT> = vec![];
+    let mut chars = text.chars().peekable();
+    while let Option::Some(ch) = chars.next() {
+        // treat \r\n as a single enter
+        if ch == '\r' && chars.peek() == Option::Some(&'\n') {
+            continue;
+        }
+        let vkey = match ch {
+            '\r' | '\n' => Option::Some(VK_RETURN),
+            '\t' => Option::Some(VK_TAB),
+            _ => Option::None,
+        };
+
+        if let Option::Some(vkey) = vkey {
+            inputs.push(create_key_input(vkey, false));
+            inputs.push(create_key_input(vkey, true));
+        } else {
+            let mut buf = [0; 2];
+            for code_unit in ch.encode_utf16(&mut buf) {
+                inputs.push(create_unicode_input(*code_unit, false));
+                inputs.push(create_unicode_input(*code_unit, true));
+            }
+        }
+    }
+    return inputs;
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+    X1,
+    X2,
+}
+
+impl MouseButton {
+    pub fn parse(s: &str) -> Result<Self, RekeyError> {
+        match s {
+            "left" => Result::Ok(MouseButton::Left),
+            "right" => Result::Ok(MouseButton::Right),
+            "middle" => Result::Ok(MouseButton::Middle),
+            "x1" => Result::Ok(MouseButton::X1),
+            "x2" => Result::Ok(MouseButton::X2),
+            _ => Result::Err(RekeyError::GenericError(format!(
+                "invalid mouse button {}, expected 'left', 'right', 'middle', 'x1' or 'x2'",
+                s
+            ))),
+        }
+    }
+}
+
+pub struct MouseMove {
+    pub x: i32,
+    pub y: i32,
+    // screen coordinates rather than relative to the current position
+    pub absolute: bool,
+}
+
+// a single sendMouse call, the parts are sent in field order
+pub struct MouseAction {
+    pub move_to: Option<MouseMove>,
+    pub button: Option<(MouseButton, SendKeyDirection)>,
+    // in wheel notches, positive scrolls up or right
+    pub wheel: i32,
+    pub hwheel: i32,
+}
+
+// the bounds of all monitors, absolute mouse positions are normalized to this
+#[derive(Default)]
+pub struct ScreenRect {
+    pub left: i32,
+    pub top: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+pub fn get_virtual_screen_rect() -> ScreenRect {
+    unsafe {
+        return ScreenRect {
+            left: GetSystemMetrics(SM_XVIRTUALSCREEN),
+            top: GetSystemMetrics(SM_YVIRTUALSCREEN),
+            width: GetSystemMetrics(SM_CXVIRTUALSCREEN),
+            height: GetSystemMetrics(SM_CYVIRTUALSCREEN),
+        };
+    }
+}
+
+pub fn build_mouse_inputs(action: &MouseAction, screen: &ScreenRect) -> Vec<INPUT> {
+    let mut inputs: Vec<INPUT> = vec![];
+
+    if let Option::Some(move_to) = &action.move_to {
+        if move_to.absolute {
+            // absolute coordinates are 0 to 65535 across the virtual screen
+            fn normalize(value: i32, start: i32, size: i32) -> i32 {
+                let size = (size - 1).max(1) as i64;
+                return ((value - start) as i64 * 65535 / size).clamp(0, 65535) as i32;
+            }
+            inputs.push(create_mouse_input(
+                normalize(move_to.x, screen.left, screen.width),
+                normalize(move_to.y, screen.top, screen.height),
+                0,
+                MOUSEEVENTF_MOVE | MOUSEEVENTF_ABSOLUTE | MOUSEEVENTF_VIRTUALDESK,
+            ));
+        } else {
+            inputs.push(create_mouse_input(
+                move_to.x,
+                move_to.y,
+                0,
+                MOUSEEVENTF_MOVE,
+            ));
+        }
+    }
+
+    if let Option::Some((button, direction)) = action.button {
+        let (down, up, data) = match button {
+            MouseButton::Left => (MOUSEEVENTF_LEFTDOWN, MOUSEEVENTF_LEFTUP, 0),
+            MouseButton::Right => (MOUSEEVENTF_RIGHTDOWN, MOUSEEVENTF_RIGHTUP, 0),
+            MouseButton::Middle => (MOUSEEVENTF_MIDDLEDOWN, MOUSEEVENTF_MIDDLEUP, 0),
+            MouseButton::X1 => (MOUSEEVENTF_XDOWN, MOUSEEVENTF_XUP, XBUTTON1 as i32),
+            MouseButton::X2 => (MOUSEEVENTF_XDOWN, MOUSEEVENTF_XUP, XBUTTON2 as i32),
+        };
+        if direction == SendKeyDirection::Both || direction == SendKeyDirection::Down {
+            inputs.push(create_mouse_input(0, 0, data, down));
+        }
+        if direction == SendKeyDirection::Both || direction == SendKeyDirection::Up {
+            inputs.push(create_mouse_input(0, 0, data, up));
+        }
+    }
+
+    if action.wheel != 0 {
+        inputs.push(create_mouse_input(
+            0,
+            0,
+            action.wheel * WHEEL_DELTA as i32,
+            MOUSEEVENTF_WHEEL,
+        ));
+    }
+
+    if action.hwheel != 0 {
+        inputs.push(create_mouse_input(
+            0,
+            0,
+            action.hwheel * WHEEL_DELTA as i32,
+            MOUSEEVENTF_HWHEEL,
+        ));
+    }
+
+    return inputs;
+}
+
+// inputs which pass on a key held back by the input buffer the way the keyboard sent it
+pub fn build_replay_key_inputs(
+    vkey_code: u16,
+    scan_code: u16,
+    extended: bool,
+    direction: KeyDirection,
+) -> Vec<INPUT> {
+    let mut flags = KEYBD_EVENT_FLAGS(0);
+    if direction == KeyDirection::Up {
+        flags |= KEYEVENTF_KEYUP;
+    }
+    if extended {
+        flags |= KEYEVENTF_EXTENDEDKEY;
+    }
+    let mut input = INPUT::default();
+    input.r#type = INPUT_KEYBOARD;
+    input.Anonymous = INPUT_0::default();
+    input.Anonymous.ki = KEYBDINPUT {
+        wVk: VIRTUAL_KEY(vkey_code),
+        wScan: scan_code,
+        dwFlags: flags,
+        time: 0,
+        dwExtraInfo: REKEY_INPUT_EXTRA_INFO,
+    };
+    return vec![input];
+}
+
+// inputs which pass on a mouse button held back by the input buffer
+pub fn build_replay_mouse_button_inputs(
+    button: MouseButton,
+    direction: KeyDirection,
+) -> Vec<INPUT> {
+    let direction = match direction {
+        KeyDirection::Down => SendKeyDirection::Down,
+        KeyDirection::Up => SendKeyDirection::Up,
+    };
+    let action = MouseAction {
+        move_to: Option::None,
+        button: Option::Some((button, direction)),
+        wheel: 0,
+        hwheel: 0,
+    };
+    return build_mouse_inputs(&action, &ScreenRect::default());
+}
+
+// inputs which pass on a wheel turn held back by the input buffer, delta is the raw wheel delta
+// the hooks report rather than notches
+pub fn build_replay_mouse_wheel_inputs(delta: i32, horizontal: bool) -> Vec<INPUT> {
+    let flags = if horizontal {
+        MOUSEEVENTF_HWHEEL
+    } else {
+        MOUSEEVENTF_WHEEL
+    };
+    return vec![create_mouse_input(0, 0, delta, flags)];
+}
+
+// cleared by rekey --check so scripts can be evaluated without typing anything
+static SEND_INPUT_ENABLED: AtomicBool = AtomicBool::new(true);
+
+pub fn send_input_set_enabled(enabled: bool) -> () {
+    SEND_INPUT_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn send_inputs(inputs: &[INPUT]) -> Result<(), RekeyError> {
+    if inputs.is_empty() {
+        return Result::Ok(());
+    }
+    if !SEND_INPUT_ENABLED.load(Ordering::Relaxed) {
+        debug!("sending input disabled, dropping {} inputs", inputs.len());
+        return Result::Ok(());
+    }
+    let input_size = size_of::<INPUT>();
+    unsafe {
+        let r = SendInput(inputs, input_size as i32) as usize;
+        if r != inputs.len() {
+            return Result::Err(RekeyError::GenericError(
+                "failed to send all inputs".to_string(),
+            ));
+        }
+    }
+    return Result::Ok(());
+}
+
+fn create_key_input(vkey: VIRTUAL_KEY, up: bool) -> INPUT {
+    let mut input = INPUT::default();
+    input.r#type = INPUT_KEYBOARD;
+    input.Anonymous = INPUT_0::default();
+    input.Anonymous.ki = KEYBDINPUT::default();
+    input.Anonymous.ki.wVk = vkey;
+    input.Anonymous.ki.dwExtraInfo = REKEY_INPUT_EXTRA_INFO;
+    if up {
+        input.Anonymous.ki.dwFlags = KEYEVENTF_KEYUP;
+    }
+    return input;
+}
+
+fn create_unicode_input(code_unit: u16, up: bool) -> INPUT {
+    let mut input = INPUT::default();
+    input.r#type = INPUT_KEYBOARD;
+    input.Anonymous = INPUT_0::default();
+    input.Anonymous.ki = KEYBDINPUT::default();
+    input.Anonymous.ki.wScan = code_unit;
+    input.Anonymous.ki.dwExtraInfo = REKEY_INPUT_EXTRA_INFO;
+    input.Anonymous.ki.dwFlags = if up {
+        KEYEVENTF_UNICODE | KEYEVENTF_KEYUP
+    } else {
+        KEYEVENTF_UNICODE
+    };
+    return input;
+}
+
+fn create_mouse_input(dx: i32, dy: i32, mouse_data: i32, flags: MOUSE_EVENT_FLAGS) -> INPUT {
+    let mut input = INPUT::default();
+    input.r#type = INPUT_MOUSE;
+    input.Anonymous = INPUT_0::default();
+    input.Anonymous.mi = MOUSEINPUT::default();
+    input.Anonymous.mi.dx = dx;
+    input.Anonymous.mi.dy = dy;
+    input.Anonymous.mi.mouseData = mouse_data;
+    input.Anonymous.mi.dwFlags = flags;
+    input.Anonymous.mi.dwExtraInfo = REKEY_INPUT_EXTRA_INFO;
+    return input;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use windows::Win32::UI::Input::KeyboardAndMouse::VK_F1;
+
+    // (virtual key, unicode code unit, up) of keyboard inputs
+    fn keys(inputs: &[INPUT]) -> Vec<(VIRTUAL_KEY, u16, bool)> {
+        return inputs
+            .iter()
+            .map(|input| {
+                assert_eq!(input.r#type, INPUT_KEYBOARD);
+                let ki = unsafe { input.Anonymous.ki };
+                assert_eq!(ki.dwExtraInfo, REKEY_INPUT_EXTRA_INFO);
+                let unicode = ki.dwFlags.0 & KEYEVENTF_UNICODE.0 != 0;
+                assert_eq!(unicode, ki.wVk.0 == 0);
+                return (ki.wVk, ki.wScan, ki.dwFlags.0 & KEYEVENTF_KEYUP.0 != 0);
+            })
+            .collect();
+    }
+
+    fn press(vkey: VIRTUAL_KEY) -> Vec<(VIRTUAL_KEY, u16, bool)> {
+        return vec![(vkey, 0, false), (vkey, 0, true)];
+    }
+
+    fn unicode(code_unit: u16) -> Vec<(VIRTUAL_KEY, u16, bool)> {
+        return vec![
+            (VIRTUAL_KEY(0), code_unit, false),
+            (VIRTUAL_KEY(0), code_unit, true),
+        ];
+    }
+
+    #[test]
+    fn text_is_sent_as_unicode() {
+        // the same inputs whatever the caps lock state, 'A' isn't shift+a
+        let expected = [
+            unicode('a' as u16),
+            unicode('A' as u16),
+            unicode('\u{fc}' as u16),
+        ];
+        assert_eq!(keys(&build_text_inputs("aAü")), expected.concat());
+    }
+
+    #[test]
+    fn characters_outside_the_bmp_are_sent_as_surrogate_pairs() {
+        let expected = [unicode(0xd83d), unicode(0xde00)];
+        assert_eq!(keys(&build_text_inputs("\u{1f600}")), expected.concat());
+    }
+
+    #[test]
+    fn line_breaks_and_tabs_are_sent_as_keys() {
+        for text in ["\r\n", "\n", "\r"] {
+            assert_eq!(
+                keys(&build_text_inputs(text)),
+                press(VK_RETURN),
+                "{:?}",
+                text
+            );
+        }
+        let expected = [
+            unicode('a' as u16),
+            press(VK_RETURN),
+            press(VK_RETURN),
+            press(VK_TAB),
+            unicode('b' as u16),
+        ];
+        assert_eq!(keys(&build_text_inputs("a\r\n\n\tb")), expected.concat());
+    }
+
+    #[test]
+    fn modifiers_go_down_first_and_come_up_last() {
+        let inputs = build_key_expr_inputs("ctrl+shift+f1", SendKeyDirection::Both).unwrap();
+        assert_eq!(
+            keys(&inputs),
+            vec![
+                (VK_CONTROL, 0, false),
+                (VK_SHIFT, 0, false),
+                (VK_F1, 0, false),
+                (VK_F1, 0, true),
+                (VK_SHIFT, 0, true),
+                (VK_CONTROL, 0, true),
+            ]
+        );
+
+        let inputs = build_key_expr_inputs("ctrl+shift+f1", SendKeyDirection::Down).unwrap();
+        assert_eq!(
+            keys(&inputs),
+            vec![
+                (VK_CONTROL, 0, false),
+                (VK_SHIFT, 0, false),
+                (VK_F1, 0, false),
+            ]
+        );
+
+        let inputs = build_key_expr_inputs("ctrl+shift+f1", SendKeyDirection::Up).unwrap();
+        assert_eq!(
+            keys(&inputs),
+            vec![(VK_F1, 0, true), (VK_SHIFT, 0, true), (VK_CONTROL, 0, true)]
+        );
+    }
+
+    #[test]
+    fn invalid_key_exprs_are_rejected() {
+        for key_expr in ["", "ctrl+", "ctrl+nosuchkey"] {
+            assert!(
+                build_key_expr_inputs(key_expr, SendKeyDirection::Both).is_err(),
+                "{}",
+                key_expr
+            );
+        }
+    }
+
+    #[test]
+    fn directions_parse() {
+        assert!(SendKeyDirection::parse("both").unwrap() == SendKeyDirection::Both);
+        assert!(SendKeyDirection::parse("down").unwrap() == SendKeyDirection::Down);
+        assert!(SendKeyDirection::parse("up").unwrap() == SendKeyDirection::Up);
+        let err = SendKeyDirection::parse("sideways")
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("'up', 'down' or 'both'"), "{}", err);
+    }
+}
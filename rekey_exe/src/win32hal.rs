@@ -1,21 +1,77 @@
 use std::ffi::{c_void, OsString};
 use std::mem::size_of;
 use std::os::windows::prelude::OsStringExt;
-use windows::Win32::{
-    Foundation::{GetLastError, HANDLE, LPARAM},
-    UI::Input::{
-        GetRawInputData, GetRawInputDeviceInfoW, GetRawInputDeviceList, RAWINPUT,
-        RAWINPUTDEVICELIST, RAWINPUTHEADER, RIDI_DEVICENAME,
+use windows::{
+    core::HSTRING,
+    Win32::{
+        Devices::HumanInterfaceDevice::HidD_GetProductString,
+        Foundation::{CloseHandle, GetLastError, HANDLE, LPARAM},
+        Storage::FileSystem::{
+            CreateFileW, FILE_FLAGS_AND_ATTRIBUTES, FILE_SHARE_READ, FILE_SHARE_WRITE,
+            OPEN_EXISTING,
+        },
+        UI::Input::{
+            GetRawInputData, GetRawInputDeviceInfoW, GetRawInputDeviceList, RAWINPUT,
+            RAWINPUTDEVICELIST, RAWINPUTHEADER, RIDI_DEVICEINFO, RIDI_DEVICENAME, RID_DEVICE_INFO,
+            RIM_TYPEHID,
+        },
+        UI::Input::{HRAWINPUT, RID_INPUT},
     },
-    UI::Input::{HRAWINPUT, RID_INPUT},
 };
 
-use crate::RekeyError;
+use crate::{devices::HidUsage, RekeyError};
 
 const MAX_RAW_INPUT_DEVICE_COUNT: usize = 1000;
 const MAX_RAW_INPUT_DEVICE_INFO_DEVICE_NAME: usize = 2000;
+const MAX_RAW_INPUT_DATA_SIZE: usize = 64 * 1024;
+// HID strings are limited to 126 wide characters plus the terminator
+const MAX_HID_STRING_LENGTH: usize = 127;
 
+// HID input can be bigger than RAWINPUT, only the part which fits is returned, see
+// get_raw_input_hid_reports for the reports
 pub fn get_raw_input_data(lparam: LPARAM) -> Result<RAWINPUT, RekeyError> {
+    let buffer = get_raw_input_buffer(lparam)?;
+    let mut raw_input: RAWINPUT = RAWINPUT::default();
+    unsafe {
+        std::ptr::copy_nonoverlapping(
+            buffer.as_ptr(),
+            std::ptr::addr_of_mut!(raw_input) as *mut u8,
+            buffer.len().min(size_of::<RAWINPUT>()),
+        );
+    }
+    return Result::Ok(raw_input);
+}
+
+// the reports of a RIM_TYPEHID input, a single input can hold several reports of the same size
+pub fn get_raw_input_hid_reports(lparam: LPARAM) -> Result<Vec<Vec<u8>>, RekeyError> {
+    let buffer = get_raw_input_buffer(lparam)?;
+    // RAWHID is dwSizeHid, dwCount then the report bytes
+    let hid = &buffer[size_of::<RAWINPUTHEADER>().min(buffer.len())..];
+    if hid.len() < 8 {
+        return Result::Err(RekeyError::GenericError(
+            "raw input too small for hid data".to_string(),
+        ));
+    }
+    let size = u32::from_ne_bytes([hid[0], hid[1], hid[2], hid[3]]) as usize;
+    let count = u32::from_ne_bytes([hid[4], hid[5], hid[6], hid[7]]) as usize;
+    let data = &hid[8..];
+    if size == 0 || size.saturating_mul(count) > data.len() {
+        return Result::Err(RekeyError::GenericError(format!(
+            "invalid hid data, {} reports of {} bytes in {} bytes",
+            count,
+            size,
+            data.len()
+        )));
+    }
+    return Result::Ok(
+        data[..size * count]
+            .chunks(size)
+            .map(|r| r.to_vec())
+            .collect(),
+    );
+}
+
+fn get_raw_input_buffer(lparam: LPARAM) -> Result<Vec<u8>, RekeyError> {
     unsafe {
         let mut pcbsize: u32 = 0;
         let get_raw_input_data_size_result = GetRawInputData(
@@ -30,19 +86,18 @@ pub fn get_raw_input_data(lparam: LPARAM) -> Result<RAWINPUT, RekeyError> {
                 "failed to get raw input data size".to_string(),
             ));
         }
-        if pcbsize > size_of::<RAWINPUT>() as u32 {
+        if pcbsize as usize > MAX_RAW_INPUT_DATA_SIZE {
             return Result::Err(RekeyError::GenericError(format!(
                 "unexpected raw input size expected size less than {} but found {}",
-                size_of::<RAWINPUT>() as u32,
-                pcbsize
+                MAX_RAW_INPUT_DATA_SIZE, pcbsize
             )));
         }
 
-        let mut raw_input: RAWINPUT = RAWINPUT::default();
+        let mut buffer: Vec<u8> = vec![0; pcbsize as usize];
         let get_raw_input_data_result = GetRawInputData(
             HRAWINPUT(lparam.0),
             RID_INPUT,
-            Option::Some(std::ptr::addr_of_mut!(raw_input) as *mut c_void),
+            Option::Some(buffer.as_mut_ptr() as *mut c_void),
             &mut pcbsize,
             size_of::<RAWINPUTHEADER>() as u32,
         );
@@ -52,7 +107,7 @@ pub fn get_raw_input_data(lparam: LPARAM) -> Result<RAWINPUT, RekeyError> {
             ));
         }
 
-        return Result::Ok(raw_input);
+        return Result::Ok(buffer);
     }
 }
 
@@ -138,3 +193,72 @@ pub fn get_raw_input_device_info_device_name(hdevice: HANDLE) -> Result<String,
         };
     }
 }
+
+// the top level collection usage of a HID device, None for keyboards and mice
+pub fn get_raw_input_device_info_hid_usage(
+    hdevice: HANDLE,
+) -> Result<Option<HidUsage>, RekeyError> {
+    unsafe {
+        let mut device_info = RID_DEVICE_INFO::default();
+        device_info.cbSize = size_of::<RID_DEVICE_INFO>() as u32;
+        let mut pcbsize = size_of::<RID_DEVICE_INFO>() as u32;
+        let get_raw_input_device_info_result = GetRawInputDeviceInfoW(
+            hdevice,
+            RIDI_DEVICEINFO,
+            Option::Some(std::ptr::addr_of_mut!(device_info) as *mut c_void),
+            &mut pcbsize,
+        );
+        if get_raw_input_device_info_result == -1i32 as u32 {
+            return Result::Err(RekeyError::Win32GetLastError(
+                "failed GetRawInputDeviceInfoW device info".to_string(),
+                GetLastError(),
+            ));
+        }
+        if device_info.dwType != RIM_TYPEHID {
+            return Result::Ok(Option::None);
+        }
+        return Result::Ok(Option::Some(HidUsage {
+            usage_page: device_info.Anonymous.hid.usUsagePage,
+            usage: device_info.Anonymous.hid.usUsage,
+        }));
+    }
+}
+
+// the product string the device reports, e.g. "USB Receiver", None for devices which aren't HID
+// or can't be opened, a device can be opened for its attributes without read or write access
+// even while windows has it open exclusively
+pub fn get_hid_product_string(device_name: &str) -> Option<String> {
+    unsafe {
+        let handle = CreateFileW(
+            &HSTRING::from(device_name),
+            0,
+            FILE_SHARE_READ | FILE_SHARE_WRITE,
+            Option::None,
+            OPEN_EXISTING,
+            FILE_FLAGS_AND_ATTRIBUTES(0),
+            HANDLE(0),
+        )
+        .ok()?;
+        let mut buffer: [u16; MAX_HID_STRING_LENGTH] = [0; MAX_HID_STRING_LENGTH];
+        let found = HidD_GetProductString(
+            handle,
+            buffer.as_mut_ptr() as *mut c_void,
+            (buffer.len() * size_of::<u16>()) as u32,
+        )
+        .as_bool();
+        let _ = CloseHandle(handle);
+        if !found {
+            return Option::None;
+        }
+
+        let len = buffer
+            .iter()
+            .position(|ch| *ch == 0)
+            .unwrap_or(buffer.len());
+        let product = String::from_utf16_lossy(&buffer[0..len]).trim().to_string();
+        if product.is_empty() {
+            return Option::None;
+        }
+        return Option::Some(product);
+    }
+}
@@ -1,52 +1,114 @@
+use lazy_static::lazy_static;
 use rekey_common::{
-    get_log_filename, get_scripts_dir, KeyDirection, DONT_SKIP_INPUT, SKIP_INPUT,
-    WM_USER_SHELL_ICON, WM_USER_SHOULD_SKIP_INPUT,
+    get_log_filename, get_scripts_dir, KeyDirection, DONT_SKIP_INPUT, SELF_INJECTED_WPARAM_FLAG,
+    SKIP_INPUT, WM_USER_INPUT_REGISTRATIONS_CHANGED, WM_USER_INSTANCE_COMMAND, WM_USER_SHELL_ICON,
+    WM_USER_SHOULD_SKIP_INPUT, WM_USER_SHOULD_SKIP_MOUSE_INPUT,
+};
+use std::{
+    mem::size_of,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Instant, SystemTime},
 };
-use std::mem::size_of;
 use windows::{
     core::{w, HSTRING, PCWSTR},
     Win32::{
         Foundation::{GetLastError, BOOL, HINSTANCE, HWND, LPARAM, LRESULT, POINT, WPARAM},
         System::LibraryLoader::GetModuleHandleW,
         UI::{
-            Input::RIM_TYPEKEYBOARD,
+            Input::{
+                KeyboardAndMouse::{
+                    INPUT, VK_LBUTTON, VK_MBUTTON, VK_RBUTTON, VK_XBUTTON1, VK_XBUTTON2,
+                },
+                RAWMOUSE, RIM_TYPEHID, RIM_TYPEKEYBOARD, RIM_TYPEMOUSE,
+            },
             Shell::{
-                ShellExecuteW, NIF_ICON, NIF_MESSAGE, NIF_TIP, NIM_DELETE, NOTIFYICONDATAW,
-                NOTIFY_ICON_DATA_FLAGS,
+                ShellExecuteW, NIF_ICON, NIF_INFO, NIF_MESSAGE, NIF_TIP, NIIF_ERROR, NIM_DELETE,
+                NIM_MODIFY, NOTIFYICONDATAW, NOTIFY_ICON_DATA_FLAGS,
             },
             WindowsAndMessaging::{
                 CreatePopupMenu, CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW,
                 GetCursorPos, GetMessageW, InsertMenuW, LoadCursorW, LoadIconW, MessageBoxW,
                 PostMessageW, PostQuitMessage, RegisterClassExW, TrackPopupMenu, TranslateMessage,
-                IDC_ARROW, IDRETRY, MB_ICONEXCLAMATION, MB_RETRYCANCEL, MF_BYPOSITION, MF_STRING,
-                MSG, SW_NORMAL, TPM_BOTTOMALIGN, TPM_LEFTALIGN, TPM_LEFTBUTTON, WINDOW_EX_STYLE,
-                WM_CLOSE, WM_COMMAND, WM_DESTROY, WM_INPUT, WM_KEYDOWN, WM_KEYUP, WM_RBUTTONDOWN,
-                WM_SYSKEYDOWN, WM_SYSKEYUP, WNDCLASSEXW, WS_CAPTION, WS_MAXIMIZEBOX,
-                WS_MINIMIZEBOX, WS_OVERLAPPED, WS_SYSMENU, WS_THICKFRAME,
+                IDC_ARROW, IDRETRY, MB_ICONEXCLAMATION, MB_ICONINFORMATION, MB_OK, MB_RETRYCANCEL,
+                MF_BYPOSITION, MF_CHECKED, MF_GRAYED, MF_POPUP, MF_STRING, MSG,
+                RI_MOUSE_BUTTON_4_DOWN, RI_MOUSE_BUTTON_4_UP, RI_MOUSE_BUTTON_5_DOWN,
+                RI_MOUSE_BUTTON_5_UP, RI_MOUSE_HWHEEL, RI_MOUSE_LEFT_BUTTON_DOWN,
+                RI_MOUSE_LEFT_BUTTON_UP, RI_MOUSE_MIDDLE_BUTTON_DOWN, RI_MOUSE_MIDDLE_BUTTON_UP,
+                RI_MOUSE_RIGHT_BUTTON_DOWN, RI_MOUSE_RIGHT_BUTTON_UP, RI_MOUSE_WHEEL, SW_NORMAL,
+                TPM_BOTTOMALIGN, TPM_LEFTALIGN, TPM_LEFTBUTTON, WINDOW_EX_STYLE, WM_CLOSE,
+                WM_COMMAND, WM_DESTROY, WM_INPUT, WM_KEYDOWN, WM_KEYUP, WM_LBUTTONDOWN,
+                WM_LBUTTONUP, WM_MBUTTONDOWN, WM_MBUTTONUP, WM_MOUSEHWHEEL, WM_MOUSEWHEEL,
+                WM_RBUTTONDOWN, WM_RBUTTONUP, WM_SYSKEYDOWN, WM_SYSKEYUP, WM_XBUTTONDOWN,
+                WM_XBUTTONUP, WNDCLASSEXW, WS_CAPTION, WS_MAXIMIZEBOX, WS_MINIMIZEBOX,
+                WS_OVERLAPPED, WS_SYSMENU, WS_THICKFRAME, XBUTTON1,
             },
         },
         UI::{
             Shell::{Shell_NotifyIconW, NIM_ADD},
-            WindowsAndMessaging::{PeekMessageW, CW_USEDEFAULT, HMENU, PM_REMOVE},
+            WindowsAndMessaging::{
+                KillTimer, PeekMessageW, SetTimer, CW_USEDEFAULT, DBT_DEVNODES_CHANGED, HMENU,
+                PM_REMOVE, WM_DEVICECHANGE, WM_INPUT_DEVICE_CHANGE, WM_TIMER,
+            },
         },
     },
 };
 
 use crate::{
     debug,
-    devices::find_device,
-    input_log::{input_log_add_wm_input, input_log_get_device},
-    scripts::{scripts_handle_input, scripts_load},
-    win32hal::get_raw_input_data,
+    devices::{devices_update_attached, find_device, Device},
+    dll::dll_set_mouse_hook_enabled,
+    error, info,
+    input_buffer::{InputBuffer, InputReplays},
+    input_log::{input_log_add_wm_input, input_log_take},
+    instance::InstanceCommand,
+    ll_hook::{ll_hook_is_installed, ll_hook_set_mouse_enabled},
+    raw_input::{raw_input_set_hid_usages, raw_input_set_mouse_enabled},
+    scripts::{
+        scripts_get_input_registrations, scripts_get_statuses, scripts_handle_device_change,
+        scripts_handle_hid_input, scripts_handle_input, scripts_handle_mouse_input, scripts_load,
+        scripts_should_buffer_unknown_device, InputKind, KeyDetails, ScriptState,
+        MOUSE_HWHEEL_CODE, MOUSE_WHEEL_CODE,
+    },
+    send_input::{
+        build_replay_key_inputs, build_replay_mouse_button_inputs, build_replay_mouse_wheel_inputs,
+        send_inputs, MouseButton,
+    },
+    win32hal::{get_raw_input_data, get_raw_input_hid_reports},
     RekeyError, SkipInput,
 };
 
 const SYS_TRAY_ID: u32 = 1001;
+// rekey --no-tray runs without the icon, notifications then only go to the log
+static SYSTRAY_ICON_ADDED: AtomicBool = AtomicBool::new(false);
+const ID_TIMER_INPUT_BUFFER: usize = 1;
+
+// keyboard or mouse input on its way to the scripts
+struct PendingInput {
+    kind: InputKind,
+    // the virtual key or mouse button code
+    code: u16,
+    direction: KeyDirection,
+    // mouse wheel delta, 0 for keys and buttons
+    wheel: i32,
+    device: Option<Arc<Device>>,
+    details: KeyDetails,
+}
+
+lazy_static! {
+    static ref INPUT_BUFFER: Mutex<InputBuffer<PendingInput>> = Mutex::new(InputBuffer::new());
+    static ref INPUT_REPLAYS: Mutex<InputReplays<(InputKind, u16, KeyDirection)>> =
+        Mutex::new(InputReplays::new());
+}
 
 const ID_MENU_EXIT: usize = 1;
 const ID_MENU_RELOAD_SCRIPTS: usize = 2;
 const ID_MENU_OPEN_SCRIPTS_FOLDER: usize = 3;
 const ID_MENU_OPEN_LOG: usize = 4;
+// each script in the scripts sub menu gets an id starting from this
+const ID_MENU_SCRIPT_BASE: usize = 1000;
 
 pub fn message_loop() -> Result<(), RekeyError> {
     unsafe {
@@ -80,7 +142,7 @@ unsafe extern "system" fn window_proc_system(
             return r;
         }
         Err(err) => {
-            debug!("window proc error {}", err);
+            error!("window proc error {}", err);
             return LRESULT(0);
         }
     }
@@ -105,9 +167,27 @@ fn window_proc(
         WM_INPUT => {
             return handle_wm_input(hwnd, msg, wparam, lparam);
         }
+        WM_TIMER => {
+            return handle_timer(hwnd, msg, wparam, lparam);
+        }
+        WM_INPUT_DEVICE_CHANGE => {
+            return handle_device_change();
+        }
+        WM_DEVICECHANGE if wparam.0 as u32 == DBT_DEVNODES_CHANGED => {
+            return handle_device_change();
+        }
         WM_USER_SHOULD_SKIP_INPUT => {
             return handle_should_skip_input(hwnd, wparam, lparam);
         }
+        WM_USER_SHOULD_SKIP_MOUSE_INPUT => {
+            return handle_should_skip_mouse_input(hwnd, wparam, lparam);
+        }
+        WM_USER_INPUT_REGISTRATIONS_CHANGED => {
+            return handle_input_registrations_changed(hwnd);
+        }
+        WM_USER_INSTANCE_COMMAND => {
+            return handle_instance_command(hwnd, wparam);
+        }
         WM_USER_SHELL_ICON => {
             return handle_shell_icon(hwnd, wparam, lparam);
         }
@@ -120,6 +200,18 @@ fn window_proc(
     }
 }
 
+// sent by a second launch of rekey, does the same as the matching tray menu item
+fn handle_instance_command(hwnd: HWND, wparam: WPARAM) -> Result<LRESULT, RekeyError> {
+    let command = InstanceCommand::from_wparam(wparam)?;
+    debug!("received {:?} from another instance", command);
+    let menu_id = match command {
+        InstanceCommand::Reload => ID_MENU_RELOAD_SCRIPTS,
+        InstanceCommand::OpenLog => ID_MENU_OPEN_LOG,
+        InstanceCommand::Exit => ID_MENU_EXIT,
+    };
+    return handle_menu_click(hwnd, WPARAM(menu_id), LPARAM(0));
+}
+
 fn handle_menu_click(hwnd: HWND, wparam: WPARAM, _lparam: LPARAM) -> Result<LRESULT, RekeyError> {
     match wparam.0 {
         ID_MENU_EXIT => unsafe {
@@ -156,12 +248,81 @@ fn handle_menu_click(hwnd: HWND, wparam: WPARAM, _lparam: LPARAM) -> Result<LRES
             load_scripts_notify_on_error(hwnd);
             return Result::Ok(LRESULT(0));
         }
+        id if id >= ID_MENU_SCRIPT_BASE => {
+            show_script_status(hwnd, id - ID_MENU_SCRIPT_BASE)?;
+            return Result::Ok(LRESULT(0));
+        }
         _ => {
             return Result::Ok(LRESULT(0));
         }
     }
 }
 
+fn show_script_status(hwnd: HWND, index: usize) -> Result<(), RekeyError> {
+    let statuses = scripts_get_statuses()?;
+    if let Option::Some(status) = statuses.get(index) {
+        let message = if let Option::Some(error) = &status.error {
+            format!("{}\n\n{}", status.state, error)
+        } else {
+            format!("{}", status.state)
+        };
+        let style = if status.error.is_some() {
+            MB_ICONEXCLAMATION
+        } else {
+            MB_ICONINFORMATION
+        };
+        unsafe {
+            MessageBoxW(
+                hwnd,
+                &HSTRING::from(message),
+                &HSTRING::from(status.name()),
+                style | MB_OK,
+            );
+        }
+    }
+    return Result::Ok(());
+}
+
+fn create_scripts_menu() -> Result<HMENU, RekeyError> {
+    unsafe {
+        let menu = CreatePopupMenu().map_err(|err| {
+            RekeyError::GenericError(format!("failed to create popup menu: {}", err))
+        })?;
+
+        let statuses = scripts_get_statuses()?;
+        if statuses.is_empty() {
+            InsertMenuW(
+                menu,
+                0xFFFFFFFF,
+                MF_BYPOSITION | MF_STRING | MF_GRAYED,
+                0,
+                w!("No Scripts"),
+            )
+            .map_err(|err| {
+                RekeyError::GenericError(format!("failed to insert menu item: {}", err))
+            })?;
+        }
+        for (i, status) in statuses.iter().enumerate() {
+            let mut flags = MF_BYPOSITION | MF_STRING;
+            if status.state == ScriptState::Loaded {
+                flags |= MF_CHECKED;
+            }
+            InsertMenuW(
+                menu,
+                0xFFFFFFFF,
+                flags,
+                ID_MENU_SCRIPT_BASE + i,
+                &HSTRING::from(format!("{}", status)),
+            )
+            .map_err(|err| {
+                RekeyError::GenericError(format!("failed to insert menu item: {}", err))
+            })?;
+        }
+
+        return Result::Ok(menu);
+    }
+}
+
 fn handle_shell_icon(hwnd: HWND, _wparam: WPARAM, lparam: LPARAM) -> Result<LRESULT, RekeyError> {
     let msg = (lparam.0 & 0xffff) as u32;
     match msg {
@@ -185,6 +346,15 @@ fn handle_shell_icon_right_click(hwnd: HWND) -> Result<LRESULT, RekeyError> {
             RekeyError::GenericError(format!("failed to create popup menu: {}", err))
         })?;
 
+        InsertMenuW(
+            menu,
+            0xFFFFFFFF,
+            MF_BYPOSITION | MF_POPUP,
+            create_scripts_menu()?.0 as usize,
+            w!("Scripts"),
+        )
+        .map_err(|err| RekeyError::GenericError(format!("failed to insert menu item: {}", err)))?;
+
         InsertMenuW(
             menu,
             0xFFFFFFFF,
@@ -240,19 +410,62 @@ fn handle_should_skip_input(
     wparam: WPARAM,
     lparam: LPARAM,
 ) -> Result<LRESULT, RekeyError> {
-    let vkey_code = wparam.0 as u16;
+    let timestamp = SystemTime::now();
+    let vkey_code = (wparam.0 & 0xffff) as u16;
     let direction = if lparam.0 >> 31 == 0 {
         KeyDirection::Down
     } else {
         KeyDirection::Up
     };
-    let mut device = input_log_get_device(vkey_code, direction)?;
-    if device.is_none() {
-        process_waiting_input_messages(hwnd)?;
-        device = input_log_get_device(vkey_code, direction)?;
+    let details = KeyDetails {
+        scan_code: ((lparam.0 >> 16) & 0xff) as u16,
+        extended: (lparam.0 >> 24) & 1 == 1,
+        is_repeat: direction == KeyDirection::Down && (lparam.0 >> 30) & 1 == 1,
+        injected: false,
+        self_injected: wparam.0 & SELF_INJECTED_WPARAM_FLAG != 0,
+        timestamp,
+    };
+    let result = handle_key_input(hwnd, vkey_code, direction, details)?;
+    if result == SkipInput::Skip {
+        return Result::Ok(SKIP_INPUT);
+    } else {
+        return Result::Ok(DONT_SKIP_INPUT);
     }
+}
+
+// key input from either input backend
+pub fn handle_key_input(
+    hwnd: HWND,
+    vkey_code: u16,
+    direction: KeyDirection,
+    details: KeyDetails,
+) -> Result<SkipInput, RekeyError> {
+    let input = PendingInput {
+        kind: InputKind::Keyboard,
+        code: vkey_code,
+        direction,
+        wheel: 0,
+        device: Option::None,
+        details,
+    };
+    return handle_input(hwnd, input);
+}
 
-    let result = scripts_handle_input(vkey_code, direction, device)?;
+// the mouse hook sends the mouse message as wparam and the x button or wheel delta as lparam
+fn handle_should_skip_mouse_input(
+    hwnd: HWND,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> Result<LRESULT, RekeyError> {
+    let timestamp = SystemTime::now();
+    let result = handle_mouse_input(
+        hwnd,
+        (wparam.0 & 0xffff) as u32,
+        lparam.0 as i16,
+        false,
+        wparam.0 & SELF_INJECTED_WPARAM_FLAG != 0,
+        timestamp,
+    )?;
     if result == SkipInput::Skip {
         return Result::Ok(SKIP_INPUT);
     } else {
@@ -260,6 +473,242 @@ fn handle_should_skip_input(
     }
 }
 
+// mouse input from either input backend, mouse_data is the x button or the wheel delta
+pub fn handle_mouse_input(
+    hwnd: HWND,
+    message: u32,
+    mouse_data: i16,
+    injected: bool,
+    self_injected: bool,
+    timestamp: SystemTime,
+) -> Result<SkipInput, RekeyError> {
+    let (code, direction, wheel) = match message {
+        WM_LBUTTONDOWN => (VK_LBUTTON.0, KeyDirection::Down, 0),
+        WM_LBUTTONUP => (VK_LBUTTON.0, KeyDirection::Up, 0),
+        WM_RBUTTONDOWN => (VK_RBUTTON.0, KeyDirection::Down, 0),
+        WM_RBUTTONUP => (VK_RBUTTON.0, KeyDirection::Up, 0),
+        WM_MBUTTONDOWN => (VK_MBUTTON.0, KeyDirection::Down, 0),
+        WM_MBUTTONUP => (VK_MBUTTON.0, KeyDirection::Up, 0),
+        WM_XBUTTONDOWN => (x_button_code(mouse_data as u16), KeyDirection::Down, 0),
+        WM_XBUTTONUP => (x_button_code(mouse_data as u16), KeyDirection::Up, 0),
+        WM_MOUSEWHEEL => (MOUSE_WHEEL_CODE, KeyDirection::Down, mouse_data as i32),
+        WM_MOUSEHWHEEL => (MOUSE_HWHEEL_CODE, KeyDirection::Down, mouse_data as i32),
+        _ => return Result::Ok(SkipInput::DontSkip),
+    };
+
+    let input = PendingInput {
+        kind: InputKind::Mouse,
+        code,
+        direction,
+        wheel,
+        device: Option::None,
+        details: KeyDetails {
+            scan_code: 0,
+            extended: false,
+            is_repeat: false,
+            injected,
+            self_injected,
+            timestamp,
+        },
+    };
+    return handle_input(hwnd, input);
+}
+
+// finds the device of the input then runs the scripts. Input from an unknown device which a
+// handler wants to buffer is skipped for now and replayed once its device is known, or it waited
+// long enough, and the scripts didn't skip it.
+fn handle_input(hwnd: HWND, mut input: PendingInput) -> Result<SkipInput, RekeyError> {
+    if input.details.self_injected && input_replays_take(&input)? {
+        return Result::Ok(SkipInput::DontSkip);
+    }
+
+    let mut input_buffer = INPUT_BUFFER.lock().map_err(|err| {
+        RekeyError::GenericError(format!("could not get input buffer lock: {}", err))
+    })?;
+    if !input_buffer.is_empty() {
+        // later input can't overtake buffered input
+        input_buffer.push(input, false, Instant::now());
+        return Result::Ok(SkipInput::Skip);
+    }
+    drop(input_buffer);
+
+    if !find_logged_input(&mut input)? {
+        process_waiting_input_messages(hwnd)?;
+        find_logged_input(&mut input)?;
+    }
+    // the low level hook runs before raw input is generated and input it skips never generates
+    // raw input, so buffering would hold every key until the timeout
+    if input.device.is_none()
+        && !ll_hook_is_installed()
+        && scripts_should_buffer_unknown_device(input.kind, input.code)?
+    {
+        let mut input_buffer = INPUT_BUFFER.lock().map_err(|err| {
+            RekeyError::GenericError(format!("could not get input buffer lock: {}", err))
+        })?;
+        input_buffer.push(input, true, Instant::now());
+        update_input_buffer_timer(hwnd, &input_buffer)?;
+        return Result::Ok(SkipInput::Skip);
+    }
+    return run_input_scripts(input);
+}
+
+// looks up the raw input of the input to find its device, whether the input was found. The dll
+// hook can't tell whether input was injected so that comes from raw input, the low level hook
+// has its own flag.
+fn find_logged_input(input: &mut PendingInput) -> Result<bool, RekeyError> {
+    if let Option::Some(logged) = input_log_take(input.code, input.direction)? {
+        if !ll_hook_is_installed() {
+            input.details.injected = logged.injected;
+        }
+        input.device = Option::Some(logged.device);
+        return Result::Ok(true);
+    }
+    return Result::Ok(false);
+}
+
+fn run_input_scripts(input: PendingInput) -> Result<SkipInput, RekeyError> {
+    let mut details = input.details;
+    details.injected = details.injected || details.self_injected;
+    if input.kind == InputKind::Mouse {
+        return scripts_handle_mouse_input(
+            input.code,
+            input.direction,
+            input.wheel,
+            input.device,
+            details,
+        );
+    }
+    return scripts_handle_input(input.code, input.direction, input.device, details);
+}
+
+// runs the scripts for buffered input whose device is known by now or which waited long enough,
+// input the scripts don't skip is sent again
+fn flush_input_buffer(hwnd: HWND) -> Result<(), RekeyError> {
+    let mut input_buffer = INPUT_BUFFER.lock().map_err(|err| {
+        RekeyError::GenericError(format!("could not get input buffer lock: {}", err))
+    })?;
+    let ready = input_buffer.pop_ready(Instant::now(), |input| {
+        if input.device.is_some() {
+            return Result::Ok(true);
+        }
+        return find_logged_input(input);
+    })?;
+    update_input_buffer_timer(hwnd, &input_buffer)?;
+    drop(input_buffer);
+
+    for input in ready {
+        let replay = build_input_replay(&input);
+        let key = (input.kind, input.code, input.direction);
+        if input.device.is_none() {
+            debug!(
+                "no device for buffered input {} {}",
+                input.code, input.direction
+            );
+        }
+        if run_input_scripts(input)? == SkipInput::DontSkip {
+            let mut input_replays = INPUT_REPLAYS.lock().map_err(|err| {
+                RekeyError::GenericError(format!("could not get input replays lock: {}", err))
+            })?;
+            input_replays.add(key, Instant::now());
+            drop(input_replays);
+            send_inputs(&replay)?;
+        }
+    }
+    return Result::Ok(());
+}
+
+fn build_input_replay(input: &PendingInput) -> Vec<INPUT> {
+    if input.kind != InputKind::Mouse {
+        return build_replay_key_inputs(
+            input.code,
+            input.details.scan_code,
+            input.details.extended,
+            input.direction,
+        );
+    }
+    if input.code == MOUSE_WHEEL_CODE || input.code == MOUSE_HWHEEL_CODE {
+        return build_replay_mouse_wheel_inputs(input.wheel, input.code == MOUSE_HWHEEL_CODE);
+    }
+    const BUTTONS: [(u16, MouseButton); 5] = [
+        (VK_LBUTTON.0, MouseButton::Left),
+        (VK_RBUTTON.0, MouseButton::Right),
+        (VK_MBUTTON.0, MouseButton::Middle),
+        (VK_XBUTTON1.0, MouseButton::X1),
+        (VK_XBUTTON2.0, MouseButton::X2),
+    ];
+    return BUTTONS
+        .iter()
+        .find(|(code, _)| *code == input.code)
+        .map_or(vec![], |(_, button)| {
+            build_replay_mouse_button_inputs(*button, input.direction)
+        });
+}
+
+fn input_replays_take(input: &PendingInput) -> Result<bool, RekeyError> {
+    let mut input_replays = INPUT_REPLAYS.lock().map_err(|err| {
+        RekeyError::GenericError(format!("could not get input replays lock: {}", err))
+    })?;
+    return Result::Ok(
+        input_replays.take(&(input.kind, input.code, input.direction), Instant::now()),
+    );
+}
+
+// wakes the window proc when the first buffered input stops waiting for its device
+fn update_input_buffer_timer(
+    hwnd: HWND,
+    input_buffer: &InputBuffer<PendingInput>,
+) -> Result<(), RekeyError> {
+    unsafe {
+        match input_buffer.next_deadline() {
+            Option::Some(deadline) => {
+                let wait = deadline.saturating_duration_since(Instant::now());
+                let millis = wait.as_millis().max(1) as u32;
+                if SetTimer(hwnd, ID_TIMER_INPUT_BUFFER, millis, Option::None) == 0 {
+                    return Result::Err(RekeyError::Win32GetLastError(
+                        "SetTimer".to_string(),
+                        GetLastError(),
+                    ));
+                }
+            }
+            Option::None => {
+                let _ = KillTimer(hwnd, ID_TIMER_INPUT_BUFFER);
+            }
+        }
+    }
+    return Result::Ok(());
+}
+
+fn handle_timer(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> Result<LRESULT, RekeyError> {
+    if wparam.0 == ID_TIMER_INPUT_BUFFER {
+        flush_input_buffer(hwnd)?;
+        return Result::Ok(LRESULT(0));
+    }
+    unsafe {
+        return Result::Ok(DefWindowProcW(hwnd, msg, wparam, lparam));
+    }
+}
+
+fn x_button_code(x_button: u16) -> u16 {
+    if x_button == XBUTTON1 {
+        return VK_XBUTTON1.0;
+    }
+    return VK_XBUTTON2.0;
+}
+
+fn handle_input_registrations_changed(hwnd: HWND) -> Result<LRESULT, RekeyError> {
+    let input_registrations = scripts_get_input_registrations()?;
+    raw_input_set_mouse_enabled(hwnd, input_registrations.mouse)?;
+    dll_set_mouse_hook_enabled(input_registrations.mouse)?;
+    ll_hook_set_mouse_enabled(input_registrations.mouse)?;
+    raw_input_set_hid_usages(hwnd, &input_registrations.hid_usages)?;
+    return Result::Ok(LRESULT(0));
+}
+
 fn process_waiting_input_messages(hwnd: HWND) -> Result<(), RekeyError> {
     let mut msg: MSG = MSG::default();
     while unsafe { PeekMessageW(&mut msg, hwnd, WM_INPUT, WM_INPUT, PM_REMOVE).as_bool() } {
@@ -286,14 +735,78 @@ fn handle_wm_input(
             _ => KeyDirection::Down,
         };
         let device = find_device(raw_input_data.header.hDevice)?;
-        input_log_add_wm_input(device, vkey_code, direction)?;
+        let injected = raw_input_data.header.hDevice.0 == 0;
+        input_log_add_wm_input(device, vkey_code, direction, injected)?;
+        flush_input_buffer(hwnd)?;
+    } else if raw_input_data.header.dwType == RIM_TYPEMOUSE.0 {
+        let mouse = unsafe { raw_input_data.data.mouse };
+        let events = raw_mouse_events(&mouse);
+        if !events.is_empty() {
+            let device = find_device(raw_input_data.header.hDevice)?;
+            let injected = raw_input_data.header.hDevice.0 == 0;
+            for (code, direction) in events {
+                input_log_add_wm_input(device.clone(), code, direction, injected)?;
+            }
+            flush_input_buffer(hwnd)?;
+        }
+    } else if raw_input_data.header.dwType == RIM_TYPEHID.0 {
+        let timestamp = SystemTime::now();
+        let reports = get_raw_input_hid_reports(lparam)?;
+        let device = find_device(raw_input_data.header.hDevice)?;
+        scripts_handle_hid_input(device, reports, timestamp)?;
     }
     unsafe {
         return Result::Ok(DefWindowProcW(hwnd, msg, wparam, lparam));
     }
 }
 
-fn get_hinstance() -> Result<HINSTANCE, RekeyError> {
+// WM_INPUT_DEVICE_CHANGE is only sent for devices of the registered raw input usages, see
+// raw_input.rs, and WM_DEVICECHANGE for any device node, either way the device list is
+// compared with the last one so each change is reported once
+fn handle_device_change() -> Result<LRESULT, RekeyError> {
+    let changes = devices_update_attached()?;
+    for device in changes.removed {
+        debug!("device removed: {}", device);
+        scripts_handle_device_change(device, false)?;
+    }
+    for device in changes.arrived {
+        debug!("device arrived: {}", device);
+        scripts_handle_device_change(device, true)?;
+    }
+    return Result::Ok(LRESULT(0));
+}
+
+// a single raw mouse input can contain several button transitions and a wheel, moves are
+// ignored since the hook doesn't send them
+fn raw_mouse_events(mouse: &RAWMOUSE) -> Vec<(u16, KeyDirection)> {
+    const BUTTON_FLAGS: [(u32, u16, KeyDirection); 12] = [
+        (RI_MOUSE_LEFT_BUTTON_DOWN, VK_LBUTTON.0, KeyDirection::Down),
+        (RI_MOUSE_LEFT_BUTTON_UP, VK_LBUTTON.0, KeyDirection::Up),
+        (RI_MOUSE_RIGHT_BUTTON_DOWN, VK_RBUTTON.0, KeyDirection::Down),
+        (RI_MOUSE_RIGHT_BUTTON_UP, VK_RBUTTON.0, KeyDirection::Up),
+        (
+            RI_MOUSE_MIDDLE_BUTTON_DOWN,
+            VK_MBUTTON.0,
+            KeyDirection::Down,
+        ),
+        (RI_MOUSE_MIDDLE_BUTTON_UP, VK_MBUTTON.0, KeyDirection::Up),
+        (RI_MOUSE_BUTTON_4_DOWN, VK_XBUTTON1.0, KeyDirection::Down),
+        (RI_MOUSE_BUTTON_4_UP, VK_XBUTTON1.0, KeyDirection::Up),
+        (RI_MOUSE_BUTTON_5_DOWN, VK_XBUTTON2.0, KeyDirection::Down),
+        (RI_MOUSE_BUTTON_5_UP, VK_XBUTTON2.0, KeyDirection::Up),
+        (RI_MOUSE_WHEEL, MOUSE_WHEEL_CODE, KeyDirection::Down),
+        (RI_MOUSE_HWHEEL, MOUSE_HWHEEL_CODE, KeyDirection::Down),
+    ];
+
+    let button_flags = unsafe { mouse.Anonymous.Anonymous.usButtonFlags } as u32;
+    return BUTTON_FLAGS
+        .iter()
+        .filter(|(flag, _, _)| button_flags & flag != 0)
+        .map(|(_, code, direction)| (*code, *direction))
+        .collect();
+}
+
+pub fn get_hinstance() -> Result<HINSTANCE, RekeyError> {
     unsafe {
         let instance = GetModuleHandleW(PCWSTR::null())
             .map_err(|err| RekeyError::Win32Error("failed GetModuleHandleW".to_string(), err))?;
@@ -326,6 +839,38 @@ pub fn add_systray_icon(hwnd: HWND) -> Result<(), RekeyError> {
                 "failed Shell_NotifyIcon".to_string(),
             ));
         }
+        SYSTRAY_ICON_ADDED.store(true, Ordering::Relaxed);
+
+        return Result::Ok(());
+    }
+}
+
+pub fn show_notification(hwnd: HWND, title: &str, message: &str) -> Result<(), RekeyError> {
+    fn copy_truncated(dest: &mut [u16], s: &str) {
+        let wide: Vec<u16> = s.encode_utf16().take(dest.len() - 1).collect();
+        dest[..wide.len()].copy_from_slice(&wide);
+        dest[wide.len()] = 0;
+    }
+
+    if !SYSTRAY_ICON_ADDED.load(Ordering::Relaxed) {
+        info!("{}: {}", title, message);
+        return Result::Ok(());
+    }
+
+    unsafe {
+        let mut notify_icon_data = NOTIFYICONDATAW::default();
+        notify_icon_data.cbSize = size_of::<NOTIFYICONDATAW>() as u32;
+        notify_icon_data.hWnd = hwnd;
+        notify_icon_data.uID = SYS_TRAY_ID;
+        notify_icon_data.uFlags = NIF_INFO;
+        notify_icon_data.dwInfoFlags = NIIF_ERROR;
+        copy_truncated(&mut notify_icon_data.szInfoTitle, title);
+        copy_truncated(&mut notify_icon_data.szInfo, message);
+        if !Shell_NotifyIconW(NIM_MODIFY, &notify_icon_data).as_bool() {
+            return Result::Err(RekeyError::GenericError(
+                "failed notification Shell_NotifyIcon".to_string(),
+            ));
+        }
 
         return Result::Ok(());
     }
@@ -343,6 +888,7 @@ pub fn delete_systray_icon(hwnd: HWND) -> Result<(), RekeyError> {
                 "failed delete Shell_NotifyIcon".to_string(),
             ));
         }
+        SYSTRAY_ICON_ADDED.store(false, Ordering::Relaxed);
 
         return Result::Ok(());
     }
@@ -392,7 +938,19 @@ pub fn create_window() -> Result<HWND, RekeyError> {
 }
 
 pub fn load_scripts_notify_on_error(hwnd: HWND) -> () {
-    if let Result::Err(err) = scripts_load() {
+    // a single failing script doesn't stop the others from loading, report them all at once
+    let result = scripts_load().and_then(|_| {
+        let failed: Vec<String> = scripts_get_statuses()?
+            .iter()
+            .filter(|status| status.state == ScriptState::Failed)
+            .map(|status| format!("{}", status))
+            .collect();
+        if failed.is_empty() {
+            return Result::Ok(());
+        }
+        return Result::Err(RekeyError::GenericError(failed.join("\n\n")));
+    });
+    if let Result::Err(err) = result {
         unsafe {
             let message = HSTRING::from(format!("{}", err));
             let results = MessageBoxW(